@@ -0,0 +1,26 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate image;
+
+use image::ImageDecoder;
+use image::pnm::PnmDecoderOptions;
+
+// Seeds for all subtypes are in `fuzz/seeds/header`, run with
+// `cargo fuzz run header fuzz/corpus/header fuzz/seeds/header`.
+fuzz_target!(|data: &[u8]| {
+    // Bound the allocation for the raster, large dimensions are valid but not interesting.
+    let options = PnmDecoderOptions::new().max_bytes(1 << 24);
+    let mut decoder = match options.read(data) {
+        Ok(decoder) => decoder,
+        Err(_) => return,
+    };
+    loop {
+        if decoder.read_image().is_err() {
+            return;
+        }
+        decoder = match decoder.next_frame() {
+            Ok(Some(next)) => next,
+            _ => return,
+        };
+    }
+});
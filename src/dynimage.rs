@@ -620,7 +620,7 @@ fn open_impl(path: &Path) -> ImageResult<DynamicImage> {
         "bmp" => image::ImageFormat::BMP,
         "ico" => image::ImageFormat::ICO,
         "hdr" => image::ImageFormat::HDR,
-        "pbm" | "pam" | "ppm" | "pgm" => image::ImageFormat::PNM,
+        "pbm" | "pam" | "ppm" | "pgm" | "pfm" | "pnm" => image::ImageFormat::PNM,
         format => {
             return Err(image::ImageError::UnsupportedError(format!(
                 "Image format image/{:?} is not supported.",
@@ -636,9 +636,13 @@ fn open_impl(path: &Path) -> ImageResult<DynamicImage> {
 ///
 /// The image format is derived from the file extension. The buffer is assumed to have
 /// the correct format according to the specified color type.
+///
+/// The extensions `pbm`, `pgm`, `ppm`, `pam` and `pfm` select the binary pnm subtypes P4, P5,
+/// P6, P7 and float maps. For bitmaps, 8-bit gray samples below 128 become black and all others
+/// white. Float maps are written from 8-bit gray or RGB samples scaled to `0.0..=1.0`. The
+/// extension `pnm` selects the subtype from the color type, see `PNMEncoder::encode_auto`.
 
-/// This will lead to corrupted files if the buffer contains malformed data. Currently only
-/// jpeg and png files are supported.
+/// This will lead to corrupted files if the buffer contains malformed data.
 pub fn save_buffer<P>(
     path: P,
     buf: &[u8],
@@ -673,9 +677,7 @@ fn save_buffer_impl(
         #[cfg(feature = "png_codec")]
         "png" => png::PNGEncoder::new(fout).encode(buf, width, height, color),
         #[cfg(feature = "pnm")]
-        "pbm" => pnm::PNMEncoder::new(fout)
-            .with_subtype(pnm::PNMSubtype::Bitmap(pnm::SampleEncoding::Binary))
-            .encode(buf, width, height, color),
+        "pbm" => save_bitmap(fout, buf, width, height, color),
         #[cfg(feature = "pnm")]
         "pgm" => pnm::PNMEncoder::new(fout)
             .with_subtype(pnm::PNMSubtype::Graymap(pnm::SampleEncoding::Binary))
@@ -685,7 +687,13 @@ fn save_buffer_impl(
             .with_subtype(pnm::PNMSubtype::Pixmap(pnm::SampleEncoding::Binary))
             .encode(buf, width, height, color),
         #[cfg(feature = "pnm")]
-        "pam" => pnm::PNMEncoder::new(fout).encode(buf, width, height, color),
+        "pam" => pnm::PNMEncoder::new(fout)
+            .with_subtype(pnm::PNMSubtype::ArbitraryMap)
+            .encode(buf, width, height, color),
+        #[cfg(feature = "pnm")]
+        "pfm" => save_floatmap(fout, buf, width, height, color),
+        #[cfg(feature = "pnm")]
+        "pnm" => pnm::PNMEncoder::new(fout).encode_auto(buf, width, height, color),
         #[cfg(feature = "bmp")]
         "bmp" => bmp::BMPEncoder::new(fout).encode(buf, width, height, color),
         format => Err(io::Error::new(
@@ -695,6 +703,53 @@ fn save_buffer_impl(
     }
 }
 
+/// Write a pbm, thresholding 8-bit luma to 0 for black and 1 for white as the encoder expects.
+#[cfg(feature = "pnm")]
+fn save_bitmap<W: Write>(
+    w: W,
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    color: color::ColorType,
+) -> io::Result<()> {
+    let bits: Vec<u8>;
+    let buf = match color {
+        color::ColorType::Gray(8) => {
+            bits = buf.iter().map(|&luma| if luma < 128 { 0 } else { 1 }).collect();
+            &bits[..]
+        }
+        _ => buf,
+    };
+    pnm::PNMEncoder::new(w)
+        .with_subtype(pnm::PNMSubtype::Bitmap(pnm::SampleEncoding::Binary))
+        .encode(buf, width, height, color)
+}
+
+/// Write a float map of 8-bit gray or RGB samples scaled to `0.0..=1.0`.
+#[cfg(feature = "pnm")]
+fn save_floatmap<W: Write>(
+    w: W,
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    color: color::ColorType,
+) -> io::Result<()> {
+    let (floatmap, color) = match color {
+        color::ColorType::Gray(8) => (pnm::FloatmapColor::Gray, color::ColorType::Gray(32)),
+        color::ColorType::RGB(8) => (pnm::FloatmapColor::RGB, color::ColorType::RGB(32)),
+        color => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Float maps can not be written from {:?}", color)[..],
+            ))
+        }
+    };
+    let samples: Vec<f32> = buf.iter().map(|&v| f32::from(v) / 255.0).collect();
+    pnm::PNMEncoder::new(w)
+        .with_subtype(pnm::PNMSubtype::Floatmap(floatmap))
+        .encode(&samples[..], width, height, color)
+}
+
 /// Create a new image from a Reader
 pub fn load<R: BufRead + Seek>(r: R, format: ImageFormat) -> ImageResult<DynamicImage> {
     #[allow(deprecated, unreachable_patterns)]
@@ -719,7 +774,9 @@ pub fn load<R: BufRead + Seek>(r: R, format: ImageFormat) -> ImageResult<Dynamic
         #[cfg(feature = "hdr")]
         image::ImageFormat::HDR => decoder_to_image(try!(hdr::HDRAdapter::new(BufReader::new(r)))),
         #[cfg(feature = "pnm")]
-        image::ImageFormat::PNM => decoder_to_image(try!(pnm::PNMDecoder::new(BufReader::new(r)))),
+        image::ImageFormat::PNM => {
+            try!(pnm::PNMDecoder::from_buf_read(BufReader::new(r))).into_dynamic_image()
+        }
         _ => Err(image::ImageError::UnsupportedError(format!(
             "A decoder for {:?} is not available.",
             format
@@ -727,7 +784,7 @@ pub fn load<R: BufRead + Seek>(r: R, format: ImageFormat) -> ImageResult<Dynamic
     }
 }
 
-static MAGIC_BYTES: [(&'static [u8], ImageFormat); 17] = [
+static MAGIC_BYTES: [(&'static [u8], ImageFormat); 19] = [
     (b"\x89PNG\r\n\x1a\n", ImageFormat::PNG),
     (&[0xff, 0xd8, 0xff], ImageFormat::JPEG),
     (b"GIF89a", ImageFormat::GIF),
@@ -745,6 +802,8 @@ static MAGIC_BYTES: [(&'static [u8], ImageFormat); 17] = [
     (b"P5", ImageFormat::PNM),
     (b"P6", ImageFormat::PNM),
     (b"P7", ImageFormat::PNM),
+    (b"Pf", ImageFormat::PNM),
+    (b"PF", ImageFormat::PNM),
 ];
 
 /// Create a new image from a byte slice
@@ -839,6 +898,123 @@ mod test {
         assert!(super::load_from_memory(b"").is_err());
     }
 
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn test_load_pnm_from_memory() {
+        for &(data, expected) in &[
+            (&b"P1\n3 1\n1 0 1\n"[..], &[0u8, 255, 0][..]),
+            (b"P2\n3 1\n255\n0 128 255\n", &[0, 128, 255]),
+            (b"P3\n1 1\n255\n1 2 3\n", &[1, 2, 3]),
+            (b"P4\n3 1\n\xa0", &[0, 255, 0]),
+            (b"P5\n3 1\n255\n\x00\x80\xff", &[0, 128, 255]),
+            (b"P6\n1 1\n255\n\x01\x02\x03", &[1, 2, 3]),
+            (
+                b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\n\
+                  TUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02\x03\x04",
+                &[1, 2, 3, 4],
+            ),
+        ] {
+            assert_eq!(super::guess_format(data).unwrap(), super::ImageFormat::PNM);
+            let image = super::load_from_memory(data).unwrap();
+            assert_eq!(image.raw_pixels(), expected);
+        }
+
+        for &data in &[&b"Pf\n1 1\n-1.0\n\0\0\0\0"[..], b"PF\n1 1\n1.0\n\0\0\0\0\0\0"] {
+            assert_eq!(super::guess_format(data).unwrap(), super::ImageFormat::PNM);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm")]
+    fn test_save_pnm_by_extension() {
+        use image::ImageDecoder;
+        use std::fs::{self, File};
+        use std::io::Read;
+        use {DecodingResult, DynamicImage, ImageBuffer};
+
+        let dir = ::std::env::temp_dir();
+        let path = |ext: &str| dir.join(format!("image-save-{}.{}", ::std::process::id(), ext));
+        let magic = |path: &::std::path::Path| {
+            let mut magic = [0; 2];
+            File::open(path).unwrap().read_exact(&mut magic).unwrap();
+            magic
+        };
+
+        let luma = vec![0, 100, 200, 255];
+        let rgb: Vec<u8> = (0..12).map(|v| v * 20).collect();
+        let rgba: Vec<u8> = (0..16).map(|v| v * 15).collect();
+        let bitmap = DynamicImage::ImageLuma8(ImageBuffer::from_raw(2, 2, luma.clone()).unwrap());
+        let gray = bitmap.clone();
+        let color = DynamicImage::ImageRgb8(ImageBuffer::from_raw(2, 2, rgb.clone()).unwrap());
+        let alpha = DynamicImage::ImageRgba8(ImageBuffer::from_raw(2, 2, rgba.clone()).unwrap());
+
+        for &(ext, ref image, expected_magic, ref expected) in &[
+            ("pbm", &bitmap, b"P4", vec![0, 0, 255, 255]),
+            ("pgm", &gray, b"P5", luma.clone()),
+            ("ppm", &color, b"P6", rgb.clone()),
+            ("pam", &alpha, b"P7", rgba.clone()),
+        ] {
+            let path = path(ext);
+            image.save(&path).unwrap();
+            assert_eq!(&magic(&path), expected_magic);
+            let decoded = super::open(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(decoded.color(), image.color());
+            assert_eq!(decoded.raw_pixels(), *expected);
+        }
+
+        let path = path("pfm");
+        color.save(&path).unwrap();
+        assert_eq!(&magic(&path), b"PF");
+        let mut decoder = ::pnm::PNMDecoder::new(File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(samples) => {
+                let samples: Vec<u8> = samples.iter().map(|&v| (v * 255.0).round() as u8).collect();
+                assert_eq!(samples, rgb);
+            }
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm")]
+    fn test_save_pnm_by_color() {
+        use std::fs::{self, File};
+        use std::io::Read;
+        use {ColorType, DynamicImage, ImageBuffer};
+
+        let path = |name: &str| {
+            ::std::env::temp_dir().join(format!("image-save-{}-{}.pnm", name, ::std::process::id()))
+        };
+        // Returns the magic constant of the written file and the decoded pixels.
+        let read_back = |path: &::std::path::Path| {
+            let mut magic = [0; 2];
+            File::open(path).unwrap().read_exact(&mut magic).unwrap();
+            let decoded = super::open(path).unwrap();
+            fs::remove_file(path).unwrap();
+            (magic, decoded.raw_pixels())
+        };
+
+        let gray = DynamicImage::ImageLuma8(ImageBuffer::from_raw(2, 1, vec![7, 9]).unwrap());
+        let rgb = DynamicImage::ImageRgb8(ImageBuffer::from_raw(1, 1, vec![1, 2, 3]).unwrap());
+        let rgba = DynamicImage::ImageRgba8(ImageBuffer::from_raw(1, 1, vec![1, 2, 3, 4]).unwrap());
+        for &(name, image, magic) in &[
+            ("gray", &gray, b"P5"),
+            ("rgb", &rgb, b"P6"),
+            ("rgba", &rgba, b"P7"),
+        ] {
+            let path = path(name);
+            image.save(&path).unwrap();
+            assert_eq!(read_back(&path), (*magic, image.raw_pixels()));
+        }
+
+        let path = path("bits");
+        super::save_buffer(&path, &[0, 1, 1], 3, 1, ColorType::Gray(1)).unwrap();
+        assert_eq!(read_back(&path), (*b"P4", vec![0, 255, 255]));
+    }
+
     quickcheck! {
         fn resize_bounds_correctly_width(old_w: u32, new_w: u32) -> bool {
             if old_w == 0 || new_w == 0 { return true; }
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
 use std::io;
@@ -38,6 +39,15 @@ pub enum ImageError {
 
     /// The end of the image has been reached
     ImageEnd,
+
+    /// The image exceeds the limits configured for the decoder
+    LimitError,
+
+    /// Decoding was cancelled before it completed
+    Cancelled,
+
+    /// A structured error of the pnm decoder
+    PnmError(PnmError),
 }
 
 impl fmt::Display for ImageError {
@@ -68,6 +78,13 @@ impl fmt::Display for ImageError {
             ),
             ImageError::IoError(ref e) => e.fmt(fmt),
             ImageError::ImageEnd => write!(fmt, "The end of the image has been reached"),
+            ImageError::LimitError => write!(
+                fmt,
+                "The image exceeds the limits \
+                 configured for the decoder"
+            ),
+            ImageError::Cancelled => write!(fmt, "Decoding was cancelled"),
+            ImageError::PnmError(ref e) => e.fmt(fmt),
         }
     }
 }
@@ -82,12 +99,24 @@ impl Error for ImageError {
             ImageError::NotEnoughData => "Not enough data",
             ImageError::IoError(..) => "IO error",
             ImageError::ImageEnd => "Image end",
+            ImageError::LimitError => "Limit error",
+            ImageError::Cancelled => "Cancelled",
+            ImageError::PnmError(..) => "Pnm error",
         }
     }
 
     fn cause(&self) -> Option<&Error> {
         match *self {
             ImageError::IoError(ref e) => Some(e),
+            ImageError::PnmError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            ImageError::IoError(ref e) => Some(e),
+            ImageError::PnmError(ref e) => Some(e),
             _ => None,
         }
     }
@@ -99,6 +128,86 @@ impl From<io::Error> for ImageError {
     }
 }
 
+impl From<PnmError> for ImageError {
+    fn from(err: PnmError) -> ImageError {
+        ImageError::PnmError(err)
+    }
+}
+
+/// Structured errors of the pnm decoder, carried by `ImageError::PnmError`.
+///
+/// Defined here rather than in the `pnm` module, so that `ImageError` does not depend on the
+/// enabled features.
+///
+/// Other malformed headers and rasters are still reported as `ImageError::FormatError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PnmError {
+    /// The input does not start with the magic constant of a pnm subtype
+    BadMagic([u8; 2]),
+
+    /// A sample is greater than the maximal value allowed for the image
+    SampleOutOfRange {
+        /// The value of the sample
+        value: u32,
+        /// The maximal allowed value, the maxval or the bound of the sample type
+        maxval: u32,
+    },
+
+    /// The raster ended before all bytes of the part being decoded could be read
+    Truncated {
+        /// The number of bytes required
+        expected: usize,
+        /// The number of bytes available
+        got: usize,
+    },
+
+    /// An ascii raster ended before all samples described by the header could be read
+    MissingSamples {
+        /// The number of samples required
+        expected: usize,
+        /// The number of samples read
+        got: usize,
+    },
+}
+
+impl fmt::Display for PnmError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PnmError::BadMagic(magic) => write!(
+                fmt,
+                "Expected magic constant for pnm, P1 through P7, Pf or PF, got {:?}",
+                String::from_utf8_lossy(&magic)
+            ),
+            PnmError::SampleOutOfRange { value, maxval } => write!(
+                fmt,
+                "Sample value {} is greater than the maximum of {}",
+                value, maxval
+            ),
+            PnmError::Truncated { expected, got } => write!(
+                fmt,
+                "The image data is truncated, expected {} bytes but got {}",
+                expected, got
+            ),
+            PnmError::MissingSamples { expected, got } => write!(
+                fmt,
+                "The ascii raster ended early, expected {} samples but got {}",
+                expected, got
+            ),
+        }
+    }
+}
+
+impl Error for PnmError {
+    fn description(&self) -> &str {
+        match *self {
+            PnmError::BadMagic(..) => "Bad magic constant",
+            PnmError::SampleOutOfRange { .. } => "Sample out of range",
+            PnmError::Truncated { .. } => "Truncated data",
+            PnmError::MissingSamples { .. } => "Missing samples",
+        }
+    }
+}
+
 /// Result of an image decoding/encoding process
 pub type ImageResult<T> = Result<T, ImageError>;
 
@@ -109,6 +218,80 @@ pub enum DecodingResult {
     U8(Vec<u8>),
     /// A vector of unsigned words
     U16(Vec<u16>),
+    /// A vector of single precision floats
+    F32(Vec<f32>),
+}
+
+impl DecodingResult {
+    /// The samples if they are unsigned bytes.
+    pub fn as_u8(&self) -> Option<&[u8]> {
+        match *self {
+            DecodingResult::U8(ref samples) => Some(samples),
+            _ => None,
+        }
+    }
+
+    /// The samples if they are unsigned words.
+    pub fn as_u16(&self) -> Option<&[u16]> {
+        match *self {
+            DecodingResult::U16(ref samples) => Some(samples),
+            _ => None,
+        }
+    }
+
+    /// Extract the samples if they are unsigned bytes.
+    pub fn into_u8(self) -> Option<Vec<u8>> {
+        match self {
+            DecodingResult::U8(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
+    /// Extract the samples if they are unsigned words.
+    pub fn into_u16(self) -> Option<Vec<u16>> {
+        match self {
+            DecodingResult::U16(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
+    /// The number of samples, regardless of their type.
+    pub fn len(&self) -> usize {
+        match *self {
+            DecodingResult::U8(ref samples) => samples.len(),
+            DecodingResult::U16(ref samples) => samples.len(),
+            DecodingResult::F32(ref samples) => samples.len(),
+        }
+    }
+
+    /// Whether there are no samples.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Extract unsigned bytes, returning the result unchanged if it holds other samples.
+impl TryFrom<DecodingResult> for Vec<u8> {
+    type Error = DecodingResult;
+
+    fn try_from(result: DecodingResult) -> Result<Self, DecodingResult> {
+        match result {
+            DecodingResult::U8(samples) => Ok(samples),
+            other => Err(other),
+        }
+    }
+}
+
+/// Extract unsigned words, returning the result unchanged if it holds other samples.
+impl TryFrom<DecodingResult> for Vec<u16> {
+    type Error = DecodingResult;
+
+    fn try_from(result: DecodingResult) -> Result<Self, DecodingResult> {
+        match result {
+            DecodingResult::U16(samples) => Ok(samples),
+            other => Err(other),
+        }
+    }
 }
 
 // A buffer for image decoding
@@ -660,10 +843,50 @@ where
 #[cfg(test)]
 mod tests {
 
-    use super::{GenericImage, GenericImageView};
+    use std::convert::TryFrom;
+
+    use super::{DecodingResult, GenericImage, GenericImageView};
     use buffer::ImageBuffer;
     use color::Rgba;
 
+    #[test]
+    fn test_decoding_result_u8() {
+        let result = DecodingResult::U8(vec![1, 2, 3]);
+        assert_eq!(result.as_u8(), Some(&[1, 2, 3][..]));
+        assert_eq!(result.as_u16(), None);
+        assert_eq!(result.len(), 3);
+        assert!(!result.is_empty());
+        assert_eq!(DecodingResult::U8(vec![1]).into_u16(), None);
+        assert_eq!(result.into_u8(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_decoding_result_try_from() {
+        assert_eq!(Vec::<u8>::try_from(DecodingResult::U8(vec![1, 2])).unwrap(), vec![1, 2]);
+        assert_eq!(Vec::<u16>::try_from(DecodingResult::U16(vec![300])).unwrap(), vec![300]);
+
+        match Vec::<u8>::try_from(DecodingResult::U16(vec![300])) {
+            Err(DecodingResult::U16(samples)) => assert_eq!(samples, vec![300]),
+            other => panic!("Converted mismatched samples {:?}", other),
+        }
+        match Vec::<u16>::try_from(DecodingResult::U8(vec![1, 2])) {
+            Err(DecodingResult::U8(samples)) => assert_eq!(samples, vec![1, 2]),
+            other => panic!("Converted mismatched samples {:?}", other),
+        }
+        assert!(Vec::<u16>::try_from(DecodingResult::F32(vec![0.5])).is_err());
+    }
+
+    #[test]
+    fn test_decoding_result_u16() {
+        let result = DecodingResult::U16(vec![1, 65535]);
+        assert_eq!(result.as_u16(), Some(&[1, 65535][..]));
+        assert_eq!(result.as_u8(), None);
+        assert_eq!(result.len(), 2);
+        assert!(DecodingResult::U16(Vec::new()).is_empty());
+        assert_eq!(DecodingResult::U16(vec![1]).into_u8(), None);
+        assert_eq!(result.into_u16(), Some(vec![1, 65535]));
+    }
+
     #[test]
     /// Test that alpha blending works as expected
     fn test_image_alpha_blending() {
@@ -16,12 +16,22 @@ extern crate num_rational;
 extern crate num_traits;
 #[macro_use]
 extern crate num_derive;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 #[cfg(all(test, feature = "benchmarks"))]
 extern crate test;
 
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 use std::io::Write;
 
@@ -36,6 +46,7 @@ pub use image::{DecodingResult,
                 ImageError,
                 ImageResult,
                 MutPixels,
+                PnmError,
                 // Iterators
                 Pixels,
                 SubImage};
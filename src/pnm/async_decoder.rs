@@ -0,0 +1,304 @@
+//! Decoding of pnm images from asynchronous readers.
+use std::cmp;
+use std::future::Future;
+use std::io::{self, BufRead, Cursor, Read};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::AsyncBufRead;
+
+use super::{PNMDecoder, PNMHeader, PnmDecoderOptions};
+use color::ColorType;
+use image::{DecodingResult, ImageDecoder, ImageError, ImageResult};
+
+/// PNM decoder reading from a `tokio::io::AsyncBufRead`.
+///
+/// Creating the decoder awaits only the bytes of the header, so `dimensions` and `colortype` are
+/// available right away. `read_image` awaits the raster and decodes it once it is complete, which
+/// for ascii images means reading until the end of the input. Otherwise the decoder behaves like
+/// `PNMDecoder`.
+pub struct AsyncPNMDecoder<R> {
+    reader: R,
+    decoder: PNMDecoder<Cursor<Vec<u8>>, Cursor<Vec<u8>>>,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncPNMDecoder<R> {
+    /// Create a new decoder that decodes from the stream ```reader```
+    pub fn new(reader: R) -> impl Future<Output = ImageResult<AsyncPNMDecoder<R>>> {
+        AsyncPNMDecoder::with_options(reader, PnmDecoderOptions::new())
+    }
+
+    /// Create a new decoder with the given options.
+    pub fn with_options(
+        reader: R,
+        options: PnmDecoderOptions,
+    ) -> impl Future<Output = ImageResult<AsyncPNMDecoder<R>>> {
+        ReadHeader {
+            reader: Some(reader),
+            data: Vec::new(),
+            options,
+        }
+    }
+
+    /// The dimensions of the image, see `ImageDecoder::dimensions`.
+    pub fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        self.decoder.dimensions()
+    }
+
+    /// The color type of the decoded samples, see `ImageDecoder::colortype`.
+    pub fn colortype(&mut self) -> ImageResult<ColorType> {
+        self.decoder.colortype()
+    }
+
+    /// The parsed header.
+    pub fn header(&self) -> &PNMHeader {
+        self.decoder.header()
+    }
+
+    /// Read the raster and decode the image, see `ImageDecoder::read_image`.
+    pub fn read_image<'a>(&'a mut self) -> impl Future<Output = ImageResult<DecodingResult>> + 'a {
+        ReadImage {
+            decoder: self,
+            eof: false,
+        }
+    }
+}
+
+/// Move the bytes buffered by `reader` to `data`, returning whether the input is exhausted.
+fn poll_append<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    data: &mut Vec<u8>,
+    cx: &mut Context,
+) -> Poll<io::Result<bool>> {
+    let len = match Pin::new(&mut *reader).poll_fill_buf(cx) {
+        Poll::Ready(Ok(buf)) => {
+            data.extend_from_slice(buf);
+            buf.len()
+        }
+        Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+        Poll::Pending => return Poll::Pending,
+    };
+    Pin::new(reader).consume(len);
+    Poll::Ready(Ok(len == 0))
+}
+
+/// Future parsing the header, created by `AsyncPNMDecoder::with_options`.
+struct ReadHeader<R> {
+    reader: Option<R>,
+    data: Vec<u8>,
+    options: PnmDecoderOptions,
+}
+
+impl<R: AsyncBufRead + Unpin> Future for ReadHeader<R> {
+    type Output = ImageResult<AsyncPNMDecoder<R>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            let reader = this.reader
+                .as_mut()
+                .expect("ReadHeader polled after completion");
+            let eof = match poll_append(reader, &mut this.data, cx) {
+                Poll::Ready(Ok(eof)) => eof,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(ImageError::IoError(err))),
+                Poll::Pending => return Poll::Pending,
+            };
+            // The header is parsed anew from the start whenever more bytes arrive, it is short.
+            let partial = Partial {
+                data: &this.data,
+                eof,
+            };
+            match this.options.read_buffered(partial) {
+                Err(ImageError::IoError(ref err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                    continue
+                }
+                Err(err) => return Poll::Ready(Err(err)),
+                Ok(_) => break,
+            }
+        }
+
+        let data = mem::replace(&mut this.data, Vec::new());
+        let result = this.options
+            .read_buffered(Cursor::new(data))
+            .map(|decoder| AsyncPNMDecoder {
+                reader: this.reader.take().unwrap(),
+                decoder,
+            });
+        Poll::Ready(result)
+    }
+}
+
+/// Future decoding the image, created by `AsyncPNMDecoder::read_image`.
+struct ReadImage<'a, R: 'a> {
+    decoder: &'a mut AsyncPNMDecoder<R>,
+    eof: bool,
+}
+
+impl<'a, R: AsyncBufRead + Unpin> Future for ReadImage<'a, R> {
+    type Output = ImageResult<DecodingResult>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let raster_len = match this.decoder.decoder.raster_len() {
+            Ok(len) => len,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+        loop {
+            let available = {
+                let cursor = this.decoder.decoder.get_mut();
+                cursor.get_ref().len() as u64 - cursor.position()
+            };
+            match raster_len {
+                _ if this.eof => break,
+                Some(len) if available >= len as u64 => break,
+                _ => (),
+            }
+            let data = this.decoder.decoder.get_mut().get_mut();
+            this.eof = match poll_append(&mut this.decoder.reader, data, cx) {
+                Poll::Ready(Ok(eof)) => eof,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(ImageError::IoError(err))),
+                Poll::Pending => return Poll::Pending,
+            };
+        }
+        Poll::Ready(this.decoder.decoder.read_image())
+    }
+}
+
+/// The bytes received so far, reporting `WouldBlock` at their end until the input is exhausted.
+struct Partial<'a> {
+    data: &'a [u8],
+    eof: bool,
+}
+
+impl<'a> Read for Partial<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let available = self.fill_buf()?;
+            let len = cmp::min(available.len(), buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            len
+        };
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<'a> BufRead for Partial<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.data.is_empty() && !self.eof {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "Header incomplete"));
+        }
+        Ok(self.data)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.data = &self.data[amt..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncRead, ReadBuf};
+    use tokio::runtime::Builder;
+
+    /// Yields the data a few bytes at a time, returning `Pending` before each chunk.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+        ready: bool,
+    }
+
+    impl<'a> Trickle<'a> {
+        fn new(data: &'a [u8], chunk: usize) -> Self {
+            Trickle {
+                data,
+                chunk,
+                ready: false,
+            }
+        }
+    }
+
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            let len = match self.as_mut().poll_fill_buf(cx) {
+                Poll::Ready(Ok(available)) => {
+                    let len = cmp::min(available.len(), buf.remaining());
+                    buf.put_slice(&available[..len]);
+                    len
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.consume(len);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<'a> AsyncBufRead for Trickle<'a> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            if !this.ready {
+                this.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = cmp::min(this.chunk, this.data.len());
+            Poll::Ready(Ok(&this.data[..len]))
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            let this = self.get_mut();
+            this.data = &this.data[amt..];
+            this.ready = false;
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn graymap() {
+        let pgm = b"P5\n# comment\n3 2\n255\n\x01\x02\x03\x04\x05\x06trailing";
+        for &chunk in &[1, 4, pgm.len()] {
+            let mut decoder = block_on(AsyncPNMDecoder::new(Trickle::new(pgm, chunk))).unwrap();
+            assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+            assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+            assert_eq!(decoder.header().comments, vec!["comment".to_string()]);
+            let samples = block_on(decoder.read_image()).unwrap().into_u8();
+            assert_eq!(samples, Some(vec![1, 2, 3, 4, 5, 6]));
+        }
+    }
+
+    #[test]
+    fn arbitrary() {
+        let pam = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\
+                    \x01\x02\x03\x04\x05\x06\x07\x08";
+        let mut decoder = block_on(AsyncPNMDecoder::new(Trickle::new(pam, 3))).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 1));
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+        let samples = block_on(decoder.read_image()).unwrap().into_u8();
+        assert_eq!(samples, Some(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn errors() {
+        match block_on(AsyncPNMDecoder::new(Trickle::new(b"P5 3 2", 2))) {
+            Err(ImageError::FormatError(_)) => (),
+            other => panic!("Expected a format error, got {:?}", other.err()),
+        }
+
+        let truncated = b"P5 3 2 255\n\x01\x02";
+        let mut decoder = block_on(AsyncPNMDecoder::new(Trickle::new(truncated, 2))).unwrap();
+        assert!(block_on(decoder.read_image()).is_err());
+    }
+}
@@ -1,21 +1,50 @@
-use std::io::{BufRead, BufReader, Read};
+use std::borrow::Cow;
+use std::cmp;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use super::{ArbitraryHeader, ArbitraryTuplType, BitmapHeader, GraymapHeader, PixmapHeader};
-use super::{HeaderRecord, PNMHeader, PNMSubtype, SampleEncoding};
-use color::ColorType;
+use super::{Endianness, FloatmapColor, FloatmapHeader};
+use super::{guess_pnm_subtype, HeaderRecord, PNMEncoder, PNMHeader, PNMSubtype, PnmError,
+            PnmImageView, SampleEncoding};
+use buffer::{ImageBuffer, Pixel};
+use color::{num_components, ColorType, Luma, Rgb};
+use dynimage::{decoder_to_image, DynamicImage};
 use image::{DecodingResult, ImageDecoder, ImageError, ImageResult};
 
-use byteorder::{BigEndian, ByteOrder};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, NativeEndian};
+
+/// Emit a `tracing` event at debug level, compiled out without the `tracing` feature.
+macro_rules! pnm_event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($args)*);
+    };
+}
 
 /// Dynamic representation, represents all decodable (sample, depth) combinations.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 enum TupleType {
     PbmBit,
     BWBit,
+    /// Bits with an alpha bit of a pam of tuple type `BLACKANDWHITE_ALPHA`
+    BWAlphaBit,
     GrayU8,
     GrayU16,
+    GrayAU8,
+    GrayAU16,
     RGBU8,
     RGBU16,
+    RGBAU8,
+    RGBAU16,
+    GrayF32,
+    RGBF32,
+    /// Samples of a custom pam tuple type with the given number of channels
+    CustomU8(u8),
+    CustomU16(u8),
 }
 
 trait Sample {
@@ -24,137 +53,798 @@ trait Sample {
     /// It is guaranteed that `bytes.len() == bytelen(width, height, samples)`
     fn from_bytes(bytes: &[u8], width: u32, height: u32, samples: u32)
         -> ImageResult<Vec<Self::T>>;
+    #[cfg_attr(not(feature = "pnm_ascii"), allow(dead_code))]
     fn from_unsigned(u32) -> ImageResult<Self::T>;
 }
 
 struct U8;
-struct U16;
+#[cfg(feature = "pnm_u16")]
+struct U16<E: ByteOrder>(PhantomData<E>);
 struct PbmBit;
 struct BWBit;
+struct F32<E: ByteOrder>(PhantomData<E>);
+
+/// Default for the maximal length of a header line or token, see `PnmDecoderOptions`
+const DEFAULT_MAX_HEADER_LINE: usize = 4096;
+/// Maximal length of the tuple type of a pam, including the spaces joining repeated lines
+const MAX_TUPLTYPE_LEN: usize = 256;
+/// Maximal number of lines of a pam header, including comments and empty lines
+const MAX_PAM_HEADER_LINES: usize = 1024;
+/// Number of decoded bytes after which `read_image_with_progress` reports progress
+const PROGRESS_BYTES: usize = 1 << 20;
+/// Maximal number of ascii samples reserved before reading them, the header may overstate them
+#[cfg(feature = "pnm_ascii")]
+const MAX_ASCII_RESERVE: usize = 1 << 24;
 
 trait DecodableImageHeader {
     fn tuple_type(&self) -> ImageResult<TupleType>;
 }
 
+/// Limits on the size of images accepted by `PNMDecoder::with_limits` or `PnmDecoderOptions`.
+///
+/// Every limit is checked after the header has been parsed and before any memory for the raster
+/// is allocated, `max_input` is additionally enforced while reading. A limit of `None` is not
+/// enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximal width of an image in pixels
+    pub max_width: Option<u32>,
+    /// The maximal height of an image in pixels
+    pub max_height: Option<u32>,
+    /// The maximal number of bytes of the decoded image, see `PNMDecoder::total_bytes`
+    pub max_bytes: Option<usize>,
+    /// The maximal number of bytes of header and raster read from the input
+    pub max_input: Option<u64>,
+}
+
+/// How `PnmDecoderOptions::downscale` maps 16-bit samples to 8 bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Downscale {
+    /// Round to the nearest value, `(v * 255 + 32767) / 65535`
+    Round,
+    /// Round towards zero, `v * 255 / 65535`
+    Truncate,
+}
+
+/// Options for decoding pnm images.
+///
+/// Create a configured decoder with `read`, the defaults are those used by `PNMDecoder::new`.
+///
+/// ```
+/// use image::pnm::PnmDecoderOptions;
+/// use image::ImageDecoder;
+///
+/// let pgm = b"P2 2 1 100 50 100";
+/// let mut decoder = PnmDecoderOptions::new()
+///     .max_bytes(1 << 20)
+///     .scale_to_full(true)
+///     .read(&pgm[..])
+///     .unwrap();
+/// assert_eq!(decoder.dimensions().unwrap(), (2, 1));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PnmDecoderOptions {
+    limits: Limits,
+    lenient: bool,
+    scale_to_full: bool,
+    strict: bool,
+    reject_trailing: bool,
+    grayscale: bool,
+    expand_rgb: bool,
+    downscale: Option<Downscale>,
+    upscale: bool,
+    endianness: Option<Endianness>,
+    max_header_line: usize,
+}
+
+impl PnmDecoderOptions {
+    /// Options that decode images strictly according to the specification without limits.
+    pub fn new() -> Self {
+        PnmDecoderOptions {
+            limits: Limits::default(),
+            lenient: false,
+            scale_to_full: false,
+            strict: false,
+            reject_trailing: false,
+            grayscale: false,
+            expand_rgb: false,
+            downscale: None,
+            upscale: false,
+            endianness: None,
+            max_header_line: DEFAULT_MAX_HEADER_LINE,
+        }
+    }
+
+    /// Reject images exceeding `limits` with `ImageError::LimitError`.
+    pub fn limits(self, limits: Limits) -> Self {
+        PnmDecoderOptions { limits, ..self }
+    }
+
+    /// Reject images whose decoded size exceeds `max_bytes` with `ImageError::LimitError`.
+    ///
+    /// Shorthand for setting `Limits::max_bytes`, see `PNMDecoder::total_bytes`.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.limits.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Reject images whose header and raster take more than `max_input` bytes of the input with
+    /// `ImageError::LimitError`.
+    ///
+    /// Shorthand for setting `Limits::max_input`. Binary rasters have the length implied by the
+    /// header, so they are checked right after parsing it. Headers and ascii rasters are rejected
+    /// as soon as they exceed the limit, which bounds the work done for endless input.
+    pub fn max_input(mut self, max_input: u64) -> Self {
+        self.limits.max_input = Some(max_input);
+        self
+    }
+
+    /// Skip unknown header lines of a pam instead of rejecting them.
+    ///
+    /// The lines `WIDTH`, `HEIGHT`, `DEPTH` and `MAXVAL` are still required. A UTF-8 byte order
+    /// mark and whitespace before the magic constant are skipped as well, they are not part of
+    /// the encoded header.
+    pub fn lenient(self, lenient: bool) -> Self {
+        PnmDecoderOptions { lenient, ..self }
+    }
+
+    /// Scale samples to the full range of their output type, see `PNMDecoder::set_scale_to_full`.
+    pub fn scale_to_full(self, scale_to_full: bool) -> Self {
+        PnmDecoderOptions {
+            scale_to_full,
+            ..self
+        }
+    }
+
+    /// Reject samples greater than the maxval of the image with `PnmError::SampleOutOfRange`.
+    ///
+    /// Such samples are decoded verbatim by default. This applies to ascii and binary samples
+    /// alike, bitmaps and float maps are not affected.
+    pub fn strict(self, strict: bool) -> Self {
+        PnmDecoderOptions { strict, ..self }
+    }
+
+    /// Reject ascii images with anything but whitespace after their last sample with
+    /// `ImageError::FormatError`.
+    ///
+    /// The input is checked up to its end once the last row has been read, so this can not be
+    /// combined with `PNMDecoder::next_frame`. Extra data after binary rasters is not checked.
+    pub fn reject_trailing(self, reject_trailing: bool) -> Self {
+        PnmDecoderOptions {
+            reject_trailing,
+            ..self
+        }
+    }
+
+    /// Convert RGB and RGBA images to grayscale while decoding.
+    ///
+    /// Each pixel is replaced by its luminance `0.299 R + 0.587 G + 0.114 B`, rounded to the
+    /// nearest integer for 8 and 16-bit samples, and the alpha channel is dropped. `colortype`
+    /// and all sizes reported by the decoder describe the converted image.
+    pub fn grayscale(self, grayscale: bool) -> Self {
+        PnmDecoderOptions { grayscale, ..self }
+    }
+
+    /// Expand grayscale images to RGB and grayscale images with alpha to RGBA while decoding.
+    ///
+    /// The gray sample is replicated into the red, green and blue channel. Bitmaps are not
+    /// affected. Combined with `grayscale`, RGB images are decoded as RGB with equal channels.
+    pub fn expand_rgb(self, expand_rgb: bool) -> Self {
+        PnmDecoderOptions { expand_rgb, ..self }
+    }
+
+    /// Convert 16-bit samples to 8 bits while decoding.
+    ///
+    /// The full range `0..=65535` is mapped to `0..=255`, so for images whose maxval is smaller
+    /// `scale_to_full` should be set as well. `colortype` and all sizes reported by the decoder
+    /// describe the converted image.
+    pub fn downscale(self, downscale: Downscale) -> Self {
+        PnmDecoderOptions {
+            downscale: Some(downscale),
+            ..self
+        }
+    }
+
+    /// Convert 8-bit samples to 16 bits while decoding.
+    ///
+    /// Each sample is multiplied by 257, mapping `0..=255` to `0..=65535`. Bitmaps are not
+    /// affected. This applies after `downscale`, so setting both yields 16-bit samples with 8-bit
+    /// precision.
+    pub fn upscale(self, upscale: bool) -> Self {
+        PnmDecoderOptions { upscale, ..self }
+    }
+
+    /// Read binary 16-bit samples and the samples of float maps in the given byte order.
+    ///
+    /// The specification requires big endian 16-bit samples, but some tools write little endian
+    /// ones. Float maps are read in the given order regardless of the sign of their scale.
+    pub fn force_endianness(self, endianness: Endianness) -> Self {
+        PnmDecoderOptions {
+            endianness: Some(endianness),
+            ..self
+        }
+    }
+
+    /// Accept header lines, comments and tokens of up to `max_line` bytes, 4 KiB by default.
+    ///
+    /// Longer ones are rejected with `ImageError::FormatError` as soon as the limit is exceeded,
+    /// without reading them in full.
+    pub fn max_header_line(self, max_line: usize) -> Self {
+        PnmDecoderOptions {
+            max_header_line: max_line,
+            ..self
+        }
+    }
+
+    /// Parse the header from `read` and create a decoder with these options.
+    pub fn read<R: Read>(&self, read: R) -> ImageResult<PNMDecoder<R>> {
+        PNMDecoder::read_header(BufReader::new(read), *self)
+    }
+
+    /// Parse the header from the buffered reader `buf` and create a decoder with these options.
+    pub fn read_buffered<B: BufRead>(&self, buf: B) -> ImageResult<PNMDecoder<B, B>> {
+        PNMDecoder::read_header(buf, *self)
+    }
+}
+
+impl Default for PnmDecoderOptions {
+    fn default() -> Self {
+        PnmDecoderOptions::new()
+    }
+}
+
 /// PNM decoder
-pub struct PNMDecoder<R> {
-    reader: BufReader<R>,
+///
+/// Creating a decoder only parses the header, so querying `dimensions` or `colortype` is cheap.
+///
+/// Float maps store their rows from bottom to top. `read_image` returns them from top to bottom
+/// like for all other formats while `read_scanline` yields the rows in the order they are stored.
+/// Decoding the whole image is rejected with `ImageError::FormatError` once rows have been read
+/// with `read_scanline`.
+///
+/// The decoder reads from the `BufRead` given by the type parameter `B`. The constructors taking
+/// any `Read` wrap it in a `BufReader`, use `from_buf_read` for readers that are already buffered.
+/// These decoders have the type `PNMDecoder<B, B>`.
+///
+/// Apart from the reader the decoder only owns plain data, so it is `Send` and `Sync` whenever
+/// `R` is. A decoder can be moved to another thread after parsing the header, for example to
+/// decode the raster in a worker pool. The same holds for `PnmRows`.
+pub struct PNMDecoder<R, B = BufReader<R>> {
+    reader: B,
     header: PNMHeader,
     tuple: TupleType,
+    rows_read: u32,
+    options: PnmDecoderOptions,
+    /// Number of bytes of the header and ascii samples read from the input
+    consumed: u64,
+    /// Number of ascii samples read from the input
+    #[cfg_attr(not(feature = "pnm_ascii"), allow(dead_code))]
+    samples_read: usize,
+    /// Offset of the raster from the start of the stream
+    data_offset: u64,
+    /// Raw bytes of a binary bitmap row, reused between rows
+    scratch: Vec<u8>,
+    /// The row decoded by `read_row`
+    row: Vec<u8>,
+    marker: PhantomData<R>,
 }
 
 impl<R: Read> PNMDecoder<R> {
     /// Create a new decoder that decodes from the stream ```read```
     pub fn new(read: R) -> ImageResult<PNMDecoder<R>> {
-        let mut buf = BufReader::new(read);
+        PnmDecoderOptions::new().read(read)
+    }
+
+    /// Create a new decoder that skips unknown header lines of a pam instead of rejecting them.
+    ///
+    /// The lines `WIDTH`, `HEIGHT`, `DEPTH` and `MAXVAL` are still required. See
+    /// `PnmDecoderOptions::lenient` for what else is tolerated.
+    pub fn new_lenient(read: R) -> ImageResult<PNMDecoder<R>> {
+        PnmDecoderOptions::new().lenient(true).read(read)
+    }
+
+    /// Create a new decoder that accepts header lines, comments and tokens of up to `max_line`
+    /// bytes.
+    ///
+    /// Longer ones are rejected with `ImageError::FormatError` as soon as the limit is exceeded,
+    /// without reading them in full. The other constructors use a limit of 4 KiB.
+    pub fn with_max_header_line(read: R, max_line: usize) -> ImageResult<PNMDecoder<R>> {
+        PnmDecoderOptions::new().max_header_line(max_line).read(read)
+    }
+
+    /// Create a new decoder that rejects images exceeding `limits` with `ImageError::LimitError`.
+    pub fn with_limits(read: R, limits: Limits) -> ImageResult<PNMDecoder<R>> {
+        PnmDecoderOptions::new().limits(limits).read(read)
+    }
+
+    /// Parse only the header, returning it together with the byte offset of the raster.
+    ///
+    /// The header is read byte by byte, so not a single byte of the raster is consumed from `read`.
+    /// Pass a mutable reference to continue reading the raster from the same reader afterwards.
+    pub fn read_info(read: R) -> ImageResult<(PNMHeader, u64)> {
+        let buf = BufReader::with_capacity(1, read);
+        let decoder: PNMDecoder<R> = PNMDecoder::read_header(buf, PnmDecoderOptions::new())?;
+        let offset = decoder.data_offset();
+        Ok((decoder.header, offset))
+    }
+}
+
+impl<B: BufRead> PNMDecoder<B, B> {
+    /// Create a new decoder that decodes from the buffered reader `buf` without wrapping it in
+    /// another buffer.
+    pub fn from_buf_read(buf: B) -> ImageResult<PNMDecoder<B, B>> {
+        PnmDecoderOptions::new().read_buffered(buf)
+    }
+}
+
+impl<R, B: BufRead> PNMDecoder<R, B> {
+    fn read_header(
+        mut buf: B,
+        options: PnmDecoderOptions,
+    ) -> ImageResult<PNMDecoder<R, B>> {
+        let preamble = if options.lenient {
+            buf.skip_preamble()?
+        } else {
+            0
+        };
         let magic = try!(buf.read_magic_constant());
-        if magic[0] != b'P' {
-            return Err(ImageError::FormatError(
-                "Expected magic constant for pnm, P1 through P7".to_string(),
-            ));
+        let subtype = match guess_pnm_subtype(&magic) {
+            Some(subtype) => subtype,
+            None => return Err(PnmError::BadMagic(magic).into()),
+        };
+        pnm_event!(subtype = ?subtype, "pnm magic parsed");
+
+        let context = HeaderContext::new(magic, options.max_header_line, options.limits.max_input);
+        let lenient = options.lenient;
+        let mut decoder = match subtype {
+            PNMSubtype::Bitmap(enc) => PNMDecoder::read_bitmap_header(buf, enc, context),
+            PNMSubtype::Graymap(enc) => PNMDecoder::read_graymap_header(buf, enc, context),
+            PNMSubtype::Pixmap(enc) => PNMDecoder::read_pixmap_header(buf, enc, context),
+            PNMSubtype::ArbitraryMap => PNMDecoder::read_arbitrary_header(buf, lenient, context),
+            PNMSubtype::Floatmap(color) => PNMDecoder::read_floatmap_header(buf, color, context),
+        }?;
+        decoder.options = options;
+        decoder.data_offset = preamble + decoder.consumed;
+        decoder.check_limits()?;
+        pnm_event!(
+            width = decoder.header.width(),
+            height = decoder.header.height(),
+            subtype = ?subtype,
+            maxval = decoder.header.maximal_sample(),
+            "pnm header parsed"
+        );
+        Ok(decoder)
+    }
+
+    /// Check the dimensions from the header against the configured limits.
+    fn check_limits(&self) -> ImageResult<()> {
+        let limits = self.options.limits;
+        let exceeds = |limit: Option<u32>, value: u32| limit.map_or(false, |max| value > max);
+        if exceeds(limits.max_width, self.header.width())
+            || exceeds(limits.max_height, self.header.height())
+        {
+            return Err(ImageError::LimitError);
+        }
+        if let Some(max_bytes) = limits.max_bytes {
+            // An overflowing size exceeds any limit.
+            match self.total_bytes() {
+                Ok(bytes) if bytes <= max_bytes => (),
+                _ => return Err(ImageError::LimitError),
+            }
         }
+        if let (Some(max_input), SampleEncoding::Binary) =
+            (limits.max_input, self.subtype().sample_encoding())
+        {
+            // An overflowing size exceeds any limit.
+            let height = u64::from(self.header.height());
+            let raster = self.stored_rowlen()
+                .ok()
+                .and_then(|len| (len as u64).checked_mul(height));
+            match raster {
+                Some(raster) if self.consumed.saturating_add(raster) <= max_input => (),
+                _ => return Err(ImageError::LimitError),
+            }
+        }
+        Ok(())
+    }
 
-        let subtype = match magic[1] {
-            b'1' => PNMSubtype::Bitmap(SampleEncoding::Ascii),
-            b'2' => PNMSubtype::Graymap(SampleEncoding::Ascii),
-            b'3' => PNMSubtype::Pixmap(SampleEncoding::Ascii),
-            b'4' => PNMSubtype::Bitmap(SampleEncoding::Binary),
-            b'5' => PNMSubtype::Graymap(SampleEncoding::Binary),
-            b'6' => PNMSubtype::Pixmap(SampleEncoding::Binary),
-            b'7' => PNMSubtype::ArbitraryMap,
-            _ => {
-                return Err(ImageError::FormatError(
-                    "Expected magic constant for ppm, P1 through P7".to_string(),
-                ))
+    /// The length of a row of a binary raster.
+    fn stored_rowlen(&self) -> ImageResult<usize> {
+        let width = self.header.width();
+        match self.tuple {
+            TupleType::PbmBit => PbmBit::bytelen(width, 1, 1),
+            tuple => sample_count(width, 1, u32::from(tuple.channels()))?
+                .checked_mul(usize::from(tuple.sample_bytes()))
+                .ok_or_else(dimension_overflow),
+        }
+    }
+
+    /// The number of bytes the raster of a binary image takes in the stream, `None` for ascii
+    /// images.
+    pub fn raster_len(&self) -> ImageResult<Option<usize>> {
+        match self.subtype().sample_encoding() {
+            SampleEncoding::Ascii => Ok(None),
+            SampleEncoding::Binary => self.stored_rowlen()?
+                .checked_mul(self.header.height() as usize)
+                .map(Some)
+                .ok_or_else(dimension_overflow),
+        }
+    }
+
+    /// The reader the remaining rows are read from.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn get_mut(&mut self) -> &mut B {
+        &mut self.reader
+    }
+
+    /// Account for `count` bytes of ascii samples read from the input.
+    #[cfg_attr(not(feature = "pnm_ascii"), allow(dead_code))]
+    fn consume_input(&mut self, count: usize) -> ImageResult<()> {
+        self.consumed = self.consumed.saturating_add(count as u64);
+        match self.options.limits.max_input {
+            Some(max_input) if self.consumed > max_input => Err(ImageError::LimitError),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parse the header of the image following this one in the same stream.
+    ///
+    /// Rows of this image that have not been read yet are skipped, as are whitespace and comments
+    /// between the images. Returns `None` if the stream ends instead, also if the last comment
+    /// lacks a newline. The next decoder is configured with the
+    /// options of this one.
+    pub fn next_frame(mut self) -> ImageResult<Option<PNMDecoder<R, B>>> {
+        self.skip_rows()?;
+        let mut offset = self.bytes_consumed();
+
+        let mut comment = false;
+        loop {
+            let (skipped, available) = {
+                let buf = match self.reader.fill_buf() {
+                    Ok(buf) => buf,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(ImageError::IoError(err)),
+                };
+                let skipped = buf.iter()
+                    .take_while(|&&byte| {
+                        comment = match byte {
+                            b'\n' | b'\r' => false,
+                            b'#' => true,
+                            _ if comment || byte.is_ascii_whitespace() => comment,
+                            _ => return false,
+                        };
+                        true
+                    })
+                    .count();
+                (skipped, buf.len())
+            };
+            if available == 0 {
+                return Ok(None);
             }
-        };
+            self.reader.consume(skipped);
+            offset += skipped as u64;
+            if skipped < available {
+                break;
+            }
+        }
+
+        let mut next = PNMDecoder::read_header(self.reader, self.options)?;
+        next.data_offset += offset;
+        Ok(Some(next))
+    }
+
+    /// The offset of the first byte of the raster from the start of the stream.
+    ///
+    /// This is the length of the header, including any preamble skipped by lenient decoding. For
+    /// decoders returned by `next_frame` it is counted from the start of the first image. The
+    /// raster of a binary image spans the next `raster_len` bytes, which is `total_bytes` unless
+    /// the image is a bitmap or decoding converts its samples.
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
 
-        match subtype {
-            PNMSubtype::Bitmap(enc) => PNMDecoder::read_bitmap_header(buf, enc),
-            PNMSubtype::Graymap(enc) => PNMDecoder::read_graymap_header(buf, enc),
-            PNMSubtype::Pixmap(enc) => PNMDecoder::read_pixmap_header(buf, enc),
-            PNMSubtype::ArbitraryMap => PNMDecoder::read_arbitrary_header(buf),
+    /// The number of bytes of the stream consumed so far, counted like `data_offset`.
+    ///
+    /// This covers the header and the rows read so far. Once all rows have been read it is the
+    /// offset of the first byte following the image, including the single whitespace byte after
+    /// the last sample of an ascii raster, which is where `into_inner` leaves the reader.
+    pub fn bytes_consumed(&self) -> u64 {
+        match self.subtype().sample_encoding() {
+            SampleEncoding::Binary => {
+                // The row length can only overflow if no row has been read.
+                let rows = self.stored_rowlen()
+                    .map_or(0, |rowlen| rowlen as u64 * u64::from(self.rows_read));
+                self.data_offset + rows
+            }
+            // Ascii samples are counted as they are consumed, following the header.
+            SampleEncoding::Ascii => {
+                let header_len = self.header.encoded().map_or(0, |encoded| encoded.len() as u64);
+                self.data_offset + (self.consumed - header_len)
+            }
         }
     }
 
     /// Extract the reader and header after an image has been read.
-    pub fn into_inner(self) -> (R, PNMHeader) {
-        (self.reader.into_inner(), self.header)
+    ///
+    /// The reader still holds the bytes it has buffered, so after `read_image` it is
+    /// positioned exactly at the byte following the raster. Trailing data such as metadata or
+    /// further images can be read from it. The single whitespace byte after the last sample of
+    /// an ascii raster has been consumed.
+    pub fn into_inner(self) -> (B, PNMHeader) {
+        (self.reader, self.header)
     }
 
-    fn read_bitmap_header(
-        mut reader: BufReader<R>,
-        encoding: SampleEncoding,
-    ) -> ImageResult<PNMDecoder<R>> {
-        let header = reader.read_bitmap_header(encoding)?;
-        Ok(PNMDecoder {
+    /// Advance past the rows not read yet without decoding them and extract reader and header.
+    ///
+    /// The bytes of a binary raster are read and discarded, use `seek_past_image` to seek over
+    /// them instead. Ascii samples are only delimited by parsing them, so they are still read
+    /// one by one. The reader is left like by `into_inner` after `read_image`, so the image
+    /// following in the stream can be decoded from it.
+    pub fn skip_image(mut self) -> ImageResult<(B, PNMHeader)> {
+        self.skip_rows()?;
+        Ok(self.into_inner())
+    }
+
+    /// Discard the rows not read yet, afterwards all rows count as read.
+    fn skip_rows(&mut self) -> ImageResult<()> {
+        let remaining = self.header.height() - self.rows_read;
+        match self.raster_len()? {
+            _ if remaining == 0 => (),
+            Some(_) => {
+                let len = self.stored_rowlen()?
+                    .checked_mul(remaining as usize)
+                    .ok_or_else(dimension_overflow)?;
+                skip_raster(&mut self.reader, len)?;
+            }
+            None => self.skip_ascii(remaining)?,
+        }
+        self.rows_read = self.header.height();
+        Ok(())
+    }
+
+    /// Parse the ascii samples of the next `rows` rows one at a time, discarding them.
+    #[cfg(feature = "pnm_ascii")]
+    fn skip_ascii(&mut self, rows: u32) -> ImageResult<()> {
+        let components = u32::from(self.tuple.channels());
+        let count = sample_count(self.header.width(), rows, components)?;
+        self.parse_ascii(components, count, |_| Ok(()))
+    }
+
+    #[cfg(not(feature = "pnm_ascii"))]
+    fn skip_ascii(&mut self, _: u32) -> ImageResult<()> {
+        Err(ImageError::UnsupportedError(
+            "ASCII PNM not supported in this build".to_string(),
+        ))
+    }
+
+    /// Iterate over the decoded rows of the image.
+    ///
+    /// Each row is laid out like by `read_scanline`. The iterator ends after the last row or after
+    /// yielding the first error. Every row is allocated anew, see `read_row` to reuse a buffer.
+    pub fn rows(self) -> PnmRows<R, B> {
+        PnmRows {
+            decoder: self,
+            failed: false,
+        }
+    }
+
+    /// Decode the next row into a buffer owned by the decoder, or return `None` after the last.
+    ///
+    /// The row is laid out like by `read_scanline` and overwritten by the next call, so copy it out
+    /// to retain it. Unless the options convert the color type or the depth of the samples, rows
+    /// are decoded straight into this buffer without allocating after the first row.
+    pub fn read_row(&mut self) -> ImageResult<Option<&[u8]>> {
+        if self.rows_read >= self.header.height() {
+            return Ok(None);
+        }
+
+        let rowlen = self.rowlen()?;
+        let mut row = mem::replace(&mut self.row, Vec::new());
+        row.resize(rowlen, 0);
+        let result = self.read_scanline(&mut row);
+        self.row = row;
+        result?;
+        Ok(Some(&self.row))
+    }
+
+    /// Inspect the header without consuming the decoder.
+    ///
+    /// ```
+    /// use image::ImageDecoder;
+    /// use image::pnm::{ArbitraryTuplType, PNMDecoder};
+    ///
+    /// let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nTUPLTYPE GRAYSCALE\nENDHDR\n\x7f";
+    /// let mut decoder = PNMDecoder::new(&pam[..]).unwrap();
+    ///
+    /// match decoder.header().as_arbitrary().and_then(|pam| pam.tupltype.as_ref()) {
+    ///     Some(&ArbitraryTuplType::Grayscale) => (),
+    ///     other => panic!("Unexpected tuple type {:?}", other),
+    /// }
+    /// decoder.read_image().unwrap();
+    /// ```
+    pub fn header(&self) -> &PNMHeader {
+        &self.header
+    }
+
+    /// The maximal sample value declared in the header.
+    ///
+    /// Samples are decoded verbatim into `u8` for a maxval of up to 255 and into `u16` otherwise,
+    /// so callers can normalize them by dividing by the maxval. See `set_scale_to_full` to have
+    /// the decoder do this and `PnmDecoderOptions::strict` to reject samples above the maxval.
+    /// Bitmaps and float maps have no maxval and return `None`.
+    pub fn maxval(&self) -> Option<u32> {
+        match self.header.decoded {
+            HeaderRecord::Bitmap(_) | HeaderRecord::Floatmap(_) => None,
+            HeaderRecord::Graymap(GraymapHeader { maxwhite, .. }) => Some(maxwhite),
+            HeaderRecord::Pixmap(PixmapHeader { maxval, .. }) => Some(maxval),
+            HeaderRecord::Arbitrary(ArbitraryHeader { maxval, .. }) => Some(maxval),
+        }
+    }
+
+    /// Scale samples to the full range of their output type.
+    ///
+    /// Images whose maxval is not 255 or 65535 are decoded verbatim by default, so a sample equal
+    /// to the maxval does not denote full intensity. When enabled, each sample is multiplied by
+    /// `255 / maxval` or `65535 / maxval` respectively and rounded to the nearest integer. This
+    /// does not affect bitmaps or float maps.
+    pub fn set_scale_to_full(&mut self, scale_to_full: bool) {
+        self.options.scale_to_full = scale_to_full;
+    }
+
+    fn from_parts(
+        reader: B,
+        decoded: HeaderRecord,
+        context: HeaderContext,
+        tuple: TupleType,
+    ) -> Self {
+        PNMDecoder {
             reader,
-            tuple: TupleType::PbmBit,
+            consumed: context.raw.len() as u64,
             header: PNMHeader {
-                decoded: HeaderRecord::Bitmap(header),
-                encoded: None,
+                decoded,
+                encoded: Some(context.raw),
+                comments: context.comments,
             },
-        })
+            tuple,
+            rows_read: 0,
+            options: PnmDecoderOptions::new(),
+            samples_read: 0,
+            data_offset: 0,
+            scratch: Vec::new(),
+            row: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+
+    fn read_bitmap_header(
+        mut reader: B,
+        encoding: SampleEncoding,
+        mut context: HeaderContext,
+    ) -> ImageResult<PNMDecoder<R, B>> {
+        let header = reader.read_bitmap_header(encoding, &mut context)?;
+        Ok(PNMDecoder::from_parts(
+            reader,
+            HeaderRecord::Bitmap(header),
+            context,
+            TupleType::PbmBit,
+        ))
     }
 
     fn read_graymap_header(
-        mut reader: BufReader<R>,
+        mut reader: B,
         encoding: SampleEncoding,
-    ) -> ImageResult<PNMDecoder<R>> {
-        let header = reader.read_graymap_header(encoding)?;
+        mut context: HeaderContext,
+    ) -> ImageResult<PNMDecoder<R, B>> {
+        let header = reader.read_graymap_header(encoding, &mut context)?;
         let tuple_type = header.tuple_type()?;
-        Ok(PNMDecoder {
+        Ok(PNMDecoder::from_parts(
             reader,
-            tuple: tuple_type,
-            header: PNMHeader {
-                decoded: HeaderRecord::Graymap(header),
-                encoded: None,
-            },
-        })
+            HeaderRecord::Graymap(header),
+            context,
+            tuple_type,
+        ))
     }
 
     fn read_pixmap_header(
-        mut reader: BufReader<R>,
+        mut reader: B,
         encoding: SampleEncoding,
-    ) -> ImageResult<PNMDecoder<R>> {
-        let header = reader.read_pixmap_header(encoding)?;
+        mut context: HeaderContext,
+    ) -> ImageResult<PNMDecoder<R, B>> {
+        let header = reader.read_pixmap_header(encoding, &mut context)?;
         let tuple_type = header.tuple_type()?;
-        Ok(PNMDecoder {
+        Ok(PNMDecoder::from_parts(
             reader,
-            tuple: tuple_type,
-            header: PNMHeader {
-                decoded: HeaderRecord::Pixmap(header),
-                encoded: None,
-            },
-        })
+            HeaderRecord::Pixmap(header),
+            context,
+            tuple_type,
+        ))
     }
 
-    fn read_arbitrary_header(mut reader: BufReader<R>) -> ImageResult<PNMDecoder<R>> {
-        let header = reader.read_arbitrary_header()?;
+    fn read_arbitrary_header(
+        mut reader: B,
+        lenient: bool,
+        mut context: HeaderContext,
+    ) -> ImageResult<PNMDecoder<R, B>> {
+        let header = reader.read_arbitrary_header(lenient, &mut context)?;
         let tuple_type = header.tuple_type()?;
-        Ok(PNMDecoder {
+        Ok(PNMDecoder::from_parts(
             reader,
-            tuple: tuple_type,
-            header: PNMHeader {
-                decoded: HeaderRecord::Arbitrary(header),
-                encoded: None,
-            },
-        })
+            HeaderRecord::Arbitrary(header),
+            context,
+            tuple_type,
+        ))
+    }
+
+    fn read_floatmap_header(
+        mut reader: B,
+        color: FloatmapColor,
+        mut context: HeaderContext,
+    ) -> ImageResult<PNMDecoder<R, B>> {
+        let header = reader.read_floatmap_header(color, &mut context)?;
+        let tuple_type = header.tuple_type()?;
+        Ok(PNMDecoder::from_parts(
+            reader,
+            HeaderRecord::Floatmap(header),
+            context,
+            tuple_type,
+        ))
     }
 }
 
 trait HeaderReader: BufRead {
+    /// Skips a UTF-8 byte order mark and whitespace before the magic constant, returning the
+    /// number of bytes skipped
+    fn skip_preamble(&mut self) -> ImageResult<u64> {
+        let mut skipped = 0;
+        let mut first = true;
+        loop {
+            let next = match self.fill_buf() {
+                Ok(buf) => buf.first().cloned(),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(ImageError::IoError(err)),
+            };
+            match next {
+                Some(0xEF) if first => {
+                    let mut bom = [0; 3];
+                    self.read_exact(&mut bom).map_err(|err| match err.kind() {
+                        io::ErrorKind::UnexpectedEof => ImageError::NotEnoughData,
+                        _ => ImageError::IoError(err),
+                    })?;
+                    if bom != [0xEF, 0xBB, 0xBF] {
+                        return Err(ImageError::FormatError(
+                            "Invalid byte order mark".to_string(),
+                        ));
+                    }
+                    skipped += 3;
+                }
+                Some(byte) if byte.is_ascii_whitespace() => {
+                    self.consume(1);
+                    skipped += 1;
+                }
+                _ => return Ok(skipped),
+            }
+            first = false;
+        }
+    }
+
     /// Reads the two magic constant bytes
     fn read_magic_constant(&mut self) -> ImageResult<[u8; 2]> {
         let mut magic: [u8; 2] = [0, 0];
-        self.read_exact(&mut magic)
-            .map_err(|_| ImageError::NotEnoughData)?;
+        self.read_exact(&mut magic).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => ImageError::NotEnoughData,
+            _ => ImageError::IoError(err),
+        })?;
         Ok(magic)
     }
 
-    /// Reads a string as well as a single whitespace after it, ignoring comments
-    fn read_next_string(&mut self) -> ImageResult<String> {
+    /// Reads a string as well as a single whitespace after it, collecting comments
+    ///
+    /// Exactly one byte of whitespace is consumed after the token. For binary subtypes this is the
+    /// separator between the last header value and the raster, all following bytes are samples.
+    /// The text of each comment passed on the way is appended to the context.
+    fn read_next_string(&mut self, context: &mut HeaderContext) -> ImageResult<String> {
         let mut bytes = Vec::new();
+        let mut comment: Option<Vec<u8>> = None;
 
         // pair input bytes with a bool mask to remove comments
         let mark_comments = self.bytes().scan(true, |partof, read| {
@@ -168,51 +858,108 @@ trait HeaderReader: BufRead {
             Some((cur_enabled, Ok(byte)))
         });
 
-        for (_, byte) in mark_comments.filter(|ref e| e.0) {
+        for (enabled, byte) in mark_comments {
+            let byte = byte.map_err(ImageError::IoError)?;
+            context.raw.push(byte);
+            context.check_input()?;
+            if !enabled {
+                if comment.as_ref().map_or(false, |text| text.len() >= context.max_line) {
+                    return Err(context.error("Comment in preamble too long"));
+                }
+                match (byte, comment.take()) {
+                    (b'\n', Some(text)) | (b'\r', Some(text)) => {
+                        push_comment(&mut context.comments, text)
+                    }
+                    (byte, Some(mut text)) => {
+                        text.push(byte);
+                        comment = Some(text);
+                    }
+                    (_, None) => comment = Some(Vec::new()),
+                }
+                continue;
+            }
             match byte {
-                Ok(b'\t') | Ok(b'\n') | Ok(b'\x0b') | Ok(b'\x0c') | Ok(b'\r') | Ok(b' ') => {
+                b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r' | b' ' => {
                     if !bytes.is_empty() {
                         break; // We're done as we already have some content
                     }
                 }
-                Ok(byte) => {
+                byte => {
+                    if bytes.is_empty() {
+                        context.token = context.raw.len() - 1;
+                    } else if bytes.len() >= context.max_line {
+                        return Err(context.error("Token in preamble too long"));
+                    }
                     bytes.push(byte);
                 }
-                Err(_) => break,
             }
         }
 
+        if let Some(text) = comment {
+            push_comment(&mut context.comments, text);
+        }
+
         if bytes.is_empty() {
-            return Err(ImageError::FormatError("Unexpected eof".to_string()));
+            context.token = context.raw.len();
+            return Err(context.error("Unexpected eof"));
         }
 
         if !bytes.as_slice().is_ascii() {
-            return Err(ImageError::FormatError(
-                "Non ascii character in preamble".to_string(),
-            ));
+            return Err(context.error("Non ascii character in preamble"));
         }
 
-        String::from_utf8(bytes)
-            .map_err(|_| ImageError::FormatError("Couldn't read preamble".to_string()))
+        String::from_utf8(bytes).map_err(|_| context.error("Couldn't read preamble"))
     }
 
-    /// Read the next line
-    fn read_next_line(&mut self) -> ImageResult<String> {
-        let mut buffer = String::new();
-        self.read_line(&mut buffer)
-            .map_err(|_| ImageError::FormatError("Line not properly formatted".to_string()))?;
-        Ok(buffer)
+    /// Read the next line including its newline, which is empty only at the end of the stream
+    ///
+    /// The line is consumed piecewise from the buffer, a line longer than the maximal line length
+    /// of the context is rejected without reading the rest of it.
+    fn read_next_line(&mut self, context: &mut HeaderContext) -> ImageResult<Vec<u8>> {
+        let mut line = Vec::new();
+        loop {
+            let (complete, used) = {
+                let available = match self.fill_buf() {
+                    Ok(available) => available,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(ImageError::IoError(err)),
+                };
+                match available.iter().position(|&byte| byte == b'\n') {
+                    Some(end) => {
+                        line.extend_from_slice(&available[..end + 1]);
+                        (true, end + 1)
+                    }
+                    None => {
+                        line.extend_from_slice(available);
+                        (available.is_empty(), available.len())
+                    }
+                }
+            };
+            self.consume(used);
+            if line.len() > context.max_line {
+                context.next_line(&[]);
+                return Err(context.line_error("Header line too long"));
+            }
+            if complete {
+                return Ok(line);
+            }
+        }
     }
 
-    fn read_next_u32(&mut self) -> ImageResult<u32> {
-        let s = try!(self.read_next_string());
+    fn read_next_u32(&mut self, context: &mut HeaderContext) -> ImageResult<u32> {
+        let s = try!(self.read_next_string(context));
         s.parse::<u32>()
-            .map_err(|_| ImageError::FormatError("Invalid number in preamble".to_string()))
+            .map_err(|_| context.error("Invalid number in preamble"))
     }
 
-    fn read_bitmap_header(&mut self, encoding: SampleEncoding) -> ImageResult<BitmapHeader> {
-        let width = try!(self.read_next_u32());
-        let height = try!(self.read_next_u32());
+    fn read_bitmap_header(
+        &mut self,
+        encoding: SampleEncoding,
+        context: &mut HeaderContext,
+    ) -> ImageResult<BitmapHeader> {
+        let width = try!(self.read_next_u32(context));
+        let height = try!(self.read_next_u32(context));
+        check_dimensions(width, height)?;
         Ok(BitmapHeader {
             encoding,
             width,
@@ -220,8 +967,12 @@ trait HeaderReader: BufRead {
         })
     }
 
-    fn read_graymap_header(&mut self, encoding: SampleEncoding) -> ImageResult<GraymapHeader> {
-        self.read_pixmap_header(encoding).map(
+    fn read_graymap_header(
+        &mut self,
+        encoding: SampleEncoding,
+        context: &mut HeaderContext,
+    ) -> ImageResult<GraymapHeader> {
+        self.read_pixmap_header(encoding, context).map(
             |PixmapHeader {
                  encoding,
                  width,
@@ -236,10 +987,18 @@ trait HeaderReader: BufRead {
         )
     }
 
-    fn read_pixmap_header(&mut self, encoding: SampleEncoding) -> ImageResult<PixmapHeader> {
-        let width = try!(self.read_next_u32());
-        let height = try!(self.read_next_u32());
-        let maxval = try!(self.read_next_u32());
+    fn read_pixmap_header(
+        &mut self,
+        encoding: SampleEncoding,
+        context: &mut HeaderContext,
+    ) -> ImageResult<PixmapHeader> {
+        let width = try!(self.read_next_u32(context));
+        let height = try!(self.read_next_u32(context));
+        let maxval = try!(self.read_next_u32(context));
+        check_dimensions(width, height)?;
+        if maxval == 0 || maxval > 0xFFFF {
+            return Err(context.error("maxval must be between 1 and 65535"));
+        }
         Ok(PixmapHeader {
             encoding,
             width,
@@ -248,69 +1007,108 @@ trait HeaderReader: BufRead {
         })
     }
 
-    fn read_arbitrary_header(&mut self) -> ImageResult<ArbitraryHeader> {
-        match self.bytes().next() {
-            None => return Err(ImageError::FormatError("Input too short".to_string())),
-            Some(Err(io)) => return Err(ImageError::IoError(io)),
-            Some(Ok(b'\n')) => (),
-            _ => {
-                return Err(ImageError::FormatError(
-                    "Expected newline after P7".to_string(),
-                ))
-            }
+    fn read_floatmap_header(
+        &mut self,
+        color: FloatmapColor,
+        context: &mut HeaderContext,
+    ) -> ImageResult<FloatmapHeader> {
+        let width = self.read_next_u32(context)?;
+        let height = self.read_next_u32(context)?;
+        let scale = self.read_next_string(context)?
+            .parse::<f32>()
+            .map_err(|_| context.error("Invalid scale in preamble"))?;
+        check_dimensions(width, height)?;
+        if scale == 0.0 || !scale.is_finite() {
+            return Err(context.error("Float map scale must be finite and non-zero"));
+        }
+        Ok(FloatmapHeader {
+            color,
+            width,
+            height,
+            scale,
+        })
+    }
+
+    /// Reads the header of a pam, skipping unknown lines if `lenient` is set
+    fn read_arbitrary_header(
+        &mut self,
+        lenient: bool,
+        context: &mut HeaderContext,
+    ) -> ImageResult<ArbitraryHeader> {
+        // Lines may end with `\r\n`, the remaining lines are trimmed after reading them.
+        let first = self.read_next_line(context)?;
+        context.raw.extend_from_slice(&first);
+        if first.is_empty() {
+            return Err(ImageError::FormatError("Input too short".to_string()));
+        }
+        if first != b"\n" && first != b"\r\n" {
+            return Err(context.error("Expected newline after P7"));
         }
 
-        let mut line = String::new();
         let mut height: Option<u32> = None;
         let mut width: Option<u32> = None;
         let mut depth: Option<u32> = None;
         let mut maxval: Option<u32> = None;
         let mut tupltype: Option<String> = None;
         loop {
-            line.truncate(0);
-            self.read_line(&mut line).map_err(ImageError::IoError)?;
-            if line.as_bytes()[0] == b'#' {
+            let line = self.read_next_line(context)?;
+            context.next_line(&line);
+            context.check_input()?;
+            if line.is_empty() {
+                return Err(context.line_error("Unexpected eof in pam header"));
+            }
+            if context.line > MAX_PAM_HEADER_LINES {
+                return Err(context.line_error("Too many lines in pam header"));
+            }
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            if line[0] == b'#' {
+                push_comment(&mut context.comments, line[1..].to_vec());
                 continue;
             }
             if !line.is_ascii() {
-                return Err(ImageError::FormatError(
-                    "Only ascii characters allowed in pam header".to_string(),
-                ));
+                return Err(context.line_error("Only ascii characters allowed in pam header"));
             }
-            let (identifier, rest) = line.trim_left()
-                .split_at(line.find(char::is_whitespace).unwrap_or_else(|| line.len()));
+            let line = String::from_utf8_lossy(&line);
+            let trimmed = line.trim_left();
+            let (identifier, rest) =
+                trimmed.split_at(trimmed.find(char::is_whitespace).unwrap_or(trimmed.len()));
             match identifier {
                 "ENDHDR" => break,
                 "HEIGHT" => if height.is_some() {
-                    return Err(ImageError::FormatError("Duplicate HEIGHT line".to_string()));
+                    return Err(context.line_error("Duplicate HEIGHT line"));
                 } else {
                     let h = rest.trim()
                         .parse::<u32>()
-                        .map_err(|_| ImageError::FormatError("Invalid height".to_string()))?;
+                        .map_err(|_| context.line_error("Invalid height"))?;
                     height = Some(h);
                 },
                 "WIDTH" => if width.is_some() {
-                    return Err(ImageError::FormatError("Duplicate WIDTH line".to_string()));
+                    return Err(context.line_error("Duplicate WIDTH line"));
                 } else {
                     let w = rest.trim()
                         .parse::<u32>()
-                        .map_err(|_| ImageError::FormatError("Invalid width".to_string()))?;
+                        .map_err(|_| context.line_error("Invalid width"))?;
                     width = Some(w);
                 },
                 "DEPTH" => if depth.is_some() {
-                    return Err(ImageError::FormatError("Duplicate DEPTH line".to_string()));
+                    return Err(context.line_error("Duplicate DEPTH line"));
                 } else {
                     let d = rest.trim()
                         .parse::<u32>()
-                        .map_err(|_| ImageError::FormatError("Invalid depth".to_string()))?;
+                        .map_err(|_| context.line_error("Invalid depth"))?;
                     depth = Some(d);
                 },
                 "MAXVAL" => if maxval.is_some() {
-                    return Err(ImageError::FormatError("Duplicate MAXVAL line".to_string()));
+                    return Err(context.line_error("Duplicate MAXVAL line"));
                 } else {
                     let m = rest.trim()
                         .parse::<u32>()
-                        .map_err(|_| ImageError::FormatError("Invalid maxval".to_string()))?;
+                        .map_err(|_| context.line_error("Invalid maxval"))?;
+                    if m == 0 || m > 0xFFFF {
+                        return Err(context.line_error("maxval must be between 1 and 65535"));
+                    }
                     maxval = Some(m);
                 },
                 "TUPLTYPE" => {
@@ -325,47 +1123,26 @@ trait HeaderReader: BufRead {
                     } else {
                         tupltype = Some(identifier.to_string());
                     }
+                    if tupltype.as_ref().map_or(0, String::len) > MAX_TUPLTYPE_LEN {
+                        return Err(context.line_error("TUPLTYPE too long"));
+                    }
                 }
-                _ => return Err(ImageError::FormatError("Unknown header line".to_string())),
+                _ if lenient => continue,
+                _ => return Err(context.line_error("Unknown header line")),
             }
         }
 
         let (h, w, d, m) = match (height, width, depth, maxval) {
-            (None, _, _, _) => {
-                return Err(ImageError::FormatError(
-                    "Expected one HEIGHT line".to_string(),
-                ))
-            }
-            (_, None, _, _) => {
-                return Err(ImageError::FormatError(
-                    "Expected one WIDTH line".to_string(),
-                ))
-            }
-            (_, _, None, _) => {
-                return Err(ImageError::FormatError(
-                    "Expected one DEPTH line".to_string(),
-                ))
-            }
-            (_, _, _, None) => {
-                return Err(ImageError::FormatError(
-                    "Expected one MAXVAL line".to_string(),
-                ))
-            }
+            (None, _, _, _) => return Err(context.line_error("Expected one HEIGHT line")),
+            (_, None, _, _) => return Err(context.line_error("Expected one WIDTH line")),
+            (_, _, None, _) => return Err(context.line_error("Expected one DEPTH line")),
+            (_, _, _, None) => return Err(context.line_error("Expected one MAXVAL line")),
             (Some(h), Some(w), Some(d), Some(m)) => (h, w, d, m),
         };
+        check_dimensions(w, h)?;
 
-        let tupltype = match tupltype {
-            None => None,
-            Some(ref t) if t == "BLACKANDWHITE" => Some(ArbitraryTuplType::BlackAndWhite),
-            Some(ref t) if t == "BLACKANDWHITE_ALPHA" => {
-                Some(ArbitraryTuplType::BlackAndWhiteAlpha)
-            }
-            Some(ref t) if t == "GRAYSCALE" => Some(ArbitraryTuplType::Grayscale),
-            Some(ref t) if t == "GRAYSCALE_ALPHA" => Some(ArbitraryTuplType::GrayscaleAlpha),
-            Some(ref t) if t == "RGB" => Some(ArbitraryTuplType::RGB),
-            Some(ref t) if t == "RGB_ALPHA" => Some(ArbitraryTuplType::RGBAlpha),
-            Some(other) => Some(ArbitraryTuplType::Custom(other)),
-        };
+        // Parsing a tuple type can not fail, unknown ones are kept as custom.
+        let tupltype = tupltype.map(|t| t.parse().unwrap());
 
         Ok(ArbitraryHeader {
             height: h,
@@ -377,628 +1154,5053 @@ trait HeaderReader: BufRead {
     }
 }
 
-impl<R: Read> HeaderReader for BufReader<R> {}
+impl<R: BufRead> HeaderReader for R {}
 
-impl<R: Read> ImageDecoder for PNMDecoder<R> {
-    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
-        Ok((self.header.width(), self.header.height()))
+/// State of the header parser, used to point at the location of errors.
+///
+/// Syntax errors in the header report the byte offset of the offending token or line, counted
+/// from the start of the file. Pam headers additionally report the line number.
+struct HeaderContext {
+    /// All bytes consumed so far, starting with the magic constant
+    raw: Vec<u8>,
+    /// Offset of the start of the last token or line
+    token: usize,
+    /// Number of the line last read, only tracked for pam headers
+    line: usize,
+    comments: Vec<String>,
+    /// Maximal length of a line, comment or token
+    max_line: usize,
+    /// Maximal length of the header, see `Limits::max_input`
+    max_input: Option<u64>,
+}
+
+impl HeaderContext {
+    /// Start right after the two bytes of the magic constant.
+    fn new(magic: [u8; 2], max_line: usize, max_input: Option<u64>) -> Self {
+        HeaderContext {
+            raw: magic.to_vec(),
+            token: 2,
+            line: 1,
+            comments: Vec::new(),
+            max_line,
+            max_input,
+        }
     }
 
-    fn colortype(&mut self) -> ImageResult<ColorType> {
-        Ok(self.tuple.color())
+    /// Reject a header longer than the limit on the input.
+    fn check_input(&self) -> ImageResult<()> {
+        match self.max_input {
+            Some(max_input) if self.raw.len() as u64 > max_input => Err(ImageError::LimitError),
+            _ => Ok(()),
+        }
     }
 
-    fn row_len(&mut self) -> ImageResult<usize> {
-        self.rowlen()
+    /// Account for a line read by the pam header parser.
+    fn next_line(&mut self, line: &[u8]) {
+        self.token = self.raw.len();
+        self.raw.extend_from_slice(line);
+        self.line += 1;
     }
 
-    fn read_scanline(&mut self, _buf: &mut [u8]) -> ImageResult<u32> {
-        unimplemented!();
+    fn error(&self, message: &str) -> ImageError {
+        ImageError::FormatError(format!("{} at byte {}", message, self.token))
     }
 
-    fn read_image(&mut self) -> ImageResult<DecodingResult> {
-        self.read()
+    fn line_error(&self, message: &str) -> ImageError {
+        ImageError::FormatError(format!(
+            "{} on line {} at byte {}",
+            message, self.line, self.token
+        ))
     }
 }
 
-impl<R: Read> PNMDecoder<R> {
-    fn rowlen(&self) -> ImageResult<usize> {
-        match self.tuple {
-            TupleType::PbmBit => PbmBit::bytelen(self.header.width(), 1, 1),
-            TupleType::BWBit => BWBit::bytelen(self.header.width(), 1, 1),
-            TupleType::RGBU8 => U8::bytelen(self.header.width(), 1, 3),
-            TupleType::RGBU16 => U16::bytelen(self.header.width(), 1, 3),
-            TupleType::GrayU8 => U8::bytelen(self.header.width(), 1, 1),
-            TupleType::GrayU16 => U16::bytelen(self.header.width(), 1, 1),
-        }
+/// Store the text of a comment without the leading `#` and surrounding whitespace.
+fn push_comment(comments: &mut Vec<String>, text: Vec<u8>) {
+    let text = String::from_utf8_lossy(&text);
+    comments.push(text.trim().to_string());
+}
+
+fn check_dimensions(width: u32, height: u32) -> ImageResult<()> {
+    if width == 0 || height == 0 {
+        return Err(ImageError::DimensionError);
     }
+    Ok(())
+}
 
-    fn read(&mut self) -> ImageResult<DecodingResult> {
-        match self.tuple {
-            TupleType::PbmBit => self.read_samples::<PbmBit>(1),
-            TupleType::BWBit => self.read_samples::<BWBit>(1),
-            TupleType::RGBU8 => self.read_samples::<U8>(3),
-            TupleType::RGBU16 => self.read_samples::<U16>(3),
-            TupleType::GrayU8 => self.read_samples::<U8>(1),
-            TupleType::GrayU16 => self.read_samples::<U16>(1),
+impl<'a> PNMDecoder<&'a [u8], &'a [u8]> {
+    /// Decode an in-memory image, borrowing the raster if it needs no conversion.
+    ///
+    /// The samples are laid out like by `read_image_into`. Binary rasters of 8-bit samples,
+    /// except packed pbm bits, are returned as a slice of `data` without copying. All other images
+    /// are decoded into an owned buffer. Data after the raster is ignored.
+    pub fn decode_slice(data: &'a [u8]) -> ImageResult<(PNMHeader, Cow<'a, [u8]>)> {
+        let mut decoder = PNMDecoder::from_buf_read(data)?;
+        let borrowed = match (decoder.tuple, decoder.subtype().sample_encoding()) {
+            (TupleType::PbmBit, _) | (_, SampleEncoding::Ascii) => false,
+            (tuple, SampleEncoding::Binary) => tuple.sample_bytes() == 1,
+        };
+        if !borrowed {
+            let mut buf = vec![0; decoder.total_bytes()?];
+            decoder.read_image_into(&mut buf)?;
+            return Ok((decoder.header, Cow::Owned(buf)));
         }
-    }
 
-    fn read_samples<S: Sample>(&mut self, components: u32) -> ImageResult<DecodingResult>
-    where
-        Vec<S::T>: Into<DecodingResult>,
-    {
-        match self.subtype().sample_encoding() {
-            SampleEncoding::Binary => {
-                let width = self.header.width();
-                let height = self.header.height();
-                let bytecount = S::bytelen(width, height, components)?;
-                let mut bytes = vec![0 as u8; bytecount];
-                (&mut self.reader)
-                    .read_exact(&mut bytes)
-                    .map_err(|_| ImageError::NotEnoughData)?;
-                let samples = S::from_bytes(&bytes, width, height, components)?;
-                Ok(samples.into())
-            }
-            SampleEncoding::Ascii => {
-                let samples = self.read_ascii::<S>(components)?;
-                Ok(samples.into())
+        let offset = decoder.header.encoded().map_or(0, |encoded| encoded.len());
+        let raster = &data[offset..];
+        let expected = decoder.total_bytes()?;
+        if raster.len() < expected {
+            return Err(PnmError::Truncated {
+                expected,
+                got: raster.len(),
+            }.into());
+        }
+        let raster = &raster[..expected];
+        if let TupleType::BWBit | TupleType::BWAlphaBit = decoder.tuple {
+            if let Some(&val) = raster.iter().find(|&&val| val > 1) {
+                return Err(out_of_range(u32::from(val), 1));
             }
         }
+        Ok((decoder.header, Cow::Borrowed(raster)))
     }
+}
 
-    fn read_ascii<Basic: Sample>(&mut self, components: u32) -> ImageResult<Vec<Basic::T>> {
-        let mut buffer = Vec::new();
-        for _ in 0..(self.header.width() * self.header.height() * components) {
-            let value = self.read_ascii_sample()?;
-            let sample = Basic::from_unsigned(value)?;
-            buffer.push(sample);
+impl<R, B: BufRead + Seek> PNMDecoder<R, B> {
+    /// Decode the rectangle of `width` times `height` pixels whose top left corner is `(x, y)`.
+    ///
+    /// The rows of the rectangle are written to `buf` back to back in the layout described for
+    /// `read_image_into`, so it must be exactly as long as their decoded bytes. For binary
+    /// subtypes only the bytes of the rectangle are read, seeking over all others. Ascii images
+    /// are decoded in full and then cropped, which requires that no rows have been read before.
+    /// Afterwards all rows count as read.
+    pub fn read_rect(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buf: &mut [u8],
+    ) -> ImageResult<()> {
+        let (image_width, image_height) = (self.header.width(), self.header.height());
+        let inside = |start: u32, len: u32, max: u32| {
+            start.checked_add(len).map_or(false, |end| end <= max)
+        };
+        if !inside(x, width, image_width) || !inside(y, height, image_height) {
+            return Err(ImageError::DimensionError);
+        }
+        let pixel_len = self.rowlen()? / image_width as usize;
+        let rect_len = sample_count(width, height, pixel_len as u32)?;
+        if buf.len() != rect_len {
+            return Err(ImageError::DimensionError);
         }
-        Ok(buffer)
-    }
 
-    fn read_ascii_sample(&mut self) -> ImageResult<u32> {
-        let istoken = |v: &Result<u8, _>| match *v {
-            Err(_) => false,
-            Ok(b'\t') | Ok(b'\n') | Ok(b'\x0b') | Ok(b'\x0c') | Ok(b'\r') | Ok(b' ') => false,
-            _ => true,
+        let result = match self.subtype().sample_encoding() {
+            SampleEncoding::Binary => self.read_raw_rect(x, y, width, height)?,
+            SampleEncoding::Ascii => {
+                if self.rows_read != 0 {
+                    return Err(ImageError::FormatError(
+                        "Ascii images can only be cropped before reading any rows".to_string(),
+                    ));
+                }
+                let result = self.read_raw_rows(image_height)?;
+                let result = match result {
+                    DecodingResult::F32(mut samples) => {
+                        let rowlen = samples.len() / image_height as usize;
+                        flip_rows(&mut samples, rowlen);
+                        DecodingResult::F32(samples)
+                    }
+                    result => result,
+                };
+                let components = u32::from(self.tuple.channels());
+                crop_samples(result, components, image_width, (x, y), (width, height))
+            }
         };
-        let token = (&mut self.reader)
-            .bytes()
-            .skip_while(|v| !istoken(v))
-            .take_while(&istoken)
-            .collect::<Result<Vec<u8>, _>>()?;
-        if !token.is_ascii() {
+        self.rows_read = image_height;
+        self.check_samples(&result)?;
+        write_samples(self.convert(self.scale(result)), buf);
+        Ok(())
+    }
+
+    /// Decode every `factor`-th pixel of every `factor`-th row, starting with the top left one.
+    ///
+    /// Returns the dimensions of the smaller image, those of the image divided by `factor` and
+    /// rounded up, along with its samples as `read_image` would decode them. Only the sampled rows
+    /// are read, seeking over all others, so this only applies to binary subtypes. Ascii images
+    /// are rejected with `ImageError::UnsupportedError`. This requires that no rows have been read
+    /// before, afterwards all rows count as read.
+    pub fn read_image_subsampled(
+        &mut self,
+        factor: u32,
+    ) -> ImageResult<((u32, u32), DecodingResult)> {
+        if factor == 0 {
+            return Err(ImageError::DimensionError);
+        }
+        if self.subtype().sample_encoding() == SampleEncoding::Ascii {
+            return Err(ImageError::UnsupportedError(
+                "Only binary images can be subsampled".to_string(),
+            ));
+        }
+        if self.rows_read != 0 {
             return Err(ImageError::FormatError(
-                "Non ascii character where sample value was expected".to_string(),
+                "Images can only be subsampled before reading any rows".to_string(),
             ));
         }
-        let string = String::from_utf8(token)
-            .map_err(|_| ImageError::FormatError("Error parsing sample".to_string()))?;
-        string
-            .parse::<u32>()
-            .map_err(|_| ImageError::FormatError("Error parsing sample value".to_string()))
+
+        let result = match self.tuple {
+            TupleType::PbmBit => self.read_subsampled_samples::<PbmBit>(factor),
+            TupleType::BWBit | TupleType::BWAlphaBit => {
+                self.read_subsampled_samples::<BWBit>(factor)
+            }
+            #[cfg(feature = "pnm_u16")]
+            TupleType::GrayU16
+            | TupleType::GrayAU16
+            | TupleType::RGBU16
+            | TupleType::RGBAU16
+            | TupleType::CustomU16(_) => match self.sample_endianness() {
+                Endianness::Little => self.read_subsampled_samples::<U16<LittleEndian>>(factor),
+                Endianness::Big => self.read_subsampled_samples::<U16<BigEndian>>(factor),
+            },
+            #[cfg(not(feature = "pnm_u16"))]
+            TupleType::GrayU16
+            | TupleType::GrayAU16
+            | TupleType::RGBU16
+            | TupleType::RGBAU16
+            | TupleType::CustomU16(_) => Err(u16_unsupported()),
+            TupleType::GrayF32 | TupleType::RGBF32 => match self.sample_endianness() {
+                Endianness::Little => self.read_subsampled_samples::<F32<LittleEndian>>(factor),
+                Endianness::Big => self.read_subsampled_samples::<F32<BigEndian>>(factor),
+            },
+            _ => self.read_subsampled_samples::<U8>(factor),
+        }?;
+        self.rows_read = self.header.height();
+        self.check_samples(&result)?;
+
+        // The header rejects zero dimensions, so every subsampled image keeps at least one pixel.
+        let subsampled = |len: u32| (len - 1) / factor + 1;
+        let dimensions = (subsampled(self.header.width()), subsampled(self.header.height()));
+        Ok((dimensions, self.convert(self.scale(result))))
     }
 
-    /// Get the pnm subtype, depending on the magic constant contained in the header
-    pub fn subtype(&self) -> PNMSubtype {
-        self.header.subtype()
+    /// Seek past the rows not read yet and extract reader and header, see `skip_image`.
+    ///
+    /// Binary rasters are not read at all, so a truncated raster is not detected. Ascii rasters
+    /// are read like by `skip_image`.
+    pub fn seek_past_image(mut self) -> ImageResult<(B, PNMHeader)> {
+        let remaining = self.header.height() - self.rows_read;
+        if self.raster_len()?.is_some() {
+            let len = (self.stored_rowlen()? as u64)
+                .checked_mul(u64::from(remaining))
+                .filter(|&len| len <= i64::max_value() as u64)
+                .ok_or_else(dimension_overflow)?;
+            self.reader.seek(SeekFrom::Current(len as i64))?;
+            self.rows_read = self.header.height();
+        }
+        self.skip_image()
     }
-}
 
-impl TupleType {
-    fn color(self) -> ColorType {
-        use self::TupleType::*;
-        match self {
-            PbmBit => ColorType::Gray(1),
-            BWBit => ColorType::Gray(1),
-            GrayU8 => ColorType::Gray(8),
-            GrayU16 => ColorType::Gray(16),
-            RGBU8 => ColorType::RGB(8),
-            RGBU16 => ColorType::GrayA(16),
+    fn read_raw_rect(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> ImageResult<DecodingResult> {
+        let rect = (x, y, width, height);
+        match self.tuple {
+            TupleType::PbmBit => self.read_rect_samples::<PbmBit>(rect),
+            TupleType::BWBit | TupleType::BWAlphaBit => self.read_rect_samples::<BWBit>(rect),
+            #[cfg(feature = "pnm_u16")]
+            TupleType::GrayU16
+            | TupleType::GrayAU16
+            | TupleType::RGBU16
+            | TupleType::RGBAU16
+            | TupleType::CustomU16(_) => match self.sample_endianness() {
+                Endianness::Little => self.read_rect_samples::<U16<LittleEndian>>(rect),
+                Endianness::Big => self.read_rect_samples::<U16<BigEndian>>(rect),
+            },
+            #[cfg(not(feature = "pnm_u16"))]
+            TupleType::GrayU16
+            | TupleType::GrayAU16
+            | TupleType::RGBU16
+            | TupleType::RGBAU16
+            | TupleType::CustomU16(_) => Err(u16_unsupported()),
+            TupleType::GrayF32 | TupleType::RGBF32 => {
+                match self.sample_endianness() {
+                    Endianness::Little => self.read_rect_samples::<F32<LittleEndian>>(rect),
+                    Endianness::Big => self.read_rect_samples::<F32<BigEndian>>(rect),
+                }
+            }
+            _ => self.read_rect_samples::<U8>(rect),
         }
     }
-}
 
-impl Sample for U8 {
-    type T = u8;
+    /// Seek to and read the bytes of each row of a rectangle of a binary raster.
+    ///
+    /// Packed bits do not start at byte boundaries, so bitmap rows are read in full and cropped.
+    fn read_rect_samples<S: Sample>(
+        &mut self,
+        (x, y, width, height): (u32, u32, u32, u32),
+    ) -> ImageResult<DecodingResult>
+    where
+        Vec<S::T>: Into<DecodingResult>,
+    {
+        let (image_width, image_height) = (self.header.width(), self.header.height());
+        let components = u32::from(self.tuple.channels());
+        let stored_rowlen = self.stored_rowlen()? as u64;
+        let position = self.reader.seek(SeekFrom::Current(0))?;
+        let start = position - u64::from(self.rows_read) * stored_rowlen;
 
-    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        Ok((width * height * samples) as usize)
-    }
+        let (first, span) = match self.tuple {
+            TupleType::PbmBit => (0, image_width),
+            _ => (x, width),
+        };
+        let offset = S::bytelen(first, 1, components)? as u64;
+        let span_len = S::bytelen(span, 1, components)?;
+        let bytecount = span_len
+            .checked_mul(height as usize)
+            .ok_or_else(dimension_overflow)?;
+        let mut bytes = vec![0; bytecount];
+        for (row, chunk) in (y..y + height).zip(bytes.chunks_mut(span_len)) {
+            // Float maps are stored from bottom to top.
+            let stored_row = match self.tuple {
+                TupleType::GrayF32 | TupleType::RGBF32 => image_height - row - 1,
+                _ => row,
+            };
+            let row_start = start + u64::from(stored_row) * stored_rowlen;
+            self.reader.seek(SeekFrom::Start(row_start + offset))?;
+            read_raster(&mut self.reader, chunk)?;
+        }
+        self.reader
+            .seek(SeekFrom::Start(start + u64::from(image_height) * stored_rowlen))?;
 
-    fn from_bytes(
-        bytes: &[u8],
-        _width: u32,
-        _height: u32,
-        _samples: u32,
-    ) -> ImageResult<Vec<Self::T>> {
-        let mut buffer = Vec::new();
-        buffer.resize(bytes.len(), 0 as u8);
-        buffer.copy_from_slice(bytes);
-        Ok(buffer)
+        let samples = S::from_bytes(&bytes, span, height, components)?.into();
+        Ok(match self.tuple {
+            TupleType::PbmBit => crop_samples(samples, 1, image_width, (x, 0), (width, height)),
+            _ => samples,
+        })
     }
 
-    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
-        if val > u32::from(u8::max_value()) {
-            Err(ImageError::FormatError(
-                "Sample value outside of bounds".to_string(),
-            ))
-        } else {
-            Ok(val as u8)
+    /// Seek to and read each `factor`-th row of a binary raster, keeping each `factor`-th pixel.
+    ///
+    /// Pixels do not start at byte boundaries in bitmaps, so sampled rows are read in full.
+    fn read_subsampled_samples<S: Sample>(&mut self, factor: u32) -> ImageResult<DecodingResult>
+    where
+        S::T: Clone,
+        Vec<S::T>: Into<DecodingResult>,
+    {
+        let (image_width, image_height) = (self.header.width(), self.header.height());
+        let components = u32::from(self.tuple.channels());
+        let stored_rowlen = self.stored_rowlen()?;
+        let start = self.reader.seek(SeekFrom::Current(0))?;
+
+        let mut row = vec![0; stored_rowlen];
+        let mut samples = Vec::new();
+        for y in (0..image_height).step_by(factor as usize) {
+            // Float maps are stored from bottom to top.
+            let stored_row = match self.tuple {
+                TupleType::GrayF32 | TupleType::RGBF32 => image_height - y - 1,
+                _ => y,
+            };
+            let row_start = start + u64::from(stored_row) * stored_rowlen as u64;
+            self.reader.seek(SeekFrom::Start(row_start))?;
+            read_raster(&mut self.reader, &mut row)?;
+            let pixels = S::from_bytes(&row, image_width, 1, components)?;
+            for pixel in pixels.chunks(components as usize).step_by(factor as usize) {
+                samples.extend_from_slice(pixel);
+            }
         }
+        self.reader
+            .seek(SeekFrom::Start(start + u64::from(image_height) * stored_rowlen as u64))?;
+        Ok(samples.into())
     }
 }
 
-impl Sample for U16 {
-    type T = u16;
+impl<R, B: BufRead> ImageDecoder for PNMDecoder<R, B> {
+    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        Ok((self.header.width(), self.header.height()))
+    }
 
-    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        Ok((width * height * samples * 2) as usize)
+    fn colortype(&mut self) -> ImageResult<ColorType> {
+        self.output().color()
     }
 
-    fn from_bytes(
-        bytes: &[u8],
-        width: u32,
-        height: u32,
-        samples: u32,
-    ) -> ImageResult<Vec<Self::T>> {
-        let mut buffer = Vec::new();
-        buffer.resize((width * height * samples) as usize, 0 as u16);
-        BigEndian::read_u16_into(bytes, &mut buffer);
-        Ok(buffer)
+    fn row_len(&mut self) -> ImageResult<usize> {
+        self.rowlen()
     }
 
-    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
-        if val > u32::from(u16::max_value()) {
-            Err(ImageError::FormatError(
-                "Sample value outside of bounds".to_string(),
-            ))
-        } else {
-            Ok(val as u16)
+    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
+        if self.rows_read >= self.header.height() {
+            return Err(ImageError::ImageEnd);
         }
-    }
-}
 
-// The image is encoded in rows of bits, high order bits first. Any bits beyond the row bits should
-// be ignored. Also, contrary to rgb, black pixels are encoded as a 1 while white is 0. This will
-// need to be reversed for the grayscale output.
-impl Sample for PbmBit {
-    type T = u8;
+        let rowlen = self.rowlen()?;
+        if self.read_row_into(&mut buf[..rowlen])? {
+            self.rows_read += 1;
+            return Ok(rowlen as u32);
+        }
 
-    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        let count = width * samples;
-        let linelen = (count / 8) + ((count % 8) != 0) as u32;
-        Ok((linelen * height) as usize)
+        let row = self.read_rows(1)?;
+        self.rows_read += 1;
+        Ok(write_samples(row, buf) as u32)
     }
 
-    fn from_bytes(
-        bytes: &[u8],
-        width: u32,
-        height: u32,
-        samples: u32,
-    ) -> ImageResult<Vec<Self::T>> {
-        let mut buffer = Vec::new();
-        let linecount = width * samples;
-        let linebytelen = (linecount / 8) + ((linecount % 8) != 0) as u32;
-        buffer.resize((width * height * samples) as usize, 0 as u8);
-        for (line, linebuffer) in bytes.chunks(linebytelen as usize).enumerate() {
-            let outbase = line * linecount as usize;
-            for samplei in 0..linecount {
-                let byteindex = (samplei / 8) as usize;
-                let inindex = 7 - samplei % 8;
-                let indicator = (linebuffer[byteindex] >> inindex) & 0x01;
-                buffer[outbase + samplei as usize] = if indicator == 0 { 1 } else { 0 };
-            }
-        }
-        Ok(buffer)
+    fn read_image(&mut self) -> ImageResult<DecodingResult> {
+        self.read()
     }
+}
 
-    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
-        match val {
-            // 0 is white in pbm
-            0 => Ok(1 as u8),
-            // 1 is black in pbm
-            1 => Ok(0 as u8),
-            _ => Err(ImageError::FormatError(
-                "Sample value outside of bounds".to_string(),
-            )),
+/// Iterator over the rows of a pnm image, created by `PNMDecoder::rows`.
+pub struct PnmRows<R, B = BufReader<R>> {
+    decoder: PNMDecoder<R, B>,
+    failed: bool,
+}
+
+impl<R, B: BufRead> Iterator for PnmRows<R, B> {
+    type Item = ImageResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.decoder.rows_read >= self.decoder.header.height() {
+            return None;
         }
+
+        let row = self.decoder.rowlen().and_then(|rowlen| {
+            let mut row = vec![0; rowlen];
+            self.decoder.read_scanline(&mut row)?;
+            Ok(row)
+        });
+        self.failed = row.is_err();
+        Some(row)
     }
 }
 
-// Encoded just like a normal U8 but we check the values.
-impl Sample for BWBit {
-    type T = u8;
+/// Iterator over the pixels of a decoded image, yielding `(x, y, channels)` in row-major order.
+///
+/// Works on the samples of a `DecodingResult::U8` or `DecodingResult::U16` together with the
+/// dimensions and color type reported by the decoder.
+pub struct PnmPixels<'a, T: 'a> {
+    chunks: ::std::slice::Chunks<'a, T>,
+    width: u32,
+    index: u32,
+}
 
-    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        U8::bytelen(width, height, samples)
+impl<'a, T: 'a> PnmPixels<'a, T> {
+    /// Create an iterator over `samples`, which must hold exactly one sample per channel of each
+    /// pixel.
+    pub fn new(samples: &'a [T], dimensions: (u32, u32), color: ColorType) -> ImageResult<Self> {
+        let (width, height) = dimensions;
+        let channels = num_components(color);
+        let expected = sample_count(width, height, channels as u32)?;
+        if samples.len() != expected {
+            return Err(ImageError::DimensionError);
+        }
+
+        Ok(PnmPixels {
+            chunks: samples.chunks(channels),
+            width,
+            index: 0,
+        })
     }
+}
 
-    fn from_bytes(
-        bytes: &[u8],
-        width: u32,
-        height: u32,
-        samples: u32,
-    ) -> ImageResult<Vec<Self::T>> {
-        let values = U8::from_bytes(bytes, width, height, samples)?;
-        if values.iter().any(|&val| val > 1) {
-            return Err(ImageError::FormatError(
-                "Sample value outside of bounds".to_string(),
-            ));
-        };
-        Ok(values)
+impl<'a, T: 'a> Iterator for PnmPixels<'a, T> {
+    type Item = (u32, u32, &'a [T]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pixel = self.chunks.next()?;
+        let (x, y) = (self.index % self.width, self.index / self.width);
+        self.index += 1;
+        Some((x, y, pixel))
     }
 
-    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
-        match val {
-            0 => Ok(0 as u8),
-            1 => Ok(1 as u8),
-            _ => Err(ImageError::FormatError(
-                "Sample value outside of bounds".to_string(),
-            )),
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
     }
 }
 
-impl Into<DecodingResult> for Vec<u8> {
-    fn into(self) -> DecodingResult {
-        DecodingResult::U8(self)
+impl<R, B: BufRead> PNMDecoder<R, B> {
+    /// The length of a decoded row. Bits are expanded to a full byte each and 16-bit samples are
+    /// written in native endianess.
+    fn rowlen(&self) -> ImageResult<usize> {
+        let output = self.output();
+        sample_count(self.header.width(), 1, u32::from(output.channels()))?
+            .checked_mul(usize::from(output.sample_bytes()))
+            .ok_or_else(dimension_overflow)
     }
-}
 
-impl Into<DecodingResult> for Vec<u16> {
-    fn into(self) -> DecodingResult {
-        DecodingResult::U16(self)
+    /// The number of bytes `read_image_into` writes, in the layout described for `row_len`.
+    ///
+    /// This is the product of width, height, `channel_count` and `bytes_per_sample`, an error is
+    /// returned if it overflows.
+    pub fn total_bytes(&self) -> ImageResult<usize> {
+        self.rowlen()?
+            .checked_mul(self.header.height() as usize)
+            .ok_or_else(dimension_overflow)
     }
-}
 
-impl DecodableImageHeader for BitmapHeader {
-    fn tuple_type(&self) -> ImageResult<TupleType> {
-        Ok(TupleType::PbmBit)
+    /// Decode the whole image into a caller provided buffer.
+    ///
+    /// The buffer must be exactly `total_bytes` long. 16-bit and float samples are written as
+    /// native endian bytes, bits are expanded to a full byte each.
+    pub fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        if buf.len() != self.total_bytes()? {
+            return Err(ImageError::DimensionError);
+        }
+        let result = self.read()?;
+        write_samples(result, buf);
+        Ok(())
     }
-}
 
-impl DecodableImageHeader for GraymapHeader {
-    fn tuple_type(&self) -> ImageResult<TupleType> {
-        match self.maxwhite {
-            v if v <= 0xFF => Ok(TupleType::GrayU8),
-            v if v <= 0xFFFF => Ok(TupleType::GrayU16),
-            _ => Err(ImageError::FormatError(
-                "Image maxval is not less or equal to 65535".to_string(),
-            )),
+    /// The smallest multiple of `alignment` that holds the decoded bytes of a row.
+    ///
+    /// Use this as the `stride` of `read_image_into_strided` for buffers whose rows must start at
+    /// aligned offsets, such as textures. `row_len` is still the logical length of each row.
+    pub fn aligned_stride(&self, alignment: usize) -> ImageResult<usize> {
+        if alignment == 0 {
+            return Err(ImageError::DimensionError);
+        }
+        let rowlen = self.rowlen()?;
+        match rowlen % alignment {
+            0 => Ok(rowlen),
+            rem => rowlen
+                .checked_add(alignment - rem)
+                .ok_or_else(dimension_overflow),
         }
     }
-}
 
-impl DecodableImageHeader for PixmapHeader {
-    fn tuple_type(&self) -> ImageResult<TupleType> {
-        match self.maxval {
-            v if v <= 0xFF => Ok(TupleType::RGBU8),
-            v if v <= 0xFFFF => Ok(TupleType::RGBU16),
-            _ => Err(ImageError::FormatError(
-                "Image maxval is not less or equal to 65535".to_string(),
-            )),
+    /// Decode the whole image into a caller provided buffer whose rows start `stride` bytes apart.
+    ///
+    /// Each row is laid out as for `read_image_into` and followed by zeroed padding up to the
+    /// start of the next row. `stride` must be at least `row_len` and the buffer exactly `stride`
+    /// times the height long, see `aligned_stride`.
+    pub fn read_image_into_strided(&mut self, buf: &mut [u8], stride: usize) -> ImageResult<()> {
+        let rowlen = self.rowlen()?;
+        let height = self.header.height() as usize;
+        let len = stride.checked_mul(height).ok_or_else(dimension_overflow)?;
+        if stride < rowlen || buf.len() != len {
+            return Err(ImageError::DimensionError);
+        }
+        let result = self.read()?;
+        write_samples(result, buf);
+        // Spread the rows from the back so none is overwritten before it is moved.
+        for row in (0..height).rev() {
+            buf.copy_within(row * rowlen..(row + 1) * rowlen, row * stride);
+            for byte in &mut buf[row * stride + rowlen..(row + 1) * stride] {
+                *byte = 0;
+            }
         }
+        Ok(())
     }
-}
 
-impl DecodableImageHeader for ArbitraryHeader {
-    fn tuple_type(&self) -> ImageResult<TupleType> {
-        match self.tupltype {
-            None if self.depth == 1 => Ok(TupleType::GrayU8),
-            None if self.depth == 2 => Err(ImageError::UnsupportedColor(ColorType::GrayA(8))),
-            None if self.depth == 3 => Ok(TupleType::RGBU8),
-            None if self.depth == 4 => Err(ImageError::UnsupportedColor(ColorType::RGBA(8))),
+    /// Decode the whole image into 8-bit RGBA pixels regardless of its color type.
+    ///
+    /// Gray samples are replicated into the color channels and the alpha channel is 255 for
+    /// images without one. Bitmaps become black and white, 16-bit samples are rounded to 8 bits
+    /// unless `PnmDecoderOptions::downscale` selects another mapping, and
+    /// `PnmDecoderOptions::upscale` is ignored. Float maps and custom pam
+    /// tuple types are rejected with `ImageError::UnsupportedColor` and
+    /// `ImageError::UnsupportedError` respectively.
+    pub fn read_image_rgba8(&mut self) -> ImageResult<Vec<u8>> {
+        let (downscale, upscale) = (self.options.downscale, self.options.upscale);
+        self.options.downscale = downscale.or(Some(Downscale::Round));
+        self.options.upscale = false;
+        let decoded = self.colortype().and_then(|color| Ok((color, self.read()?)));
+        self.options.downscale = downscale;
+        self.options.upscale = upscale;
 
-            Some(ArbitraryTuplType::BlackAndWhite) if self.maxval == 1 && self.depth == 1 => {
-                Ok(TupleType::BWBit)
+        let (color, samples) = match decoded? {
+            (color, DecodingResult::U8(samples)) => (color, samples),
+            (color, _) => return Err(ImageError::UnsupportedColor(color)),
+        };
+        let (channels, scale) = match color {
+            ColorType::Gray(1) => (1, 255),
+            ColorType::GrayA(1) => (2, 255),
+            ColorType::Gray(8) => (1, 1),
+            ColorType::GrayA(8) => (2, 1),
+            ColorType::RGB(8) => (3, 1),
+            ColorType::RGBA(8) => (4, 1),
+            color => return Err(ImageError::UnsupportedColor(color)),
+        };
+        let mut rgba = Vec::with_capacity(samples.len() / channels * 4);
+        for pixel in samples.chunks(channels) {
+            let gray = pixel[0] * scale;
+            match channels {
+                1 => rgba.extend_from_slice(&[gray, gray, gray, 255]),
+                2 => rgba.extend_from_slice(&[gray, gray, gray, pixel[1] * scale]),
+                3 => {
+                    rgba.extend_from_slice(pixel);
+                    rgba.push(255);
+                }
+                _ => rgba.extend_from_slice(pixel),
             }
-            Some(ArbitraryTuplType::BlackAndWhite) => Err(ImageError::FormatError(
-                "Invalid depth or maxval for tuple type BLACKANDWHITE".to_string(),
-            )),
+        }
+        Ok(rgba)
+    }
 
-            Some(ArbitraryTuplType::Grayscale) if self.depth == 1 && self.maxval <= 0xFF => {
-                Ok(TupleType::GrayU8)
-            }
-            Some(ArbitraryTuplType::Grayscale) if self.depth <= 1 && self.maxval <= 0xFFFF => {
-                Ok(TupleType::GrayU16)
-            }
-            Some(ArbitraryTuplType::Grayscale) => Err(ImageError::FormatError(
-                "Invalid depth or maxval for tuple type GRAYSCALE".to_string(),
-            )),
+    /// Decode the whole image into a `PnmImageView` for reading pixels through
+    /// `GenericImageView`.
+    ///
+    /// Float maps are rejected with `ImageError::UnsupportedColor`, see `PnmImageView::new`.
+    pub fn read_image_view(&mut self) -> ImageResult<PnmImageView> {
+        let (width, height) = self.dimensions()?;
+        let color = self.colortype()?;
+        let samples = self.read()?;
+        PnmImageView::new(samples, width, height, color)
+    }
 
-            Some(ArbitraryTuplType::RGB) if self.depth == 3 && self.maxval <= 0xFF => {
-                Ok(TupleType::RGBU8)
-            }
-            Some(ArbitraryTuplType::RGB) if self.depth == 3 && self.maxval <= 0xFFFF => {
-                Ok(TupleType::RGBU16)
-            }
-            Some(ArbitraryTuplType::RGB) => Err(ImageError::FormatError(
-                "Invalid depth for tuple type RGB".to_string(),
-            )),
+    /// Decode the whole image like `read_image`, reporting the progress to `progress`.
+    ///
+    /// The raster is read in batches of rows of about 1 MiB decoded size. After each batch
+    /// `progress` is called with the number of decoded bytes so far and `total_bytes`, so the
+    /// last call reports both as equal.
+    pub fn read_image_with_progress<F>(&mut self, mut progress: F) -> ImageResult<DecodingResult>
+    where
+        F: FnMut(u64, u64),
+    {
+        self.read_batched(|read, total| {
+            progress(read, total);
+            Ok(())
+        })
+    }
 
-            Some(ArbitraryTuplType::BlackAndWhiteAlpha) => {
-                Err(ImageError::UnsupportedColor(ColorType::GrayA(1)))
+    /// Decode the whole image like `read_image`, aborting once `cancel` is set.
+    ///
+    /// The flag is checked before the raster is read and after each batch of rows of about 1 MiB
+    /// decoded size. A cancelled decode returns `ImageError::Cancelled`, the rows read so far are
+    /// discarded.
+    pub fn read_image_cancellable(&mut self, cancel: &AtomicBool) -> ImageResult<DecodingResult> {
+        let check = || {
+            if cancel.load(Ordering::SeqCst) {
+                Err(ImageError::Cancelled)
+            } else {
+                Ok(())
             }
-            Some(ArbitraryTuplType::GrayscaleAlpha) => {
-                Err(ImageError::UnsupportedColor(ColorType::GrayA(8)))
+        };
+        check()?;
+        self.read_batched(|_, _| check())
+    }
+
+    /// Read the raster in batches, calling `after_batch` with the decoded and total bytes.
+    fn read_batched<F>(&mut self, mut after_batch: F) -> ImageResult<DecodingResult>
+    where
+        F: FnMut(u64, u64) -> ImageResult<()>,
+    {
+        self.check_unread()?;
+        let total = self.total_bytes()? as u64;
+        let rowlen = self.rowlen()?;
+        let height = self.header.height();
+        let batch = cmp::min(cmp::max(PROGRESS_BYTES / rowlen, 1), height as usize) as u32;
+
+        let mut result = self.read_rows(batch)?;
+        self.rows_read += batch;
+        after_batch(rowlen as u64 * u64::from(batch), total)?;
+        while self.rows_read < height {
+            let rows = cmp::min(batch, height - self.rows_read);
+            match (&mut result, self.read_rows(rows)?) {
+                (&mut DecodingResult::U8(ref mut all), DecodingResult::U8(more)) => {
+                    all.extend(more)
+                }
+                (&mut DecodingResult::U16(ref mut all), DecodingResult::U16(more)) => {
+                    all.extend(more)
+                }
+                (&mut DecodingResult::F32(ref mut all), DecodingResult::F32(more)) => {
+                    all.extend(more)
+                }
+                _ => unreachable!("Rows of one image decode to the same sample type"),
             }
-            Some(ArbitraryTuplType::RGBAlpha) => {
-                Err(ImageError::UnsupportedColor(ColorType::RGBA(8)))
+            self.rows_read += rows;
+            after_batch(rowlen as u64 * u64::from(self.rows_read), total)?;
+        }
+        Ok(self.top_down(result))
+    }
+
+    /// Decode a bitmap into packed bits instead of a byte per pixel.
+    ///
+    /// Each row starts at a byte boundary and holds its pixels from the most significant bit on,
+    /// the unused bits at the end of a row are zero. Like for `read_image`, a set bit is white, so
+    /// the bits of pbm files are inverted. Images other than bitmaps are rejected with
+    /// `ImageError::UnsupportedColor`.
+    pub fn read_image_packed(&mut self) -> ImageResult<Vec<u8>> {
+        self.check_unread()?;
+        let (width, height) = (self.header.width(), self.header.height());
+        let packed = match (self.tuple, self.subtype().sample_encoding()) {
+            (TupleType::PbmBit, SampleEncoding::Binary) => {
+                let mut packed = vec![0; PbmBit::bytelen(width, height, 1)?];
+                read_raster(&mut self.reader, &mut packed)?;
+                let padding = (8 - width % 8) % 8;
+                let rowlen = PbmBit::bytelen(width, 1, 1)?;
+                for row in packed.chunks_mut(rowlen) {
+                    for byte in row.iter_mut() {
+                        *byte = !*byte;
+                    }
+                    row[rowlen - 1] &= 0xFF << padding;
+                }
+                packed
             }
-            _ => Err(ImageError::FormatError(
-                "Tuple type not recognized".to_string(),
-            )),
+            (TupleType::PbmBit, _) | (TupleType::BWBit, _) => match self.read()? {
+                DecodingResult::U8(samples) => pack_bits(&samples, width),
+                _ => return Err(ImageError::UnsupportedColor(self.tuple.color()?)),
+            },
+            _ => return Err(ImageError::UnsupportedColor(self.tuple.color()?)),
+        };
+        self.rows_read = height;
+        Ok(packed)
+    }
+
+    /// Decode a grayscale image into a buffer of 8-bit luma pixels.
+    ///
+    /// Bitmaps are expanded to 0 for black and 255 for white. Any other color type than 8-bit
+    /// grayscale is rejected with `ImageError::UnsupportedColor`.
+    pub fn into_luma8(self) -> ImageResult<ImageBuffer<Luma<u8>, Vec<u8>>> {
+        self.into_buffer_u8(ColorType::Gray(8))
+    }
+
+    /// Decode an 8-bit rgb image into a buffer of rgb pixels.
+    pub fn into_rgb8(self) -> ImageResult<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+        self.into_buffer_u8(ColorType::RGB(8))
+    }
+
+    /// Decode a 16-bit grayscale image into a buffer of 16-bit luma pixels.
+    pub fn into_luma16(self) -> ImageResult<ImageBuffer<Luma<u16>, Vec<u16>>> {
+        self.into_buffer_u16(ColorType::Gray(16))
+    }
+
+    /// Decode a 16-bit rgb image into a buffer of rgb pixels.
+    pub fn into_rgb16(self) -> ImageResult<ImageBuffer<Rgb<u16>, Vec<u16>>> {
+        self.into_buffer_u16(ColorType::RGB(16))
+    }
+
+    /// Decode the image into a `DynamicImage`.
+    ///
+    /// `DynamicImage` only holds 8-bit images, so 16-bit samples are rounded to 8 bits unless
+    /// `PnmDecoderOptions::downscale` selects another mapping. Images with a maxval other than
+    /// 65535 should be decoded with `scale_to_full` for this to preserve their intensity. Bitmaps
+    /// are decoded to a byte per pixel instead of the packed bits `decoder_to_image` expects, so
+    /// they become `ImageLuma8` with 0 for black and 255 for white, or `ImageLumaA8` with an alpha
+    /// of 0 or 255 for pams of tuple type `BLACKANDWHITE_ALPHA`. Float maps are rejected with
+    /// `ImageError::UnsupportedColor`.
+    pub fn into_dynamic_image(mut self) -> ImageResult<DynamicImage> {
+        if self.options.downscale.is_none() {
+            self.options.downscale = Some(Downscale::Round);
+        }
+        match self.colortype()? {
+            ColorType::Gray(1) => self.into_luma8().map(DynamicImage::ImageLuma8),
+            ColorType::GrayA(1) => self.into_buffer_u8(ColorType::GrayA(8))
+                .map(DynamicImage::ImageLumaA8),
+            _ => decoder_to_image(self),
         }
     }
-}
-#[cfg(test)]
-mod tests {
+
+    /// Write the image to `out` as the same subtype with the samples in the `target` encoding.
+    ///
+    /// Dimensions, maxval and comments are preserved and the samples are written verbatim,
+    /// ignoring `set_scale_to_full`. Only bitmaps, graymaps and pixmaps have both encodings,
+    /// other subtypes are rejected with `ImageError::UnsupportedError`. This requires that no rows
+    /// have been read before.
+    pub fn transcode<W: Write>(mut self, out: W, target: SampleEncoding) -> ImageResult<()> {
+        if self.rows_read != 0 {
+            return Err(ImageError::FormatError(
+                "Images can only be transcoded before reading any rows".to_string(),
+            ));
+        }
+
+        let (width, height) = (self.header.width(), self.header.height());
+        let decoded = match self.header.decoded {
+            HeaderRecord::Bitmap(header) => HeaderRecord::Bitmap(BitmapHeader {
+                encoding: target,
+                ..header
+            }),
+            HeaderRecord::Graymap(header) => HeaderRecord::Graymap(GraymapHeader {
+                encoding: target,
+                ..header
+            }),
+            HeaderRecord::Pixmap(header) => HeaderRecord::Pixmap(PixmapHeader {
+                encoding: target,
+                ..header
+            }),
+            _ => {
+                return Err(ImageError::UnsupportedError(format!(
+                    "Can not transcode subtype {}",
+                    self.subtype()
+                )))
+            }
+        };
+        let header = PNMHeader {
+            decoded,
+            encoded: None,
+            comments: self.header.comments.clone(),
+        };
+
+        let color = self.tuple.color()?;
+        let result = self.read_raw_rows(height)?;
+        self.check_samples(&result)?;
+        self.rows_read = height;
+        let mut encoder = PNMEncoder::new(out).with_header(header);
+        match result {
+            DecodingResult::U8(samples) => encoder.encode(&samples[..], width, height, color)?,
+            DecodingResult::U16(samples) => encoder.encode(&samples[..], width, height, color)?,
+            _ => return Err(ImageError::UnsupportedColor(color)),
+        }
+        Ok(())
+    }
+
+    /// Write an 8-bit grayscale image to `out` as a binary bitmap (P4).
+    ///
+    /// Samples greater or equal to `threshold` become white, all others black. The samples are
+    /// compared as `read_image` returns them, so `set_scale_to_full` applies. Comments are
+    /// preserved. Any other color type than 8-bit grayscale is rejected with
+    /// `ImageError::UnsupportedColor`.
+    pub fn to_bitmap<W: Write>(mut self, out: W, threshold: u8) -> ImageResult<()> {
+        let color = self.output().color()?;
+        if color != ColorType::Gray(8) {
+            return Err(ImageError::UnsupportedColor(color));
+        }
+
+        let (width, height) = (self.header.width(), self.header.height());
+        let header = PNMHeader {
+            decoded: HeaderRecord::Bitmap(BitmapHeader {
+                encoding: SampleEncoding::Binary,
+                width,
+                height,
+            }),
+            encoded: None,
+            comments: self.header.comments.clone(),
+        };
+        let bits: Vec<u8> = match self.read()? {
+            DecodingResult::U8(samples) => samples
+                .into_iter()
+                .map(|v| if v >= threshold { 1 } else { 0 })
+                .collect(),
+            _ => return Err(ImageError::UnsupportedColor(color)),
+        };
+        PNMEncoder::new(out).with_header(header).encode(
+            &bits[..],
+            width,
+            height,
+            ColorType::Gray(1),
+        )?;
+        Ok(())
+    }
+
+    fn into_buffer_u8<P>(mut self, color: ColorType) -> ImageResult<ImageBuffer<P, Vec<u8>>>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+    {
+        let actual = self.output().color()?;
+        let bitmap = match (actual, color) {
+            (ColorType::Gray(1), ColorType::Gray(8)) => true,
+            (ColorType::GrayA(1), ColorType::GrayA(8)) => true,
+            _ => false,
+        };
+        if actual != color && !bitmap {
+            return Err(ImageError::UnsupportedColor(actual));
+        }
+        let samples = match self.read()? {
+            DecodingResult::U8(samples) if bitmap => {
+                samples.into_iter().map(|bit| bit * 255).collect()
+            }
+            DecodingResult::U8(samples) => samples,
+            _ => return Err(ImageError::UnsupportedColor(actual)),
+        };
+        ImageBuffer::from_raw(self.header.width(), self.header.height(), samples)
+            .ok_or(ImageError::DimensionError)
+    }
+
+    fn into_buffer_u16<P>(mut self, color: ColorType) -> ImageResult<ImageBuffer<P, Vec<u16>>>
+    where
+        P: Pixel<Subpixel = u16> + 'static,
+    {
+        let actual = self.output().color()?;
+        if actual != color {
+            return Err(ImageError::UnsupportedColor(actual));
+        }
+        let samples = match self.read()? {
+            DecodingResult::U16(samples) => samples,
+            _ => return Err(ImageError::UnsupportedColor(actual)),
+        };
+        ImageBuffer::from_raw(self.header.width(), self.header.height(), samples)
+            .ok_or(ImageError::DimensionError)
+    }
+
+    fn read(&mut self) -> ImageResult<DecodingResult> {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!(
+            "pnm_decode",
+            width = self.header.width(),
+            height = self.header.height(),
+            subtype = ?self.subtype()
+        ).entered();
+        self.check_unread()?;
+        let height = self.header.height();
+        pnm_event!(bytes = self.total_bytes()?, "pnm allocating raster");
+        let result = self.read_rows(height)?;
+        self.rows_read = height;
+        pnm_event!("pnm decode complete");
+        Ok(self.top_down(result))
+    }
+
+    /// Reject decoding the whole image once rows have been read with `read_scanline`.
+    fn check_unread(&self) -> ImageResult<()> {
+        if self.rows_read != 0 {
+            return Err(ImageError::FormatError(
+                "The whole image can only be decoded before reading any rows".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reorder the rows of a whole float map from top to bottom.
+    fn top_down(&self, result: DecodingResult) -> DecodingResult {
+        match result {
+            DecodingResult::F32(mut samples) => {
+                let rowlen = samples.len() / self.header.height() as usize;
+                flip_rows(&mut samples, rowlen);
+                DecodingResult::F32(samples)
+            }
+            result => result,
+        }
+    }
+
+    /// Read the next `rows` rows of the raster, continuing from the current reader position.
+    fn read_rows(&mut self, rows: u32) -> ImageResult<DecodingResult> {
+        let result = self.read_raw_rows(rows)?;
+        self.check_samples(&result)?;
+        Ok(self.convert(self.scale(result)))
+    }
+
+    /// Reject samples above the maxval if `PnmDecoderOptions::strict` is set.
+    fn check_samples(&self, result: &DecodingResult) -> ImageResult<()> {
+        if !self.options.strict {
+            return Ok(());
+        }
+
+        let maxval = self.header.maximal_sample();
+        let invalid = match (self.tuple, result) {
+            (TupleType::PbmBit, _) | (TupleType::BWBit, _) | (TupleType::BWAlphaBit, _) => None,
+            (_, &DecodingResult::U8(ref samples)) => samples
+                .iter()
+                .map(|&v| u32::from(v))
+                .find(|&v| v > maxval),
+            (_, &DecodingResult::U16(ref samples)) => samples
+                .iter()
+                .map(|&v| u32::from(v))
+                .find(|&v| v > maxval),
+            (_, _) => None,
+        };
+        match invalid {
+            Some(value) => Err(out_of_range(value, maxval)),
+            None => Ok(()),
+        }
+    }
+
+    /// Apply `set_scale_to_full` to decoded samples.
+    fn scale(&self, result: DecodingResult) -> DecodingResult {
+        if !self.options.scale_to_full {
+            return result;
+        }
+
+        let maxval = self.header.maximal_sample();
+        match (self.tuple, result) {
+            (TupleType::PbmBit, result)
+            | (TupleType::BWBit, result)
+            | (TupleType::BWAlphaBit, result) => result,
+            (_, DecodingResult::U8(samples)) => DecodingResult::U8(
+                samples
+                    .into_iter()
+                    .map(|v| scale_sample(u32::from(v), maxval, 0xFF) as u8)
+                    .collect(),
+            ),
+            (_, DecodingResult::U16(samples)) => DecodingResult::U16(
+                samples
+                    .into_iter()
+                    .map(|v| scale_sample(u32::from(v), maxval, 0xFFFF) as u16)
+                    .collect(),
+            ),
+            (_, result) => result,
+        }
+    }
+
+    /// Apply the color and depth conversions of `PnmDecoderOptions` to decoded samples.
+    fn convert(&self, mut result: DecodingResult) -> DecodingResult {
+        let mut tuple = self.tuple;
+        if self.options.grayscale && tuple.grayscale() != tuple {
+            result = luminance_samples(result, tuple.channels());
+            tuple = tuple.grayscale();
+        }
+        if self.options.expand_rgb && tuple.expanded() != tuple {
+            result = match result {
+                DecodingResult::U8(samples) => DecodingResult::U8(expand_gray(&samples, tuple)),
+                DecodingResult::U16(samples) => DecodingResult::U16(expand_gray(&samples, tuple)),
+                DecodingResult::F32(samples) => DecodingResult::F32(expand_gray(&samples, tuple)),
+            };
+        }
+        if let Some(downscale) = self.options.downscale {
+            if let DecodingResult::U16(samples) = result {
+                let samples = samples.into_iter().map(|v| downscale_sample(v, downscale));
+                result = DecodingResult::U8(samples.collect());
+            }
+            tuple = tuple.narrowed();
+        }
+        if self.options.upscale && tuple.widened() != tuple {
+            if let DecodingResult::U8(samples) = result {
+                let samples = samples.into_iter().map(|v| u16::from(v) * 257);
+                result = DecodingResult::U16(samples.collect());
+            }
+        }
+        result
+    }
+
+    /// The tuple type of the decoded samples, after applying the conversions of `convert`.
+    fn output(&self) -> TupleType {
+        let mut tuple = self.tuple;
+        if self.options.grayscale {
+            tuple = tuple.grayscale();
+        }
+        if self.options.expand_rgb {
+            tuple = tuple.expanded();
+        }
+        if self.options.downscale.is_some() {
+            tuple = tuple.narrowed();
+        }
+        if self.options.upscale {
+            tuple = tuple.widened();
+        }
+        tuple
+    }
+
+    fn read_raw_rows(&mut self, rows: u32) -> ImageResult<DecodingResult> {
+        match self.tuple {
+            TupleType::PbmBit => self.read_samples::<PbmBit>(1, rows),
+            TupleType::BWBit => self.read_samples::<BWBit>(1, rows),
+            TupleType::BWAlphaBit => self.read_samples::<BWBit>(2, rows),
+            TupleType::RGBU8 => self.read_u8_samples(3, rows),
+            TupleType::RGBU16 => self.read_u16_samples(3, rows),
+            TupleType::GrayU8 => self.read_u8_samples(1, rows),
+            TupleType::GrayU16 => self.read_u16_samples(1, rows),
+            TupleType::GrayAU8 => self.read_u8_samples(2, rows),
+            TupleType::GrayAU16 => self.read_u16_samples(2, rows),
+            TupleType::RGBAU8 => self.read_u8_samples(4, rows),
+            TupleType::RGBAU16 => self.read_u16_samples(4, rows),
+            TupleType::GrayF32 => self.read_floats(1, rows),
+            TupleType::RGBF32 => self.read_floats(3, rows),
+            TupleType::CustomU8(depth) => self.read_u8_samples(u32::from(depth), rows),
+            TupleType::CustomU16(depth) => self.read_u16_samples(u32::from(depth), rows),
+        }
+    }
+
+    #[cfg(feature = "pnm_u16")]
+    fn read_u16_samples(&mut self, components: u32, rows: u32) -> ImageResult<DecodingResult> {
+        match self.sample_endianness() {
+            Endianness::Little => self.read_samples::<U16<LittleEndian>>(components, rows),
+            Endianness::Big => self.read_samples::<U16<BigEndian>>(components, rows),
+        }
+    }
+
+    #[cfg(not(feature = "pnm_u16"))]
+    fn read_u16_samples(&mut self, _: u32, _: u32) -> ImageResult<DecodingResult> {
+        Err(u16_unsupported())
+    }
+
+    fn read_floats(&mut self, components: u32, rows: u32) -> ImageResult<DecodingResult> {
+        match self.sample_endianness() {
+            Endianness::Little => self.read_samples::<F32<LittleEndian>>(components, rows),
+            Endianness::Big => self.read_samples::<F32<BigEndian>>(components, rows),
+        }
+    }
+
+    /// The byte order of binary 16-bit and float samples.
+    ///
+    /// Unless forced, floats follow the sign of the scale and all other samples are big endian.
+    fn sample_endianness(&self) -> Endianness {
+        match (self.options.endianness, self.header.as_floatmap()) {
+            (Some(endianness), _) => endianness,
+            (None, Some(header)) => header.endianness(),
+            (None, None) => Endianness::Big,
+        }
+    }
+
+    /// Binary 8-bit samples are read straight into the result without an intermediate buffer.
+    fn read_u8_samples(&mut self, components: u32, rows: u32) -> ImageResult<DecodingResult> {
+        match self.subtype().sample_encoding() {
+            SampleEncoding::Binary => {
+                let bytecount = U8::bytelen(self.header.width(), rows, components)?;
+                let mut samples = vec![0; bytecount];
+                read_raster(&mut self.reader, &mut samples)?;
+                Ok(DecodingResult::U8(samples))
+            }
+            SampleEncoding::Ascii => self.read_samples::<U8>(components, rows),
+        }
+    }
+
+    fn read_samples<S: Sample>(&mut self, components: u32, rows: u32) -> ImageResult<DecodingResult>
+    where
+        Vec<S::T>: Into<DecodingResult>,
+    {
+        match self.subtype().sample_encoding() {
+            SampleEncoding::Binary => {
+                let width = self.header.width();
+                let bytecount = S::bytelen(width, rows, components)?;
+                let mut bytes = vec![0; bytecount];
+                read_raster(&mut self.reader, &mut bytes)?;
+                let samples = S::from_bytes(&bytes, width, rows, components)?;
+                Ok(samples.into())
+            }
+            SampleEncoding::Ascii => {
+                let samples = self.read_ascii::<S>(components, rows)?;
+                Ok(samples.into())
+            }
+        }
+    }
+
+    #[cfg(feature = "pnm_ascii")]
+    fn read_ascii<Basic: Sample>(
+        &mut self,
+        components: u32,
+        rows: u32,
+    ) -> ImageResult<Vec<Basic::T>> {
+        let count = sample_count(self.header.width(), rows, components)?;
+        let mut buffer = Vec::with_capacity(cmp::min(count, MAX_ASCII_RESERVE));
+        self.parse_ascii(components, count, |value| {
+            buffer.push(Basic::from_unsigned(value)?);
+            Ok(())
+        })?;
+        Ok(buffer)
+    }
+
+    /// Parse the next `count` ascii samples, passing their values to `sample` in order.
+    #[cfg(feature = "pnm_ascii")]
+    fn parse_ascii<F>(&mut self, components: u32, count: usize, mut sample: F) -> ImageResult<()>
+    where
+        F: FnMut(u32) -> ImageResult<()>,
+    {
+        for index in 0..count {
+            let value = match self.tuple {
+                TupleType::PbmBit => self.read_ascii_bit()?,
+                _ => self.read_ascii_sample()?,
+            };
+            match value {
+                Some(value) => sample(value)?,
+                None => {
+                    return Err(PnmError::MissingSamples {
+                        expected: count,
+                        got: index,
+                    }.into())
+                }
+            }
+        }
+        self.samples_read += count;
+        let total = sample_count(self.header.width(), self.header.height(), components)?;
+        if self.options.reject_trailing && self.samples_read == total {
+            self.check_ascii_end()?;
+        }
+        Ok(())
+    }
+
+    /// Decode the next row straight into `row` if no option converts its samples.
+    ///
+    /// Returns `false` without reading anything if the samples need to be converted, which only
+    /// the allocating path of `read_rows` does. Floats take that path as well.
+    fn read_row_into(&mut self, row: &mut [u8]) -> ImageResult<bool> {
+        let sample_bytes = self.tuple.sample_bytes() as usize;
+        if self.output() != self.tuple
+            || sample_bytes > 2
+            || (sample_bytes == 2 && !cfg!(feature = "pnm_u16"))
+        {
+            return Ok(false);
+        }
+
+        let components = u32::from(self.tuple.channels());
+        match self.subtype().sample_encoding() {
+            SampleEncoding::Binary => self.read_binary_row(components, row)?,
+            SampleEncoding::Ascii => self.read_ascii_row(components, row)?,
+        }
+
+        let bitmap = match self.tuple {
+            TupleType::PbmBit | TupleType::BWBit | TupleType::BWAlphaBit => true,
+            _ => false,
+        };
+        if bitmap || !(self.options.strict || self.options.scale_to_full) {
+            return Ok(true);
+        }
+        // Equivalent to `check_samples` and `scale` on the native endian samples of the row.
+        let maxval = self.header.maximal_sample();
+        for sample in row.chunks_mut(sample_bytes) {
+            let value = match sample_bytes {
+                1 => u32::from(sample[0]),
+                _ => u32::from(NativeEndian::read_u16(sample)),
+            };
+            if self.options.strict && value > maxval {
+                return Err(out_of_range(value, maxval));
+            }
+            if self.options.scale_to_full {
+                match sample_bytes {
+                    1 => sample[0] = scale_sample(value, maxval, 0xFF) as u8,
+                    _ => {
+                        let scaled = scale_sample(value, maxval, 0xFFFF) as u16;
+                        NativeEndian::write_u16(sample, scaled)
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read a binary row of 8-bit or 16-bit samples into `row` as native endian bytes.
+    fn read_binary_row(&mut self, components: u32, row: &mut [u8]) -> ImageResult<()> {
+        match self.tuple {
+            TupleType::PbmBit => {
+                let bytelen = PbmBit::bytelen(self.header.width(), 1, components)?;
+                self.scratch.resize(bytelen, 0);
+                read_raster(&mut self.reader, &mut self.scratch)?;
+                expand_bits(&self.scratch, row);
+            }
+            TupleType::BWBit | TupleType::BWAlphaBit => {
+                read_raster(&mut self.reader, row)?;
+                if let Some(&value) = row.iter().find(|&&value| value > 1) {
+                    return Err(out_of_range(u32::from(value), 1));
+                }
+            }
+            tuple if tuple.sample_bytes() == 2 => {
+                read_raster(&mut self.reader, row)?;
+                let endianness = self.sample_endianness();
+                for sample in row.chunks_mut(2) {
+                    let value = match endianness {
+                        Endianness::Big => BigEndian::read_u16(sample),
+                        Endianness::Little => LittleEndian::read_u16(sample),
+                    };
+                    NativeEndian::write_u16(sample, value);
+                }
+            }
+            _ => read_raster(&mut self.reader, row)?,
+        }
+        Ok(())
+    }
+
+    /// Parse an ascii row of 8-bit or 16-bit samples into `row` as native endian bytes.
+    #[cfg(feature = "pnm_ascii")]
+    fn read_ascii_row(&mut self, components: u32, row: &mut [u8]) -> ImageResult<()> {
+        let count = sample_count(self.header.width(), 1, components)?;
+        let tuple = self.tuple;
+        let mut samples = row.chunks_mut(tuple.sample_bytes() as usize);
+        self.parse_ascii(components, count, |value| {
+            let sample = samples.next().expect("The row holds every sample");
+            match tuple {
+                TupleType::PbmBit => sample[0] = PbmBit::from_unsigned(value)?,
+                TupleType::BWBit | TupleType::BWAlphaBit => {
+                    sample[0] = BWBit::from_unsigned(value)?
+                }
+                _ if sample.len() == 1 => sample[0] = U8::from_unsigned(value)?,
+                _ if value > u32::from(u16::max_value()) => {
+                    return Err(out_of_range(value, u32::from(u16::max_value())))
+                }
+                _ => NativeEndian::write_u16(sample, value as u16),
+            }
+            Ok(())
+        })
+    }
+
+    #[cfg(not(feature = "pnm_ascii"))]
+    fn read_ascii_row(&mut self, _: u32, _: &mut [u8]) -> ImageResult<()> {
+        Err(ImageError::UnsupportedError(
+            "ASCII PNM not supported in this build".to_string(),
+        ))
+    }
+
+    /// Skip whitespace up to the end of the input, rejecting anything else.
+    #[cfg(feature = "pnm_ascii")]
+    fn check_ascii_end(&mut self) -> ImageResult<()> {
+        loop {
+            let (skipped, trailing) = {
+                let buf = match self.reader.fill_buf() {
+                    Ok(buf) => buf,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(ImageError::IoError(err)),
+                };
+                if buf.is_empty() {
+                    return Ok(());
+                }
+                let skipped = buf.iter()
+                    .take_while(|&&byte| match byte {
+                        b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r' | b' ' => true,
+                        _ => false,
+                    })
+                    .count();
+                (skipped, skipped < buf.len())
+            };
+            self.reader.consume(skipped);
+            self.consume_input(skipped)?;
+            if trailing {
+                return Err(ImageError::FormatError(
+                    "Unexpected data after the last sample".to_string(),
+                ));
+            }
+        }
+    }
+
+    #[cfg(not(feature = "pnm_ascii"))]
+    fn read_ascii<Basic: Sample>(&mut self, _: u32, _: u32) -> ImageResult<Vec<Basic::T>> {
+        Err(ImageError::UnsupportedError(
+            "ASCII PNM not supported in this build".to_string(),
+        ))
+    }
+
+    /// Bits of an ascii bitmap need not be separated by whitespace, so read a single digit.
+    ///
+    /// Returns `None` if the input ends before the next bit.
+    #[cfg(feature = "pnm_ascii")]
+    fn read_ascii_bit(&mut self) -> ImageResult<Option<u32>> {
+        loop {
+            let mut byte = [0];
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => (),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(ImageError::IoError(err)),
+            }
+            self.consume_input(1)?;
+            match byte[0] {
+                b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r' | b' ' => continue,
+                b'0' => return Ok(Some(0)),
+                b'1' => return Ok(Some(1)),
+                _ => {
+                    return Err(ImageError::FormatError(
+                        "Expected 0 or 1 as bitmap sample value".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Parse the next whitespace separated decimal sample, consuming the whitespace after it.
+    ///
+    /// Scans the buffer of the reader directly to avoid allocating for each sample. Returns `None`
+    /// if the input ends before the next sample.
+    #[cfg(feature = "pnm_ascii")]
+    fn read_ascii_sample(&mut self) -> ImageResult<Option<u32>> {
+        let mut token = AsciiSample::default();
+        loop {
+            let (consumed, done) = {
+                let buf = match self.reader.fill_buf() {
+                    Ok(buf) => buf,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(ImageError::IoError(err)),
+                };
+                if buf.is_empty() {
+                    break;
+                }
+                let mut consumed = 0;
+                let mut done = false;
+                for &byte in buf {
+                    consumed += 1;
+                    match byte {
+                        b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r' | b' ' => if token.len > 0 {
+                            done = true;
+                            break;
+                        },
+                        _ => token.push(byte),
+                    }
+                }
+                (consumed, done)
+            };
+            self.reader.consume(consumed);
+            self.consume_input(consumed)?;
+            if done {
+                break;
+            }
+        }
+        if token.len == 0 {
+            return Ok(None);
+        }
+        token.value().map(Some)
+    }
+
+    /// Get the pnm subtype, depending on the magic constant contained in the header
+    pub fn subtype(&self) -> PNMSubtype {
+        self.header.subtype()
+    }
+
+    /// The number of interleaved samples of each pixel.
+    ///
+    /// Pams of a custom tuple type are decoded with `DEPTH` channels of 8 or 16 bits depending on
+    /// their maxval. `colortype` can not describe them, so this is the only way to learn their
+    /// layout.
+    pub fn channel_count(&self) -> u8 {
+        self.output().channels()
+    }
+
+    /// The number of bytes of each decoded sample.
+    ///
+    /// Bits are expanded to a full byte each, so this is 1 for bitmaps.
+    pub fn bytes_per_sample(&self) -> u8 {
+        self.output().sample_bytes()
+    }
+}
+
+impl TupleType {
+    fn color(self) -> ImageResult<ColorType> {
+        use self::TupleType::*;
+        Ok(match self {
+            PbmBit => ColorType::Gray(1),
+            BWBit => ColorType::Gray(1),
+            BWAlphaBit => ColorType::GrayA(1),
+            GrayU8 => ColorType::Gray(8),
+            GrayU16 => ColorType::Gray(16),
+            GrayAU8 => ColorType::GrayA(8),
+            GrayAU16 => ColorType::GrayA(16),
+            RGBU8 => ColorType::RGB(8),
+            RGBU16 => ColorType::RGB(16),
+            RGBAU8 => ColorType::RGBA(8),
+            RGBAU16 => ColorType::RGBA(16),
+            GrayF32 => ColorType::Gray(32),
+            RGBF32 => ColorType::RGB(32),
+            CustomU8(_) | CustomU16(_) => {
+                return Err(ImageError::UnsupportedError(
+                    "Custom pam tuple types have no color type".to_string(),
+                ))
+            }
+        })
+    }
+
+    fn channels(self) -> u8 {
+        use self::TupleType::*;
+        match self {
+            PbmBit | BWBit | GrayU8 | GrayU16 | GrayF32 => 1,
+            BWAlphaBit | GrayAU8 | GrayAU16 => 2,
+            RGBU8 | RGBU16 | RGBF32 => 3,
+            RGBAU8 | RGBAU16 => 4,
+            CustomU8(channels) | CustomU16(channels) => channels,
+        }
+    }
+
+    /// The tuple type of the luminance of RGB and RGBA samples, other types are unchanged.
+    fn grayscale(self) -> TupleType {
+        use self::TupleType::*;
+        match self {
+            RGBU8 | RGBAU8 => GrayU8,
+            RGBU16 | RGBAU16 => GrayU16,
+            RGBF32 => GrayF32,
+            other => other,
+        }
+    }
+
+    /// The tuple type of RGB or RGBA samples replicating gray ones, other types are unchanged.
+    fn expanded(self) -> TupleType {
+        use self::TupleType::*;
+        match self {
+            GrayU8 => RGBU8,
+            GrayU16 => RGBU16,
+            GrayAU8 => RGBAU8,
+            GrayAU16 => RGBAU16,
+            GrayF32 => RGBF32,
+            other => other,
+        }
+    }
+
+    /// The tuple type of 8-bit samples with the channels of 16-bit ones, other types are
+    /// unchanged.
+    fn narrowed(self) -> TupleType {
+        use self::TupleType::*;
+        match self {
+            GrayU16 => GrayU8,
+            GrayAU16 => GrayAU8,
+            RGBU16 => RGBU8,
+            RGBAU16 => RGBAU8,
+            CustomU16(channels) => CustomU8(channels),
+            other => other,
+        }
+    }
+
+    /// The tuple type of 16-bit samples with the channels of 8-bit ones, other types are
+    /// unchanged.
+    fn widened(self) -> TupleType {
+        use self::TupleType::*;
+        match self {
+            GrayU8 => GrayU16,
+            GrayAU8 => GrayAU16,
+            RGBU8 => RGBU16,
+            RGBAU8 => RGBAU16,
+            CustomU8(channels) => CustomU16(channels),
+            other => other,
+        }
+    }
+
+    fn sample_bytes(self) -> u8 {
+        use self::TupleType::*;
+        match self {
+            PbmBit | BWBit | BWAlphaBit | GrayU8 | GrayAU8 | RGBU8 | RGBAU8 | CustomU8(_) => 1,
+            GrayU16 | GrayAU16 | RGBU16 | RGBAU16 | CustomU16(_) => 2,
+            GrayF32 | RGBF32 => 4,
+        }
+    }
+}
+
+/// Incrementally parsed decimal sample, accepting the same tokens as `u32::from_str`.
+#[cfg(feature = "pnm_ascii")]
+#[derive(Default)]
+struct AsciiSample {
+    len: usize,
+    digits: usize,
+    /// `None` once the token is no valid number
+    value: Option<u32>,
+    non_ascii: bool,
+}
+
+#[cfg(feature = "pnm_ascii")]
+impl AsciiSample {
+    fn push(&mut self, byte: u8) {
+        if self.len == 0 {
+            self.value = Some(0);
+        }
+        self.value = match byte {
+            b'0'...b'9' => {
+                self.digits += 1;
+                self.value
+                    .and_then(|v| v.checked_mul(10))
+                    .and_then(|v| v.checked_add(u32::from(byte - b'0')))
+            }
+            b'+' if self.len == 0 => self.value,
+            _ => None,
+        };
+        self.non_ascii |= !byte.is_ascii();
+        self.len += 1;
+    }
+
+    fn value(&self) -> ImageResult<u32> {
+        if self.non_ascii {
+            return Err(ImageError::FormatError(
+                "Non ascii character where sample value was expected".to_string(),
+            ));
+        }
+        match self.value {
+            Some(value) if self.digits > 0 => Ok(value),
+            _ => Err(ImageError::FormatError(
+                "Error parsing sample value".to_string(),
+            )),
+        }
+    }
+}
+
+/// Number of samples in an image of the given dimensions, erroring instead of overflowing.
+fn sample_count(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|count| count.checked_mul(samples as usize))
+        .ok_or_else(dimension_overflow)
+}
+
+fn dimension_overflow() -> ImageError {
+    ImageError::DimensionError
+}
+
+fn out_of_range(value: u32, maxval: u32) -> ImageError {
+    PnmError::SampleOutOfRange { value, maxval }.into()
+}
+
+#[cfg(not(feature = "pnm_u16"))]
+fn u16_unsupported() -> ImageError {
+    ImageError::UnsupportedError("16-bit support not compiled in".to_string())
+}
+
+impl Sample for U8 {
+    type T = u8;
+
+    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+        sample_count(width, height, samples)
+    }
+
+    /// The bytes are the samples, so they are copied once without zero-filling a buffer first.
+    fn from_bytes(
+        bytes: &[u8],
+        _width: u32,
+        _height: u32,
+        _samples: u32,
+    ) -> ImageResult<Vec<Self::T>> {
+        Ok(bytes.to_vec())
+    }
+
+    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
+        if val > u32::from(u8::max_value()) {
+            Err(out_of_range(val, u32::from(u8::max_value())))
+        } else {
+            Ok(val as u8)
+        }
+    }
+}
+
+#[cfg(feature = "pnm_u16")]
+impl<E: ByteOrder> Sample for U16<E> {
+    type T = u16;
+
+    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+        sample_count(width, height, samples)?
+            .checked_mul(2)
+            .ok_or_else(dimension_overflow)
+    }
+
+    fn from_bytes(
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> ImageResult<Vec<Self::T>> {
+        let count = sample_count(width, height, samples)?;
+        if Some(bytes.len()) != count.checked_mul(2) {
+            return Err(ImageError::FormatError(
+                "Raster length does not match 16-bit sample count".to_string(),
+            ));
+        }
+        let mut buffer = Vec::new();
+        buffer.resize(count, 0 as u16);
+        read_u16_into::<E>(bytes, &mut buffer);
+        Ok(buffer)
+    }
+
+    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
+        if val > u32::from(u16::max_value()) {
+            Err(out_of_range(val, u32::from(u16::max_value())))
+        } else {
+            Ok(val as u16)
+        }
+    }
+}
+
+/// Number of samples converted by a single task of the parallel byteswap.
+#[cfg(all(feature = "pnm_u16", feature = "rayon"))]
+const U16_CHUNK: usize = 1 << 16;
+
+/// Convert bytes in the byte order `E` to native samples, splitting the work across threads.
+///
+/// Every chunk is converted with the same routine as the serial path, so the result is identical.
+#[cfg(all(feature = "pnm_u16", feature = "rayon"))]
+fn read_u16_into<E: ByteOrder>(bytes: &[u8], buffer: &mut [u16]) {
+    use rayon::prelude::*;
+
+    buffer
+        .par_chunks_mut(U16_CHUNK)
+        .zip(bytes.par_chunks(2 * U16_CHUNK))
+        .for_each(|(samples, bytes)| E::read_u16_into(bytes, samples));
+}
+
+/// Convert bytes in the byte order `E` to native samples.
+#[cfg(all(feature = "pnm_u16", not(feature = "rayon")))]
+fn read_u16_into<E: ByteOrder>(bytes: &[u8], buffer: &mut [u16]) {
+    E::read_u16_into(bytes, buffer);
+}
+
+// Floats are stored in IEEE-754 format, with a byte order depending on the sign of the scale.
+impl<E: ByteOrder> Sample for F32<E> {
+    type T = f32;
+
+    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+        sample_count(width, height, samples)?
+            .checked_mul(4)
+            .ok_or_else(dimension_overflow)
+    }
+
+    fn from_bytes(
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> ImageResult<Vec<Self::T>> {
+        let count = sample_count(width, height, samples)?;
+        if Some(bytes.len()) != count.checked_mul(4) {
+            return Err(ImageError::FormatError(
+                "Raster length does not match float sample count".to_string(),
+            ));
+        }
+        let mut buffer = Vec::new();
+        buffer.resize(count, 0u32);
+        E::read_u32_into(bytes, &mut buffer);
+        Ok(buffer.into_iter().map(f32::from_bits).collect())
+    }
+
+    fn from_unsigned(_: u32) -> ImageResult<Self::T> {
+        Err(ImageError::FormatError(
+            "Float samples can not be ascii encoded".to_string(),
+        ))
+    }
+}
+
+/// The eight samples each byte of a bitmap raster decodes to, high order bits first.
+static PBM_EXPANSION: [[u8; 8]; 256] = pbm_expansion();
+
+const fn pbm_expansion() -> [[u8; 8]; 256] {
+    let mut table = [[0; 8]; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut bit = 0;
+        while bit < 8 {
+            // Black is encoded as 1 but decoded as 0.
+            table[byte][bit] = ((byte >> (7 - bit)) & 1 == 0) as u8;
+            bit += 1;
+        }
+        byte += 1;
+    }
+    table
+}
+
+/// Expand the bytes of a single bitmap row into its samples.
+fn expand_bits(bytes: &[u8], samples: &mut [u8]) {
+    // The last chunk of a row is shorter if the width is not a multiple of 8.
+    for (&byte, samples) in bytes.iter().zip(samples.chunks_mut(8)) {
+        samples.copy_from_slice(&PBM_EXPANSION[byte as usize][..samples.len()]);
+    }
+}
+
+// The image is encoded in rows of bits, high order bits first. Any bits beyond the row bits should
+// be ignored. Also, contrary to rgb, black pixels are encoded as a 1 while white is 0. This will
+// need to be reversed for the grayscale output.
+impl Sample for PbmBit {
+    type T = u8;
+
+    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+        let count = sample_count(width, 1, samples)?;
+        let linelen = (count / 8) + ((count % 8) != 0) as usize;
+        linelen
+            .checked_mul(height as usize)
+            .ok_or_else(dimension_overflow)
+    }
+
+    fn from_bytes(
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> ImageResult<Vec<Self::T>> {
+        let mut buffer = Vec::new();
+        let linecount = width as usize * samples as usize;
+        let linebytelen = (linecount / 8) + ((linecount % 8) != 0) as usize;
+        buffer.resize(linecount * height as usize, 0 as u8);
+        if linecount == 0 {
+            return Ok(buffer);
+        }
+        for (linebuffer, out) in bytes.chunks(linebytelen).zip(buffer.chunks_mut(linecount)) {
+            expand_bits(linebuffer, out);
+        }
+        Ok(buffer)
+    }
+
+    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
+        match val {
+            // 0 is white in pbm
+            0 => Ok(1 as u8),
+            // 1 is black in pbm
+            1 => Ok(0 as u8),
+            _ => Err(out_of_range(val, 1)),
+        }
+    }
+}
+
+// Encoded just like a normal U8 but we check the values.
+impl Sample for BWBit {
+    type T = u8;
+
+    fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+        U8::bytelen(width, height, samples)
+    }
+
+    fn from_bytes(
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> ImageResult<Vec<Self::T>> {
+        let values = U8::from_bytes(bytes, width, height, samples)?;
+        if let Some(&val) = values.iter().find(|&&val| val > 1) {
+            return Err(out_of_range(u32::from(val), 1));
+        };
+        Ok(values)
+    }
+
+    fn from_unsigned(val: u32) -> ImageResult<Self::T> {
+        match val {
+            0 => Ok(0 as u8),
+            1 => Ok(1 as u8),
+            _ => Err(out_of_range(val, 1)),
+        }
+    }
+}
+
+impl Into<DecodingResult> for Vec<u8> {
+    fn into(self) -> DecodingResult {
+        DecodingResult::U8(self)
+    }
+}
+
+impl Into<DecodingResult> for Vec<u16> {
+    fn into(self) -> DecodingResult {
+        DecodingResult::U16(self)
+    }
+}
+
+impl Into<DecodingResult> for Vec<f32> {
+    fn into(self) -> DecodingResult {
+        DecodingResult::F32(self)
+    }
+}
+
+/// Fill `buf` with raster bytes, reporting how many were available if the data ends early.
+fn read_raster<R: Read>(reader: &mut R, buf: &mut [u8]) -> ImageResult<()> {
+    let mut got = 0;
+    while got < buf.len() {
+        match reader.read(&mut buf[got..]) {
+            Ok(0) => {
+                return Err(PnmError::Truncated {
+                    expected: buf.len(),
+                    got,
+                }.into())
+            }
+            Ok(count) => got += count,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(ImageError::IoError(err)),
+        }
+    }
+    Ok(())
+}
+
+/// Discard `len` bytes of the raster, consuming them from the buffer of the reader.
+fn skip_raster<R: BufRead>(reader: &mut R, len: usize) -> ImageResult<()> {
+    let mut got = 0;
+    while got < len {
+        let available = match reader.fill_buf() {
+            Ok(buf) => cmp::min(buf.len(), len - got),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(ImageError::IoError(err)),
+        };
+        if available == 0 {
+            return Err(PnmError::Truncated { expected: len, got }.into());
+        }
+        reader.consume(available);
+        got += available;
+    }
+    Ok(())
+}
+
+/// The luminance `0.299 R + 0.587 G + 0.114 B`, rounded to the nearest integer.
+fn luminance(r: u32, g: u32, b: u32) -> u32 {
+    (299 * r + 587 * g + 114 * b + 500) / 1000
+}
+
+/// Map a 16-bit sample to 8 bits.
+fn downscale_sample(value: u16, downscale: Downscale) -> u8 {
+    let value = u32::from(value) * 255;
+    let value = match downscale {
+        Downscale::Round => (value + 32767) / 65535,
+        Downscale::Truncate => value / 65535,
+    };
+    value as u8
+}
+
+/// Replace each pixel of `channels` RGB or RGBA samples by its luminance.
+fn luminance_samples(result: DecodingResult, channels: u8) -> DecodingResult {
+    let channels = usize::from(channels);
+    match result {
+        DecodingResult::U8(samples) => DecodingResult::U8(
+            samples
+                .chunks(channels)
+                .map(|p| luminance(u32::from(p[0]), u32::from(p[1]), u32::from(p[2])) as u8)
+                .collect(),
+        ),
+        DecodingResult::U16(samples) => DecodingResult::U16(
+            samples
+                .chunks(channels)
+                .map(|p| luminance(u32::from(p[0]), u32::from(p[1]), u32::from(p[2])) as u16)
+                .collect(),
+        ),
+        DecodingResult::F32(samples) => DecodingResult::F32(
+            samples
+                .chunks(channels)
+                .map(|p| 0.299 * p[0] + 0.587 * p[1] + 0.114 * p[2])
+                .collect(),
+        ),
+    }
+}
+
+/// Replicate the gray channel of each pixel of `tuple` into red, green and blue, keeping alpha.
+fn expand_gray<T: Copy>(samples: &[T], tuple: TupleType) -> Vec<T> {
+    let channels = usize::from(tuple.channels());
+    let expanded = usize::from(tuple.expanded().channels());
+    let mut result = Vec::with_capacity(samples.len() / channels * expanded);
+    for pixel in samples.chunks(channels) {
+        result.extend_from_slice(&[pixel[0], pixel[0], pixel[0]]);
+        result.extend_from_slice(&pixel[1..]);
+    }
+    result
+}
+
+/// Scale a sample from the range `0..=maxval` to `0..=full`, rounding to the nearest value.
+///
+/// The headers reject a maxval of 0, it is still treated as 1 rather than dividing by zero.
+fn scale_sample(value: u32, maxval: u32, full: u32) -> u32 {
+    let maxval = cmp::max(maxval, 1);
+    let scaled = (u64::from(value) * u64::from(full) + u64::from(maxval / 2)) / u64::from(maxval);
+    scaled.min(u64::from(full)) as u32
+}
+
+/// Write decoded samples as native endian bytes to the start of `buf`, returning the length.
+fn write_samples(samples: DecodingResult, buf: &mut [u8]) -> usize {
+    match samples {
+        DecodingResult::U8(samples) => {
+            ::copy_memory(&samples, &mut buf[..samples.len()]);
+            samples.len()
+        }
+        DecodingResult::U16(samples) => {
+            let len = samples.len() * 2;
+            NativeEndian::write_u16_into(&samples, &mut buf[..len]);
+            len
+        }
+        DecodingResult::F32(samples) => {
+            let len = samples.len() * 4;
+            let bits = samples.iter().map(|sample| sample.to_bits()).collect::<Vec<_>>();
+            NativeEndian::write_u32_into(&bits, &mut buf[..len]);
+            len
+        }
+    }
+}
+
+/// Cut the rectangle at `origin` of `size` pixels out of rows that are `width` pixels long.
+fn crop_samples(
+    samples: DecodingResult,
+    components: u32,
+    width: u32,
+    origin: (u32, u32),
+    size: (u32, u32),
+) -> DecodingResult {
+    fn crop<T: Copy>(samples: &[T], rowlen: usize, columns: Range<usize>, rows: Range<usize>)
+        -> Vec<T> {
+        samples[rows.start * rowlen..rows.end * rowlen]
+            .chunks(rowlen)
+            .flat_map(|row| row[columns.clone()].iter().cloned())
+            .collect()
+    }
+
+    let components = components as usize;
+    let rowlen = width as usize * components;
+    let columns = origin.0 as usize * components..(origin.0 + size.0) as usize * components;
+    let rows = origin.1 as usize..(origin.1 + size.1) as usize;
+    match samples {
+        DecodingResult::U8(samples) => DecodingResult::U8(crop(&samples, rowlen, columns, rows)),
+        DecodingResult::U16(samples) => DecodingResult::U16(crop(&samples, rowlen, columns, rows)),
+        DecodingResult::F32(samples) => DecodingResult::F32(crop(&samples, rowlen, columns, rows)),
+    }
+}
+
+/// Pack rows of bits stored in a byte each, starting each row at a byte boundary.
+fn pack_bits(bits: &[u8], width: u32) -> Vec<u8> {
+    bits.chunks(width as usize)
+        .flat_map(|row| row.chunks(8))
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0, |packed, (index, &bit)| packed | (bit << (7 - index)))
+        })
+        .collect()
+}
+
+/// Reverse the order of the rows of an image, each being `rowlen` samples long.
+fn flip_rows<T>(samples: &mut [T], rowlen: usize) {
+    let height = samples.len() / rowlen;
+    for row in 0..height / 2 {
+        let (top, bottom) = samples.split_at_mut((height - row - 1) * rowlen);
+        let top = &mut top[row * rowlen..(row + 1) * rowlen];
+        for (a, b) in top.iter_mut().zip(bottom[..rowlen].iter_mut()) {
+            ::std::mem::swap(a, b);
+        }
+    }
+}
+
+impl DecodableImageHeader for BitmapHeader {
+    fn tuple_type(&self) -> ImageResult<TupleType> {
+        Ok(TupleType::PbmBit)
+    }
+}
+
+impl DecodableImageHeader for GraymapHeader {
+    fn tuple_type(&self) -> ImageResult<TupleType> {
+        match self.maxwhite {
+            v if v <= 0xFF => Ok(TupleType::GrayU8),
+            v if v <= 0xFFFF => Ok(TupleType::GrayU16),
+            _ => Err(ImageError::FormatError(
+                "Image maxval is not less or equal to 65535".to_string(),
+            )),
+        }
+    }
+}
+
+impl DecodableImageHeader for PixmapHeader {
+    fn tuple_type(&self) -> ImageResult<TupleType> {
+        match self.maxval {
+            v if v <= 0xFF => Ok(TupleType::RGBU8),
+            v if v <= 0xFFFF => Ok(TupleType::RGBU16),
+            _ => Err(ImageError::FormatError(
+                "Image maxval is not less or equal to 65535".to_string(),
+            )),
+        }
+    }
+}
+
+impl DecodableImageHeader for FloatmapHeader {
+    fn tuple_type(&self) -> ImageResult<TupleType> {
+        match self.color {
+            FloatmapColor::Gray => Ok(TupleType::GrayF32),
+            FloatmapColor::RGB => Ok(TupleType::RGBF32),
+        }
+    }
+}
+
+impl DecodableImageHeader for ArbitraryHeader {
+    fn tuple_type(&self) -> ImageResult<TupleType> {
+        match self.tupltype {
+            None if self.depth == 1 && self.maxval <= 0xFF => Ok(TupleType::GrayU8),
+            None if self.depth == 1 && self.maxval <= 0xFFFF => Ok(TupleType::GrayU16),
+            None if self.depth == 2 && self.maxval <= 0xFF => Ok(TupleType::GrayAU8),
+            None if self.depth == 2 && self.maxval <= 0xFFFF => Ok(TupleType::GrayAU16),
+            None if self.depth == 3 && self.maxval <= 0xFF => Ok(TupleType::RGBU8),
+            None if self.depth == 3 && self.maxval <= 0xFFFF => Ok(TupleType::RGBU16),
+            None if self.depth == 4 && self.maxval <= 0xFF => Ok(TupleType::RGBAU8),
+            None if self.depth == 4 && self.maxval <= 0xFFFF => Ok(TupleType::RGBAU16),
+
+            Some(ArbitraryTuplType::BlackAndWhite) if self.depth != 1 => {
+                Err(ImageError::DimensionError)
+            }
+            Some(ArbitraryTuplType::BlackAndWhite) if self.maxval == 1 => Ok(TupleType::BWBit),
+            Some(ArbitraryTuplType::BlackAndWhite) => Err(ImageError::FormatError(
+                "Invalid maxval for tuple type BLACKANDWHITE".to_string(),
+            )),
+
+            Some(ArbitraryTuplType::Grayscale) if self.depth == 1 && self.maxval <= 0xFF => {
+                Ok(TupleType::GrayU8)
+            }
+            Some(ArbitraryTuplType::Grayscale) if self.depth <= 1 && self.maxval <= 0xFFFF => {
+                Ok(TupleType::GrayU16)
+            }
+            Some(ArbitraryTuplType::Grayscale) => Err(ImageError::DimensionError),
+
+            Some(ArbitraryTuplType::RGB) if self.depth == 3 && self.maxval <= 0xFF => {
+                Ok(TupleType::RGBU8)
+            }
+            Some(ArbitraryTuplType::RGB) if self.depth == 3 && self.maxval <= 0xFFFF => {
+                Ok(TupleType::RGBU16)
+            }
+            Some(ArbitraryTuplType::RGB) => Err(ImageError::DimensionError),
+
+            Some(ArbitraryTuplType::BlackAndWhiteAlpha) if self.depth != 2 => {
+                Err(ImageError::DimensionError)
+            }
+            Some(ArbitraryTuplType::BlackAndWhiteAlpha) if self.maxval == 1 => {
+                Ok(TupleType::BWAlphaBit)
+            }
+            Some(ArbitraryTuplType::BlackAndWhiteAlpha) => Err(ImageError::FormatError(
+                "Invalid maxval for tuple type BLACKANDWHITE_ALPHA".to_string(),
+            )),
+            Some(ArbitraryTuplType::GrayscaleAlpha) if self.depth == 2 && self.maxval <= 0xFF => {
+                Ok(TupleType::GrayAU8)
+            }
+            Some(ArbitraryTuplType::GrayscaleAlpha) if self.depth == 2 && self.maxval <= 0xFFFF => {
+                Ok(TupleType::GrayAU16)
+            }
+            Some(ArbitraryTuplType::GrayscaleAlpha) => Err(ImageError::DimensionError),
+            Some(ArbitraryTuplType::RGBAlpha) if self.depth == 4 && self.maxval <= 0xFF => {
+                Ok(TupleType::RGBAU8)
+            }
+            Some(ArbitraryTuplType::RGBAlpha) if self.depth == 4 && self.maxval <= 0xFFFF => {
+                Ok(TupleType::RGBAU16)
+            }
+            Some(ArbitraryTuplType::RGBAlpha) => Err(ImageError::DimensionError),
+
+            Some(ArbitraryTuplType::Custom(_)) if self.depth == 0 || self.depth > 0xFF => Err(
+                ImageError::FormatError("Depth of custom tuple type must be 1 to 255".to_string()),
+            ),
+            Some(ArbitraryTuplType::Custom(_)) if self.maxval <= 0xFF => {
+                Ok(TupleType::CustomU8(self.depth as u8))
+            }
+            Some(ArbitraryTuplType::Custom(_)) if self.maxval <= 0xFFFF => {
+                Ok(TupleType::CustomU16(self.depth as u8))
+            }
+            _ => Err(ImageError::FormatError(
+                "Tuple type not recognized".to_string(),
+            )),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
     use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::error::Error;
+    use std::rc::Rc;
     /// Tests reading of a valid blackandwhite pam
     #[test]
-    fn pam_blackandwhite() {
-        let pamdata = b"P7
-WIDTH 4
-HEIGHT 4
-DEPTH 1
-MAXVAL 1
-TUPLTYPE BLACKANDWHITE
-# Comment line
-ENDHDR
-\x01\x00\x00\x01\x01\x00\x00\x01\x01\x00\x00\x01\x01\x00\x00\x01";
-        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
-        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
-        assert_eq!(decoder.subtype(), PNMSubtype::ArbitraryMap);
+    fn pam_blackandwhite() {
+        let pamdata = b"P7
+WIDTH 4
+HEIGHT 4
+DEPTH 1
+MAXVAL 1
+TUPLTYPE BLACKANDWHITE
+# Comment line
+ENDHDR
+\x01\x00\x00\x01\x01\x00\x00\x01\x01\x00\x00\x01\x01\x00\x00\x01";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        assert_eq!(decoder.subtype(), PNMSubtype::ArbitraryMap);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(
+                data,
+                vec![
+                    0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x01,
+                    0x00, 0x00, 0x01,
+                ]
+            ),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Arbitrary(ArbitraryHeader {
+                            width: 4,
+                            height: 4,
+                            maxval: 1,
+                            depth: 1,
+                            tupltype: Some(ArbitraryTuplType::BlackAndWhite),
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    /// Tests reading of a valid grayscale pam
+    #[test]
+    fn pam_blackandwhite_alpha() {
+        let pamdata = b"P7\nWIDTH 2\nHEIGHT 2\nDEPTH 2\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE_ALPHA\n\
+                        ENDHDR\n\x01\x01\x00\x01\x01\x00\x00\x00";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::GrayA(1));
+        assert_eq!(decoder.channel_count(), 2);
+        assert_eq!(
+            decoder.read_image().unwrap().into_u8(),
+            Some(vec![1, 1, 0, 1, 1, 0, 0, 0])
+        );
+
+        let decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        match decoder.into_dynamic_image().unwrap() {
+            DynamicImage::ImageLumaA8(image) => {
+                assert_eq!(image.into_raw(), vec![255, 255, 0, 255, 255, 0, 0, 0])
+            }
+            _ => panic!("Decoded wrong image format"),
+        }
+
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        let rgba = decoder.read_image_rgba8().unwrap();
+        assert_eq!(&rgba[..8], &[255, 255, 255, 255, 0, 0, 0, 255]);
+
+        let mut encoded = Vec::new();
+        PNMEncoder::new(&mut encoded)
+            .encode(&[1u8, 1, 0, 1, 1, 0, 0, 0][..], 2, 2, ColorType::GrayA(1))
+            .unwrap();
+        let mut decoder = PNMDecoder::new(&encoded[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::GrayA(1));
+        assert_eq!(
+            decoder.read_image().unwrap().into_u8(),
+            Some(vec![1, 1, 0, 1, 1, 0, 0, 0])
+        );
+
+        let outofrange = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE_ALPHA\n\
+                           ENDHDR\n\x01\x02";
+        let mut decoder = PNMDecoder::new(&outofrange[..]).unwrap();
+        assert!(decoder.read_image().is_err());
+
+        let depth = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE_ALPHA\n\
+                      ENDHDR\n\x01";
+        match PNMDecoder::new(&depth[..]) {
+            Err(ImageError::DimensionError) => (),
+            _ => panic!("Accepted a BLACKANDWHITE_ALPHA pam of depth 1"),
+        }
+    }
+
+    #[test]
+    fn pam_grayscale() {
+        let pamdata = b"P7
+WIDTH 4
+HEIGHT 4
+DEPTH 1
+MAXVAL 255
+TUPLTYPE GRAYSCALE
+# Comment line
+ENDHDR
+\xde\xad\xbe\xef\xde\xad\xbe\xef\xde\xad\xbe\xef\xde\xad\xbe\xef";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        assert_eq!(decoder.subtype(), PNMSubtype::ArbitraryMap);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(
+                data,
+                vec![
+                    0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde,
+                    0xad, 0xbe, 0xef,
+                ]
+            ),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Arbitrary(ArbitraryHeader {
+                            width: 4,
+                            height: 4,
+                            depth: 1,
+                            maxval: 255,
+                            tupltype: Some(ArbitraryTuplType::Grayscale),
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    /// Tests reading of a valid rgb pam
+    #[test]
+    fn pam_rgb() {
+        let pamdata = b"P7
+# Comment line
+MAXVAL 255
+TUPLTYPE RGB
+DEPTH 3
+WIDTH 2
+HEIGHT 2
+ENDHDR
+\xde\xad\xbe\xef\xde\xad\xbe\xef\xde\xad\xbe\xef";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(8));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert_eq!(decoder.subtype(), PNMSubtype::ArbitraryMap);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(
+                data,
+                vec![
+                    0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef,
+                ]
+            ),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Arbitrary(ArbitraryHeader {
+                            maxval: 255,
+                            tupltype: Some(ArbitraryTuplType::RGB),
+                            depth: 3,
+                            width: 2,
+                            height: 2,
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    #[test]
+    fn pbm_binary() {
+        // The data contains two rows of the image (each line is padded to the full byte). For
+        // comments on its format, see documentation of `impl SampleType for PbmBit`.
+        let pbmbinary = [&b"P4 6 2\n"[..], &[0b01101100 as u8, 0b10110111]].concat();
+        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
+        assert_eq!(decoder.dimensions().unwrap(), (6, 2));
+        assert_eq!(
+            decoder.subtype(),
+            PNMSubtype::Bitmap(SampleEncoding::Binary)
+        );
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 0]),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Bitmap(BitmapHeader {
+                            encoding: SampleEncoding::Binary,
+                            width: 6,
+                            height: 2,
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn pbm_ascii() {
+        // The data contains two rows of the image (each line is padded to the full byte). For
+        // comments on its format, see documentation of `impl SampleType for PbmBit`.
+        let pbmbinary = b"P1 6 2\n 0 1 1 0 1 1\n1 0 1 1 0 1";
+        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
+        assert_eq!(decoder.dimensions().unwrap(), (6, 2));
+        assert_eq!(decoder.subtype(), PNMSubtype::Bitmap(SampleEncoding::Ascii));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 0]),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Bitmap(BitmapHeader {
+                            encoding: SampleEncoding::Ascii,
+                            width: 6,
+                            height: 2,
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    #[test]
+    fn pgm_binary() {
+        // The data contains two rows of the image (each line is padded to the full byte). For
+        // comments on its format, see documentation of `impl SampleType for PbmBit`.
+        let elements = (0..16).collect::<Vec<_>>();
+        let pbmbinary = [&b"P5 4 4 255\n"[..], &elements].concat();
+        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        assert_eq!(
+            decoder.subtype(),
+            PNMSubtype::Graymap(SampleEncoding::Binary)
+        );
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, elements),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Graymap(GraymapHeader {
+                            encoding: SampleEncoding::Binary,
+                            width: 4,
+                            height: 4,
+                            maxwhite: 255,
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn pgm_ascii() {
+        // The data contains two rows of the image (each line is padded to the full byte). For
+        // comments on its format, see documentation of `impl SampleType for PbmBit`.
+        let pbmbinary = b"P2 4 4 255\n 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15";
+        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        assert_eq!(
+            decoder.subtype(),
+            PNMSubtype::Graymap(SampleEncoding::Ascii)
+        );
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, (0..16).collect::<Vec<_>>()),
+        }
+        match decoder.into_inner() {
+            (
+                _,
+                PNMHeader {
+                    decoded:
+                        HeaderRecord::Graymap(GraymapHeader {
+                            encoding: SampleEncoding::Ascii,
+                            width: 4,
+                            height: 4,
+                            maxwhite: 255,
+                        }),
+                    encoded: _,
+                    comments: _,
+                },
+            ) => (),
+            _ => panic!("Decoded header is incorrect"),
+        }
+    }
+
+    #[test]
+    fn pgm_scanlines() {
+        let elements = (0..16).collect::<Vec<_>>();
+        let pgmbinary = [&b"P5 4 4 255\n"[..], &elements].concat();
+        let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        assert_eq!(decoder.row_len().unwrap(), 4);
+        let mut row = [0u8; 4];
+        for line in elements.chunks(4) {
+            assert_eq!(decoder.read_scanline(&mut row).unwrap(), 4);
+            assert_eq!(&row[..], line);
+        }
+        match decoder.read_scanline(&mut row) {
+            Err(ImageError::ImageEnd) => (),
+            other => panic!("Expected end of image, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn pgm_ascii_scanlines() {
+        let pgmascii = b"P2 4 2 255\n 0 1 2 3\n 4 5 6 7";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        let mut row = [0u8; 4];
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 4);
+        assert_eq!(row, [0, 1, 2, 3]);
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 4);
+        assert_eq!(row, [4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn pbm_scanlines() {
+        let pbmbinary = [&b"P4 6 2\n"[..], &[0b01101100 as u8, 0b10110111]].concat();
+        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        assert_eq!(decoder.row_len().unwrap(), 6);
+        let mut row = [0u8; 6];
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 6);
+        assert_eq!(row, [1, 0, 0, 1, 0, 0]);
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 6);
+        assert_eq!(row, [0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn pgm_u16_scanlines() {
+        let pgmbinary = [&b"P5 2 1 65535\n"[..], &[0x12, 0x34, 0xbe, 0xaf]].concat();
+        let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        assert_eq!(decoder.row_len().unwrap(), 4);
+        let mut row = [0u8; 4];
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 4);
+        let mut samples = [0u16; 2];
+        NativeEndian::read_u16_into(&row, &mut samples);
+        assert_eq!(samples, [0x1234, 0xbeaf]);
+    }
+
+    #[test]
+    fn truncated_scanline() {
+        let pgmbinary = [&b"P5 4 2 255\n"[..], &[0, 1, 2, 3, 4, 5]].concat();
+        let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        let mut row = [0u8; 4];
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 4);
+        match decoder.read_scanline(&mut row) {
+            Err(ImageError::PnmError(PnmError::Truncated {
+                expected: 4,
+                got: 2,
+            })) => (),
+            other => panic!("Expected truncation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn ppm_u16_colortype() {
+        let samples = [0xff, 0xff, 0x12, 0x34, 0x00, 0x01, 0xbe, 0xaf, 0x00, 0x00, 0x80, 0x00];
+        let ppmbinary = [&b"P6 2 1 65535\n"[..], &samples].concat();
+        let mut decoder = PNMDecoder::new(&ppmbinary[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U16(data) => {
+                assert_eq!(data.len(), 6);
+                assert_eq!(data, vec![0xffff, 0x1234, 0x0001, 0xbeaf, 0x0000, 0x8000]);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn pbm_ascii_contiguous_bits() {
+        let pbmascii = b"P1 6 3\n011011\n1 0 1101\n 0110 1 1";
+        let mut decoder = PNMDecoder::new(&pbmascii[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(
+                data,
+                vec![1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 0, 1, 0, 0, 1, 0, 0]
+            ),
+        }
+    }
+
+    #[test]
+    fn pbm_ascii_invalid_bit() {
+        let pbmascii = b"P1 3 1\n012";
+        let mut decoder = PNMDecoder::new(&pbmascii[..]).unwrap();
+        assert!(decoder.read_image().is_err());
+    }
+
+    #[test]
+    fn pam_blank_lines() {
+        let pamdata = b"P7
+
+WIDTH 2
+
+HEIGHT 2
+\t
+DEPTH 1
+MAXVAL 255
+
+ENDHDR
+\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 4]),
+        }
+    }
+
+    #[test]
+    fn pam_header_eof() {
+        let pamdata = b"P7\nWIDTH 2\nHEIGHT 2\n";
+        assert!(PNMDecoder::new(&pamdata[..]).is_err());
+    }
+
+    #[test]
+    fn pam_leading_whitespace() {
+        let pamdata = b"P7
+  WIDTH 4
+\tHEIGHT 1
+ DEPTH 1
+MAXVAL 255
+ENDHDR
+\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (4, 1));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) | DecodingResult::F32(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 4]),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn overflowing_dimensions() {
+        let ppmbinary = b"P6 4294967295 4294967295 65535\n\x00\x00";
+        let mut decoder = PNMDecoder::new(&ppmbinary[..]).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("Expected overflow error, got {:?}", other),
+        }
+        let max = u32::max_value();
+        for result in &[
+            U8::bytelen(max, max, 3),
+            U16::<BigEndian>::bytelen(max, max, 1),
+            PbmBit::bytelen(max, max, 9),
+        ] {
+            match *result {
+                Err(ImageError::DimensionError) => (),
+                ref other => panic!("Expected dimension error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn zero_dimensions() {
+        for data in &[
+            &b"P5 0 4 255\n"[..],
+            &b"P5 4 0 255\n"[..],
+            &b"P4 0 1\n"[..],
+            &b"P7\nWIDTH 0\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nENDHDR\n"[..],
+        ] {
+            match PNMDecoder::new(&data[..]) {
+                Err(ImageError::DimensionError) => (),
+                Err(err) => panic!("Unexpected error {:?}", err),
+                Ok(_) => panic!("Decoded image with zero dimension"),
+            }
+        }
+    }
+
+    #[test]
+    fn zero_maxval() {
+        for data in &[&b"P5 4 4 0\n"[..], &b"P3 1 1 0\n0 0 0"[..]] {
+            match PNMDecoder::new(&data[..]) {
+                Err(ImageError::FormatError(msg)) => {
+                    assert_eq!(msg, "maxval must be between 1 and 65535 at byte 7")
+                }
+                Err(err) => panic!("Unexpected error {:?}", err),
+                Ok(_) => panic!("Decoded image with zero maxval"),
+            }
+        }
+
+        let options = PnmDecoderOptions::new().scale_to_full(true);
+        for maxval in &["0", "65536"] {
+            let pam = format!(
+                "P7\nWIDTH 2\nHEIGHT 1\nDEPTH 1\nMAXVAL {}\nTUPLTYPE GRAYSCALE\nENDHDR\n\0\0",
+                maxval
+            );
+            match options.read(pam.as_bytes()) {
+                Err(ImageError::FormatError(msg)) => assert_eq!(
+                    msg,
+                    "maxval must be between 1 and 65535 on line 5 at byte 28"
+                ),
+                Err(err) => panic!("Unexpected error {:?}", err),
+                Ok(_) => panic!("Decoded pam with maxval {}", maxval),
+            }
+        }
+        assert_eq!(scale_sample(0, 0, 0xFF), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn u16_mismatched_length() {
+        assert!(U16::<BigEndian>::from_bytes(&[0, 1, 2], 1, 1, 2).is_err());
+        assert!(U16::<BigEndian>::from_bytes(&[0, 1, 2, 3], 3, 1, 1).is_err());
+        let samples = U16::<BigEndian>::from_bytes(&[0, 1, 2, 3], 2, 1, 1).unwrap();
+        assert_eq!(samples, vec![0x0001, 0x0203]);
+        let samples = U16::<LittleEndian>::from_bytes(&[0, 1, 2, 3], 2, 1, 1).unwrap();
+        assert_eq!(samples, vec![0x0100, 0x0302]);
+    }
+
+    #[test]
+    fn single_raster_separator() {
+        for &(separator, ref expected) in &[
+            (&b"\n\n"[..], [b'\n', 0, 1, 2]),
+            (&b" "[..], [0, 1, 2, 3]),
+            (&b"  "[..], [b' ', 0, 1, 2]),
+            (&b"\r\n"[..], [b'\n', 0, 1, 2]),
+        ] {
+            let pgmbinary = [&b"P5 2 2 255"[..], separator, &[0, 1, 2, 3]].concat();
+            let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+            match decoder.read_image().unwrap() {
+                DecodingResult::U16(_) | DecodingResult::F32(_) => {
+                    panic!("Decoded wrong image format")
+                }
+                DecodingResult::U8(data) => assert_eq!(&data[..], &expected[..]),
+            }
+        }
+    }
+
+    fn floatmap_bytes<E: ByteOrder>(magic: &[u8], scale: &[u8], samples: &[f32]) -> Vec<u8> {
+        let bits = samples.iter().map(|sample| sample.to_bits()).collect::<Vec<_>>();
+        let mut raster = vec![0; bits.len() * 4];
+        E::write_u32_into(&bits, &mut raster);
+        [magic, &b"\n2 2\n"[..], scale, &b"\n"[..], &raster].concat()
+    }
+
+    #[test]
+    fn pfm_gray_big_endian() {
+        // Rows are stored bottom to top.
+        let stored = [0.5, -1.25, 0.0, 1e10];
+        let data = floatmap_bytes::<BigEndian>(b"Pf", b"1.0", &stored);
+        let mut decoder = PNMDecoder::new(&data[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(32));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert_eq!(decoder.subtype(), PNMSubtype::Floatmap(FloatmapColor::Gray));
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(data) => assert_eq!(data, vec![0.0, 1e10, 0.5, -1.25]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pfm_rgb_little_endian() {
+        let stored = [
+            0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0, 1.1,
+        ];
+        let data = floatmap_bytes::<LittleEndian>(b"PF", b"-4.0", &stored);
+        let mut decoder = PNMDecoder::new(&data[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(32));
+        assert_eq!(decoder.subtype(), PNMSubtype::Floatmap(FloatmapColor::RGB));
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(data) => assert_eq!(
+                data,
+                vec![0.6, 0.7, 0.8, 0.9, 1.0, 1.1, 0.0, 0.1, 0.2, 0.3, 0.4, 0.5]
+            ),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        let (_, header) = decoder.into_inner();
+        assert_eq!(header.as_floatmap().unwrap().scale, -4.0);
+    }
+
+    #[test]
+    fn pfm_endianness_differs() {
+        let stored = [1.0, 2.0, 3.0, 4.0];
+        let big = floatmap_bytes::<BigEndian>(b"Pf", b"1", &stored);
+        let mut swapped = floatmap_bytes::<BigEndian>(b"Pf", b"-1", &stored);
+        match PNMDecoder::new(&big[..]).unwrap().read_image().unwrap() {
+            DecodingResult::F32(data) => assert_eq!(data, vec![3.0, 4.0, 1.0, 2.0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        match PNMDecoder::new(&swapped[..]).unwrap().read_image().unwrap() {
+            DecodingResult::F32(data) => assert_ne!(data, vec![3.0, 4.0, 1.0, 2.0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        swapped = floatmap_bytes::<LittleEndian>(b"Pf", b"-1", &stored);
+        match PNMDecoder::new(&swapped[..]).unwrap().read_image().unwrap() {
+            DecodingResult::F32(data) => assert_eq!(data, vec![3.0, 4.0, 1.0, 2.0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pfm_header_roundtrip() {
+        let data = floatmap_bytes::<LittleEndian>(b"PF", b"-1.0", &[0.0; 12]);
+        let mut decoder = PNMDecoder::new(&data[..]).unwrap();
+        decoder.read_image().unwrap();
+        let (_, header) = decoder.into_inner();
+        let mut written = Vec::new();
+        header.write(&mut written).unwrap();
+        assert_eq!(&written[..], &b"PF\n2 2\n-1.0\n"[..]);
+    }
+
+    #[test]
+    fn pfm_invalid_scale() {
+        assert!(PNMDecoder::new(&b"Pf\n1 1\n0.0\n\0\0\0\0"[..]).is_err());
+        assert!(PNMDecoder::new(&b"Pf\n1 1\nabc\n\0\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn pam_rgb_alpha() {
+        let pamdata = b"P7
+WIDTH 2
+HEIGHT 1
+DEPTH 4
+MAXVAL 255
+TUPLTYPE RGB_ALPHA
+ENDHDR
+\xde\xad\xbe\xef\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+        assert_eq!(decoder.row_len().unwrap(), 8);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => {
+                assert_eq!(data, vec![0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0x03, 0x04])
+            }
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn pam_rgb_alpha_u16() {
+        let pamdata = b"P7
+WIDTH 1
+HEIGHT 1
+DEPTH 4
+MAXVAL 65535
+TUPLTYPE RGB_ALPHA
+ENDHDR
+\xde\xad\xbe\xef\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0xdead, 0xbeef, 0x0102, 0x0304]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pam_rgb_alpha_wrong_depth() {
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n";
+        assert!(PNMDecoder::new(&pamdata[..]).is_err());
+
+        for &(depth, tupltype) in &[
+            (3, "RGB_ALPHA"),
+            (4, "RGB"),
+            (2, "BLACKANDWHITE"),
+            (3, "GRAYSCALE"),
+            (1, "GRAYSCALE_ALPHA"),
+        ] {
+            let pam = format!(
+                "P7\nWIDTH 1\nHEIGHT 1\nDEPTH {}\nMAXVAL 1\nTUPLTYPE {}\nENDHDR\n",
+                depth, tupltype
+            );
+            match PNMDecoder::new(pam.as_bytes()) {
+                Err(ImageError::DimensionError) => (),
+                other => panic!(
+                    "Expected a dimension error for {}, got {:?}",
+                    tupltype,
+                    other.err()
+                ),
+            }
+        }
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nTUPLTYPE BLACKANDWHITE\nENDHDR\n";
+        match PNMDecoder::new(&pam[..]) {
+            Err(ImageError::FormatError(_)) => (),
+            other => panic!("Expected a format error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn pam_grayscale_alpha() {
+        let pamdata = b"P7
+WIDTH 2
+HEIGHT 2
+DEPTH 2
+MAXVAL 255
+TUPLTYPE GRAYSCALE_ALPHA
+ENDHDR
+\xde\xad\xbe\xef\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::GrayA(8));
+        assert_eq!(decoder.row_len().unwrap(), 4);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => {
+                assert_eq!(data, vec![0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0x03, 0x04])
+            }
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn pam_grayscale_alpha_u16() {
+        let pamdata = b"P7
+WIDTH 2
+HEIGHT 1
+DEPTH 2
+MAXVAL 1023
+TUPLTYPE GRAYSCALE_ALPHA
+ENDHDR
+\x03\xff\x00\x00\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::GrayA(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0x03ff, 0x0000, 0x0102, 0x0304]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pam_implicit_grayscale_alpha() {
+        let pamdata = b"P7
+WIDTH 2
+HEIGHT 1
+DEPTH 2
+MAXVAL 255
+ENDHDR
+\x10\xff\x20\x80";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::GrayA(8));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0x10, 0xff, 0x20, 0x80]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn pam_implicit_rgb_alpha() {
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nENDHDR\n\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 4]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 65535\nENDHDR\n\
+                        \x00\x01\x00\x02\x00\x03\x00\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![1, 2, 3, 4]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 1023\nENDHDR\n\x03\xff\x00\x10";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::GrayA(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![1023, 16]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 65535\nENDHDR\n\x12\x34";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(16));
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn pam_custom_tupltype() {
+        let pamdata = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nTUPLTYPE SPECTRAL\nENDHDR\n\
+                        \x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.channel_count(), 5);
+        assert!(decoder.colortype().is_err());
+        assert_eq!(decoder.row_len().unwrap(), 10);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 4095\nTUPLTYPE SPECTRAL\nENDHDR\n\
+                        \x00\x01\x00\x02\x00\x03\x00\x04\x0f\xff";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.channel_count(), 5);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![1, 2, 3, 4, 4095]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pamdata = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 0\nMAXVAL 255\nTUPLTYPE SPECTRAL\nENDHDR\n";
+        assert!(PNMDecoder::new(&pamdata[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn scale_to_full() {
+        let pgmascii = b"P2 2 2 100\n0 50 99 100";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        decoder.set_scale_to_full(true);
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0, 128, 252, 255]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pgmascii = b"P2 2 1 1023\n512 1023";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        decoder.set_scale_to_full(true);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![32800, 65535]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pgmascii = b"P2 2 2 100\n0 50 99 100";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0, 50, 99, 100]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn transcode() {
+        let pgmascii = b"P2\n# gray\n3 2 1000\n0 1 2\n999 1000 500";
+        let decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        let mut pgmbinary = Vec::new();
+        decoder
+            .transcode(&mut pgmbinary, SampleEncoding::Binary)
+            .unwrap();
+        assert!(pgmbinary.starts_with(b"P5\n"));
+        let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        assert_eq!(decoder.subtype(), PNMSubtype::Graymap(SampleEncoding::Binary));
+        assert_eq!(decoder.maxval(), Some(1000));
+        assert_eq!(decoder.header().comments(), &["gray".to_string()]);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0, 1, 2, 999, 1000, 500]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let ppmascii = b"P3 2 1 100\n1 2 3 100 50 0";
+        let decoder = PNMDecoder::new(&ppmascii[..]).unwrap();
+        let mut ppmbinary = Vec::new();
+        decoder
+            .transcode(&mut ppmbinary, SampleEncoding::Binary)
+            .unwrap();
+        let mut decoder = PNMDecoder::new(&ppmbinary[..]).unwrap();
+        assert_eq!(decoder.subtype(), PNMSubtype::Pixmap(SampleEncoding::Binary));
+        assert_eq!(decoder.maxval(), Some(100));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 100, 50, 0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let decoder = PNMDecoder::new(&ppmbinary[..]).unwrap();
+        let mut ppmascii = Vec::new();
+        decoder
+            .transcode(&mut ppmascii, SampleEncoding::Ascii)
+            .unwrap();
+        let mut decoder = PNMDecoder::new(&ppmascii[..]).unwrap();
+        assert_eq!(decoder.subtype(), PNMSubtype::Pixmap(SampleEncoding::Ascii));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 100, 50, 0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pbmascii = b"P1 3 1\n1 0 1";
+        let decoder = PNMDecoder::new(&pbmascii[..]).unwrap();
+        let mut pbmbinary = Vec::new();
+        decoder
+            .transcode(&mut pbmbinary, SampleEncoding::Binary)
+            .unwrap();
+        assert_eq!(pbmbinary, b"P4\n3 1\n\xA0");
+
+        let decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        let mut pbmascii = Vec::new();
+        decoder
+            .transcode(&mut pbmascii, SampleEncoding::Ascii)
+            .unwrap();
+        assert!(pbmascii.starts_with(b"P1\n3 1\n1 0 1"));
+        let mut decoder = PNMDecoder::new(&pbmascii[..]).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![0, 1, 0]));
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nENDHDR\n\x00";
+        let decoder = PNMDecoder::new(&pam[..]).unwrap();
+        match decoder.transcode(Vec::new(), SampleEncoding::Binary) {
+            Err(ImageError::UnsupportedError(_)) => (),
+            other => panic!("Expected unsupported error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn grayscale() {
+        let ppm = b"P6 4 1 255\n\xFF\x00\x00\x00\xFF\x00\x00\x00\xFF\x10\x80\xF0";
+        let options = PnmDecoderOptions::new().grayscale(true);
+        let mut decoder = options.read(&ppm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.row_len().unwrap(), 4);
+        assert_eq!(decoder.channel_count(), 1);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![76, 150, 29, 107]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let mut decoder = PNMDecoder::new(&ppm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(8));
+        assert_eq!(decoder.row_len().unwrap(), 12);
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 65535\nTUPLTYPE RGB_ALPHA\nENDHDR\n\
+                    \xFF\xFF\xFF\xFF\xFF\xFF\x00\x00";
+        let mut decoder = options.read(&pam[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![65535]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pgm = b"P5 1 1 255\n\x07";
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![7]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn expand_rgb() {
+        let pgm = b"P5 3 1 255\n\x00\x80\xFF";
+        let options = PnmDecoderOptions::new().expand_rgb(true);
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(8));
+        assert_eq!(decoder.row_len().unwrap(), 9);
+        let data = match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => data,
+            other => panic!("Decoded wrong image format {:?}", other),
+        };
+        assert_eq!(data.len(), 9);
+        for (pixel, &gray) in data.chunks(3).zip(&[0x00, 0x80, 0xFF]) {
+            assert_eq!(pixel, &[gray, gray, gray]);
+        }
+
+        let pgm = b"P5 1 1 1000\n\x03\xE7";
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![999, 999, 999]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\n\
+                    ENDHDR\n\x10\x20";
+        let mut decoder = options.read(&pam[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0x10, 0x10, 0x10, 0x20]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pbm = b"P4 1 1\n\x80";
+        let mut decoder = options.read(&pbm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn downscale() {
+        let pgm = b"P5 5 1 65535\n\x00\x00\x00\x80\x7F\xFF\x80\x00\xFF\xFF";
+        let mut decoder = PnmDecoderOptions::new()
+            .downscale(Downscale::Round)
+            .read(&pgm[..])
+            .unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.row_len().unwrap(), 5);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0, 0, 127, 128, 255]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let mut decoder = PnmDecoderOptions::new()
+            .downscale(Downscale::Truncate)
+            .read(&pgm[..])
+            .unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0, 0, 127, 127, 255]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pgm = b"P5 1 1 255\n\x80";
+        let mut decoder = PnmDecoderOptions::new()
+            .downscale(Downscale::Round)
+            .read(&pgm[..])
+            .unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0x80]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn upscale() {
+        let pgm = b"P5 3 1 255\n\x00\x80\xFF";
+        let options = PnmDecoderOptions::new().upscale(true);
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(16));
+        assert_eq!(decoder.row_len().unwrap(), 6);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0, 32896, 65535]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pbm = b"P4 1 1\n\x80";
+        let mut decoder = options.read(&pbm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    /// Endless input starting with `prefix`, counting the bytes handed out.
+    struct Endless {
+        prefix: Vec<u8>,
+        fill: u8,
+        read: Rc<Cell<u64>>,
+    }
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let offset = self.read.get() as usize;
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = *self.prefix.get(offset + i).unwrap_or(&self.fill);
+            }
+            self.read.set((offset + buf.len()) as u64);
+            Ok(buf.len())
+        }
+    }
+
+    fn endless(prefix: &[u8], fill: u8) -> (Endless, Rc<Cell<u64>>) {
+        let read = Rc::new(Cell::new(0));
+        let endless = Endless {
+            prefix: prefix.to_vec(),
+            fill,
+            read: read.clone(),
+        };
+        (endless, read)
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn max_input() {
+        let options = PnmDecoderOptions::new().max_input(1 << 16);
+
+        // A binary raster is read exactly, so the following frame starts right after it.
+        let (input, read) = endless(b"P5 4 4 255\n0123456789abcdefP5 1 1 255\n", b'x');
+        let mut decoder = options.read(input).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, b"0123456789abcdef".to_vec()),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        let mut decoder = decoder.next_frame().unwrap().unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, b"x".to_vec()),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        assert!(read.get() < 1 << 17);
+
+        let (input, _) = endless(b"P5 1000 1000 255\n", 7);
+        match options.read(input) {
+            Err(ImageError::LimitError) => (),
+            other => panic!("Expected a limit error, got {:?}", other.err()),
+        }
+
+        // A sample of endless digits.
+        let (input, read) = endless(b"P2 1 1 255\n", b'1');
+        let mut decoder = options.read(input).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::LimitError) => (),
+            other => panic!("Expected a limit error, got {:?}", other),
+        }
+        assert!(read.get() < 1 << 17);
+
+        // Endless whitespace in an ascii bitmap.
+        let (input, _) = endless(b"P1 2 1\n1", b' ');
+        let mut decoder = options.read(input).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::LimitError) => (),
+            other => panic!("Expected a limit error, got {:?}", other),
+        }
+
+        // Endless whitespace in headers.
+        for &fill in &[b' ', b'\n'] {
+            let (input, read) = endless(b"P5 1", fill);
+            match options.read(input) {
+                Err(ImageError::LimitError) => (),
+                other => panic!("Expected a limit error, got {:?}", other.err()),
+            }
+            assert!(read.get() < 1 << 17);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn trailing_data() {
+        let pgmbinary = b"P5 2 1 255\n\x01\x02\nmetadata";
+        let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        let (mut reader, _) = decoder.into_inner();
+        let mut trailing = Vec::new();
+        reader.read_to_end(&mut trailing).unwrap();
+        assert_eq!(trailing, b"\nmetadata");
+
+        let pgmascii = b"P2 2 1 255\n1 2\n\nmetadata";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        decoder.read_image().unwrap();
+        let (mut reader, _) = decoder.into_inner();
+        let mut trailing = Vec::new();
+        reader.read_to_end(&mut trailing).unwrap();
+        assert_eq!(trailing, b"\nmetadata");
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn crlf_ppm() {
+        let ppmascii = b"P3\r\n# created on windows\r\n2 1\r\n255\r\n1 2 3\r\n4 5 6\r\n";
+        let mut decoder = PNMDecoder::new(&ppmascii[..]).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 1));
+        assert_eq!(decoder.maxval(), Some(255));
+        assert_eq!(
+            decoder.header().comments(),
+            &["created on windows".to_string()]
+        );
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3, 4, 5, 6]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn crlf_pam() {
+        let pam = b"P7\r\nWIDTH 2\r\nHEIGHT 1\r\nDEPTH 1\r\nMAXVAL 255\r\n# windows\r\n\r\n\
+                    TUPLTYPE GRAYSCALE\r\nENDHDR\r\n\x0A\x0D";
+        let mut decoder = PNMDecoder::new(&pam[..]).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 1));
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.header().comments(), &["windows".to_string()]);
+        match decoder.header().as_arbitrary().and_then(|pam| pam.tupltype.as_ref()) {
+            Some(&ArbitraryTuplType::Grayscale) => (),
+            other => panic!("Unexpected tuple type {:?}", other),
+        }
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0x0A, 0x0D]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn trailing_comment() {
+        let pgmascii = b"P2 2 1 255\n1 2\n# trailing comment";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+        assert!(decoder.next_frame().unwrap().is_none());
+
+        let pgmascii = b"P2 1 1 255 7 # first\n#second # still second\r\nP2 1 1 255 8";
+        let decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        let mut decoder = decoder.next_frame().unwrap().unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![8]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pbmbinary = b"P4 1 1\n\x80\n#";
+        let decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn tupltype_case() {
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE Rgb_Alpha\nENDHDR\n\
+                    \x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pam[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+        match decoder.header().as_arbitrary().and_then(|pam| pam.tupltype.as_ref()) {
+            Some(&ArbitraryTuplType::RGBAlpha) => (),
+            other => panic!("Unexpected tuple type {:?}", other),
+        }
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nTUPLTYPE Depth_Map\nENDHDR\n\x01";
+        let decoder = PNMDecoder::new(&pam[..]).unwrap();
+        match decoder.header().as_arbitrary().and_then(|pam| pam.tupltype.as_ref()) {
+            Some(&ArbitraryTuplType::Custom(ref name)) => assert_eq!(name, "Depth_Map"),
+            other => panic!("Unexpected tuple type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let ppm = b"\xEF\xBB\xBF\r\n P6 1 1 255\n\x01\x02\x03";
+        match PNMDecoder::new(&ppm[..]) {
+            Err(ImageError::PnmError(PnmError::BadMagic(_))) => (),
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Accepted a byte order mark in strict mode"),
+        }
+
+        let mut decoder = PNMDecoder::new_lenient(&ppm[..]).unwrap();
+        assert_eq!(decoder.subtype(), PNMSubtype::Pixmap(SampleEncoding::Binary));
+        assert_eq!(decoder.header().encoded(), Some(&b"P6 1 1 255\n"[..]));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let ppm = b"  P6 1 1 255\n\x01\x02\x03";
+        assert!(PNMDecoder::new(&ppm[..]).is_err());
+        assert!(PNMDecoder::new_lenient(&ppm[..]).is_ok());
+
+        let broken = b"\xEF\xBBP6 1 1 255\n\x01\x02\x03";
+        assert!(PNMDecoder::new_lenient(&broken[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn read_image_with_progress() {
+        let mut pgm = b"P5 1024 3000 255\n".to_vec();
+        pgm.extend((0..1024 * 3000).map(|i| (i / 1024) as u8));
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        let mut calls = Vec::new();
+        let data = decoder
+            .read_image_with_progress(|read, total| calls.push((read, total)))
+            .unwrap();
+        assert_eq!(data.as_u8(), Some(&pgm[17..]));
+        assert!(calls.len() > 1);
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(calls.iter().all(|&(_, total)| total == 1024 * 3000));
+        assert_eq!(calls.last(), Some(&(1024 * 3000, 1024 * 3000)));
+
+        let pgmascii = b"P2 2 2 255\n1 2\n3 4";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        let mut calls = Vec::new();
+        let data = decoder
+            .read_image_with_progress(|read, total| calls.push((read, total)))
+            .unwrap();
+        assert_eq!(data.as_u8(), Some(&[1, 2, 3, 4][..]));
+        assert_eq!(calls, vec![(4, 4)]);
+
+        let pfm = b"Pf 1 2 -1\n\x00\x00\x80\x3f\x00\x00\x00\x40";
+        let mut decoder = PNMDecoder::new(&pfm[..]).unwrap();
+        match decoder.read_image_with_progress(|_, _| ()).unwrap() {
+            DecodingResult::F32(data) => assert_eq!(data, vec![2.0, 1.0]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    /// Sets a flag once more than `after` bytes have been read.
+    struct CancelAfter<'a> {
+        data: &'a [u8],
+        after: usize,
+        cancel: &'a AtomicBool,
+    }
+
+    impl<'a> Read for CancelAfter<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.data.read(buf)?;
+            self.after = self.after.saturating_sub(read);
+            if self.after == 0 {
+                self.cancel.store(true, Ordering::SeqCst);
+            }
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn read_image_cancellable() {
+        let mut pgm = b"P5 1024 3000 255\n".to_vec();
+        pgm.extend(vec![0; 1024 * 3000]);
+        let cancel = AtomicBool::new(false);
+        let input = CancelAfter {
+            data: &pgm,
+            after: 1 << 20,
+            cancel: &cancel,
+        };
+        let mut decoder = PNMDecoder::new(input).unwrap();
+        match decoder.read_image_cancellable(&cancel) {
+            Err(ImageError::Cancelled) => (),
+            other => panic!("Expected a cancelled decode, got {:?}", other),
+        }
+        let (reader, _) = decoder.into_inner();
+        assert!(reader.get_ref().data.len() > 1 << 20);
+
+        let cancel = AtomicBool::new(false);
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        let data = decoder.read_image_cancellable(&cancel).unwrap();
+        assert_eq!(data.len(), 1024 * 3000);
+
+        let cancel = AtomicBool::new(true);
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert!(decoder.read_image_cancellable(&cancel).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn structured_errors() {
+        match PNMDecoder::new(&b"P9 1 1 255\n\x00"[..]) {
+            Err(ImageError::PnmError(PnmError::BadMagic(magic))) => assert_eq!(&magic, b"P9"),
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Accepted a bad magic constant"),
+        }
+
+        let pgmascii = b"P2 2 1 255\n1 256";
+        match PNMDecoder::new(&pgmascii[..]).unwrap().read_image() {
+            Err(ImageError::PnmError(PnmError::SampleOutOfRange { value, maxval })) => {
+                assert_eq!((value, maxval), (256, 255))
+            }
+            other => panic!("Expected a sample out of range, got {:?}", other),
+        }
+
+        let pbmascii = b"P1 2 1\n1 2";
+        match PNMDecoder::new(&pbmascii[..]).unwrap().read_image() {
+            Err(ImageError::FormatError(_)) => (),
+            other => panic!("Expected a format error, got {:?}", other),
+        }
+
+        let pambw = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 1\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE\nENDHDR\n\
+                      \x01\x05";
+        match PNMDecoder::new(&pambw[..]).unwrap().read_image() {
+            Err(ImageError::PnmError(PnmError::SampleOutOfRange { value, maxval })) => {
+                assert_eq!((value, maxval), (5, 1))
+            }
+            other => panic!("Expected a sample out of range, got {:?}", other),
+        }
+
+        let err = ImageError::from(PnmError::BadMagic(*b"P8"));
+        assert!(err.source().unwrap().downcast_ref::<PnmError>().is_some());
+        let err = ImageError::from(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+        assert!(err.source().unwrap().downcast_ref::<io::Error>().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn decode_slice() {
+        let pgm = b"P5 2 2 255\n\x01\x02\x03\x04trailing";
+        let (header, samples) = PNMDecoder::decode_slice(&pgm[..]).unwrap();
+        assert_eq!((header.width(), header.height()), (2, 2));
+        assert_eq!(&*samples, &[1, 2, 3, 4]);
+        match samples {
+            Cow::Borrowed(raster) => assert_eq!(raster.as_ptr(), pgm[11..].as_ptr()),
+            Cow::Owned(_) => panic!("Copied the raster"),
+        }
+
+        let pgmascii = b"P2 2 1 255\n1 2";
+        match PNMDecoder::decode_slice(&pgmascii[..]).unwrap().1 {
+            Cow::Owned(samples) => assert_eq!(samples, vec![1, 2]),
+            Cow::Borrowed(_) => panic!("Borrowed an ascii raster"),
+        }
+
+        let pbm = b"P4 2 1\n\x40";
+        assert_eq!(&*PNMDecoder::decode_slice(&pbm[..]).unwrap().1, &[1, 0]);
+
+        let truncated = b"P5 2 2 255\n\x01\x02";
+        match PNMDecoder::decode_slice(&truncated[..]) {
+            Err(ImageError::PnmError(PnmError::Truncated { expected: 4, got: 2 })) => (),
+            other => panic!("Expected a truncation error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn from_buf_read() {
+        use std::io::Cursor;
+
+        let pgm = b"P5 2 1 255\n\x01\x02trailing".to_vec();
+        let mut decoder: PNMDecoder<Cursor<Vec<u8>>, Cursor<Vec<u8>>> =
+            PNMDecoder::from_buf_read(Cursor::new(pgm)).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![1, 2]));
+
+        // The cursor is used directly, so it is positioned right after the raster.
+        let (cursor, _) = decoder.into_inner();
+        assert_eq!(cursor.position(), 13);
+    }
+
+    #[test]
+    #[cfg(not(feature = "pnm_u16"))]
+    fn u16_unsupported() {
+        let pgm = b"P5 1 1 65535\n\x01\x02";
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(16));
+        match decoder.read_image() {
+            Err(ImageError::UnsupportedError(ref msg)) => {
+                assert_eq!(msg, "16-bit support not compiled in")
+            }
+            other => panic!("Expected an unsupported error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "pnm_ascii"))]
+    fn ascii_unsupported() {
+        for data in &[&b"P1 1 1 0"[..], &b"P2 1 1 255 0"[..], &b"P3 1 1 255 0 0 0"[..]] {
+            let mut decoder = PNMDecoder::new(&data[..]).unwrap();
+            match decoder.read_image() {
+                Err(ImageError::UnsupportedError(ref msg)) => {
+                    assert_eq!(msg, "ASCII PNM not supported in this build")
+                }
+                other => panic!("Expected an unsupported error, got {:?}", other.err()),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn tracing_events() {
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Records the fields of all events as `name=value` lines.
+        struct Capture(Arc<Mutex<Vec<String>>>);
+
+        struct Fields(Vec<String>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &fmt::Debug) {
+                self.0.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+            fn new_span(&self, _: &Attributes) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event) {
+                let mut fields = Fields(Vec::new());
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0.join(" "));
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let pgm = b"P5 3 2 200\n\x01\x02\x03\x04\x05\x06";
+        ::tracing::subscriber::with_default(Capture(events.clone()), || {
+            let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+            decoder.read_image().unwrap();
+        });
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                "message=pnm magic parsed subtype=Graymap(Binary)",
+                "message=pnm header parsed width=3 height=2 subtype=Graymap(Binary) maxval=200",
+                "message=pnm allocating raster bytes=6",
+                "message=pnm decode complete",
+            ]
+        );
+    }
+
+    #[test]
+    fn send_sync() {
+        use std::io::Cursor;
+
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<PNMDecoder<Cursor<Vec<u8>>>>();
+        assert_sync::<PNMDecoder<Cursor<Vec<u8>>>>();
+        assert_send::<PNMDecoder<BufReader<::std::fs::File>>>();
+        assert_send::<PnmRows<Cursor<Vec<u8>>>>();
+
+        let pgm = b"P5 2 1 255\n\x01\x02".to_vec();
+        let decoder = PNMDecoder::from_buf_read(Cursor::new(pgm)).unwrap();
+        let samples = ::std::thread::spawn(move || {
+            let mut decoder = decoder;
+            decoder.read_image().unwrap().into_u8()
+        }).join()
+            .unwrap();
+        assert_eq!(samples, Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn header_read_error() {
+        /// Returns the data and then an error instead of the end of the stream.
+        struct Failing<'a>(&'a [u8]);
+
+        impl<'a> Read for Failing<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::Other, "connection reset"));
+                }
+                let len = buf.len().min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        // The error must not end the token as if it were whitespace.
+        for data in &[&b"P5 3 2 25"[..], b"P5 3 2 # comment"] {
+            match PNMDecoder::new(Failing(data)) {
+                Err(ImageError::IoError(ref err)) => assert_eq!(err.kind(), io::ErrorKind::Other),
+                other => panic!("Expected an io error, got {:?}", other.err()),
+            }
+        }
+
+        // Nor must it end an ascii raster as if the samples were missing.
+        if cfg!(feature = "pnm_ascii") {
+            let mut decoder = PNMDecoder::new(Failing(b"P2 3 1 255\n1 2")).unwrap();
+            match decoder.read_image() {
+                Err(ImageError::IoError(ref err)) => assert_eq!(err.kind(), io::ErrorKind::Other),
+                other => panic!("Expected an io error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn ascii_capacity() {
+        let (width, height) = (300, 7);
+        let mut pgm = format!("P2 {} {} 255\n", width, height).into_bytes();
+        for i in 0..width * height {
+            pgm.extend(format!("{} ", i % 256).bytes());
+        }
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        let samples = decoder.read_ascii::<U8>(1, height).unwrap();
+        assert_eq!(samples.len(), (width * height) as usize);
+        // Growing the buffer sample by sample would have left spare capacity.
+        assert_eq!(samples.capacity(), samples.len());
+        assert_eq!(samples[257], 1);
+    }
+
+    #[test]
+    fn pbm_expansion_table() {
+        // Expands bit by bit like the decoder did before using the table.
+        fn expand(bytes: &[u8], width: usize, height: usize) -> Vec<u8> {
+            let linebytelen = (width + 7) / 8;
+            let mut buffer = vec![0; width * height];
+            for (line, linebuffer) in bytes.chunks(linebytelen).enumerate() {
+                for i in 0..width {
+                    let indicator = (linebuffer[i / 8] >> (7 - i % 8)) & 0x01;
+                    buffer[line * width + i] = if indicator == 0 { 1 } else { 0 };
+                }
+            }
+            buffer
+        }
+
+        // Every byte value occurs, including set padding bits at the end of each row.
+        let (width, height) = (13, 128);
+        let bytes: Vec<u8> = (0..2 * height).map(|i| (i * 167 + 3) as u8).collect();
+        let samples = PbmBit::from_bytes(&bytes, width as u32, height as u32, 1).unwrap();
+        assert_eq!(samples, expand(&bytes, width, height));
+
+        let all: Vec<u8> = (0..=255).collect();
+        let samples = PbmBit::from_bytes(&all, 8, 256, 1).unwrap();
+        assert_eq!(samples, expand(&all, 8, 256));
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn reject_trailing_samples() {
+        let pgm = b"P2 2 2 255\n1 2\n3 4\n5\n";
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![1, 2, 3, 4]));
+
+        let options = PnmDecoderOptions::new().reject_trailing(true);
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::FormatError(ref msg)) => {
+                assert_eq!(msg, "Unexpected data after the last sample")
+            }
+            other => panic!("Unexpected result {:?}", other.map(|_| ())),
+        }
+
+        let mut decoder = options.read(&b"P2 2 2 255\n1 2\n3 4 \n\n"[..]).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![1, 2, 3, 4]));
+
+        let rows: Result<Vec<_>, _> = options.read(&pgm[..]).unwrap().rows().collect();
+        assert!(rows.is_err());
+
+        let mut decoder = options.read(&b"P1 3 1\n010"[..]).unwrap();
+        assert!(decoder.read_image().is_ok());
+        let mut decoder = options.read(&b"P1 3 1\n0101"[..]).unwrap();
+        assert!(decoder.read_image().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn ascii_sample_count_overflow() {
+        // 65536 * 65536 wraps to 0 in u32, the samples must be counted in usize.
+        let mut decoder = PNMDecoder::new(&b"P2 65536 65536 255\n1 2 3\n"[..]).unwrap();
+        match decoder.read_ascii::<U8>(1, 65536) {
+            #[cfg(target_pointer_width = "64")]
+            Err(ImageError::PnmError(PnmError::MissingSamples { expected, got })) => {
+                assert_eq!((expected, got), (1 << 32, 3))
+            }
+            #[cfg(not(target_pointer_width = "64"))]
+            Err(ImageError::DimensionError) => (),
+            other => panic!("Unexpected result {:?}", other.map(|samples| samples.len())),
+        }
+
+        let max = u32::max_value();
+        let ppm = format!("P3 {} {} 255\n", max, max);
+        let mut decoder = PNMDecoder::new(ppm.as_bytes()).unwrap();
+        match decoder.read_ascii::<U8>(3, max) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("Unexpected result {:?}", other.map(|samples| samples.len())),
+        }
+    }
+
+    #[test]
+    fn dynamic_image() {
+        fn decode(data: &[u8]) -> ImageResult<DynamicImage> {
+            PNMDecoder::new(data)?.into_dynamic_image()
+        }
+
+        match decode(b"P4 3 1\n\x40").unwrap() {
+            DynamicImage::ImageLuma8(image) => assert_eq!(image.into_raw(), vec![255, 0, 255]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+        match decode(b"P5 2 1 255\n\x01\x02").unwrap() {
+            DynamicImage::ImageLuma8(image) => assert_eq!(image.into_raw(), vec![1, 2]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+        match decode(b"P6 1 1 255\n\x01\x02\x03").unwrap() {
+            DynamicImage::ImageRgb8(image) => assert_eq!(image.into_raw(), vec![1, 2, 3]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\
+                    \x01\x02";
+        match decode(pam).unwrap() {
+            DynamicImage::ImageLumaA8(image) => assert_eq!(image.into_raw(), vec![1, 2]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\
+                    \x01\x02\x03\x04";
+        match decode(pam).unwrap() {
+            DynamicImage::ImageRgba8(image) => assert_eq!(image.into_raw(), vec![1, 2, 3, 4]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+        match decode(b"Pf 1 1 -1.0\n\x00\x00\x80\x3f") {
+            Err(ImageError::UnsupportedColor(ColorType::Gray(32))) => (),
+            other => panic!("Unexpected result {:?}", other.map(|image| image.color())),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn dynamic_image_u16() {
+        let pgm = b"P5 2 1 65535\n\x00\x00\xff\xff";
+        match PNMDecoder::new(&pgm[..]).unwrap().into_dynamic_image().unwrap() {
+            DynamicImage::ImageLuma8(image) => assert_eq!(image.into_raw(), vec![0, 255]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+
+        let ppm = b"P6 1 1 1023\n\x03\xff\x00\x00\x02\x00";
+        let options = PnmDecoderOptions::new().scale_to_full(true);
+        match options.read(&ppm[..]).unwrap().into_dynamic_image().unwrap() {
+            DynamicImage::ImageRgb8(image) => assert_eq!(image.into_raw(), vec![255, 0, 128]),
+            other => panic!("Unexpected image {:?}", other.color()),
+        }
+
+        let truncate = PnmDecoderOptions::new().downscale(Downscale::Truncate);
+        match truncate.read(&b"P5 1 1 65535\n\x80\x00"[..]).unwrap().into_dynamic_image() {
+            Ok(DynamicImage::ImageLuma8(image)) => assert_eq!(image.into_raw(), vec![127]),
+            other => panic!("Unexpected result {:?}", other.map(|image| image.color())),
+        }
+    }
+
+    #[test]
+    fn read_image_into_strided() {
+        let (width, height) = (50u32, 3u32);
+        let raster: Vec<u8> = (0..width * height * 3).map(|i| (i % 251) as u8).collect();
+        let ppm = [format!("P6 {} {} 255\n", width, height).as_bytes(), &raster[..]].concat();
+
+        let mut decoder = PNMDecoder::new(&ppm[..]).unwrap();
+        assert_eq!(decoder.row_len().unwrap(), 150);
+        let stride = decoder.aligned_stride(256).unwrap();
+        assert_eq!(stride, 256);
+        let mut buf = vec![0xAA; stride * height as usize];
+        decoder.read_image_into_strided(&mut buf, stride).unwrap();
+        for (row, chunk) in buf.chunks(stride).enumerate() {
+            assert_eq!(&chunk[..150], &raster[row * 150..(row + 1) * 150]);
+            assert!(chunk[150..].iter().all(|&byte| byte == 0));
+        }
+
+        let decoder = PNMDecoder::new(&ppm[..]).unwrap();
+        assert_eq!(decoder.aligned_stride(1).unwrap(), 150);
+        assert_eq!(decoder.aligned_stride(50).unwrap(), 150);
+        assert_eq!(decoder.aligned_stride(64).unwrap(), 192);
+        assert!(decoder.aligned_stride(0).is_err());
+
+        let mut decoder = PNMDecoder::new(&ppm[..]).unwrap();
+        assert!(decoder.read_image_into_strided(&mut [0; 447], 149).is_err());
+        assert!(decoder.read_image_into_strided(&mut [0; 449], 150).is_err());
+        let mut buf = vec![0; 450];
+        decoder.read_image_into_strided(&mut buf, 150).unwrap();
+        assert_eq!(buf, raster);
+    }
+
+    #[test]
+    fn read_image_rgba8() {
+        fn decode(data: &[u8]) -> ImageResult<Vec<u8>> {
+            PNMDecoder::new(data)?.read_image_rgba8()
+        }
+
+        assert_eq!(decode(b"P4 2 1\n\x80").unwrap(), [0, 0, 0, 255, 255, 255, 255, 255]);
+        assert_eq!(decode(b"P5 2 1 255\n\x01\x02").unwrap(), [1, 1, 1, 255, 2, 2, 2, 255]);
+        assert_eq!(decode(b"P6 1 1 255\n\x01\x02\x03").unwrap(), [1, 2, 3, 255]);
+        let pam = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\
+                    \x01\x02\x03\x04";
+        assert_eq!(decode(pam).unwrap(), [1, 1, 1, 2, 3, 3, 3, 4]);
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\
+                    \x01\x02\x03\x04";
+        assert_eq!(decode(pam).unwrap(), [1, 2, 3, 4]);
+
+        match decode(b"Pf 1 1 -1.0\n\x00\x00\x80\x3f") {
+            Err(ImageError::UnsupportedColor(ColorType::Gray(32))) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn read_image_rgba8_u16() {
+        let mut decoder = PNMDecoder::new(&b"P6 1 1 65535\n\xff\xff\x80\x00\x00\x00"[..]).unwrap();
+        assert_eq!(decoder.read_image_rgba8().unwrap(), [255, 128, 0, 255]);
+        // The decoder still describes the image as stored.
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(16));
+
+        let options = PnmDecoderOptions::new().upscale(true);
+        let ppms = [
+            &b"P6 1 1 255\n\xff\x80\x00"[..],
+            &b"P6 1 1 65535\n\xff\xff\x80\x00\x00\x00"[..],
+        ];
+        for data in &ppms {
+            let mut decoder = options.read(&data[..]).unwrap();
+            assert_eq!(decoder.read_image_rgba8().unwrap(), [255, 128, 0, 255]);
+            assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(16));
+        }
+    }
+
+    #[test]
+    fn bytes_consumed() {
+        let stream = b"P5\n# embedded\n3 2\n255\n\x01\x02\x03\x04\x05\x06TRAILER";
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        assert_eq!(decoder.bytes_consumed(), 22);
+        let mut row = [0; 3];
+        decoder.read_scanline(&mut row).unwrap();
+        assert_eq!(decoder.bytes_consumed(), 25);
+        decoder.read_scanline(&mut row).unwrap();
+        assert_eq!(decoder.bytes_consumed(), 28);
+        assert_eq!(&stream[decoder.bytes_consumed() as usize..], b"TRAILER");
+
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        decoder.read_image().unwrap();
+        assert_eq!(decoder.bytes_consumed(), 28);
+
+        let pbm = b"P4 10 2\n\xff\xc0\x00\x00rest";
+        let mut decoder = PNMDecoder::new(&pbm[..]).unwrap();
+        decoder.read_image().unwrap();
+        assert_eq!(decoder.bytes_consumed(), 12);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn bytes_consumed_ascii() {
+        let stream = b"P2 2 2 255\n 10  20\n30 40\nrest";
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        assert_eq!(decoder.bytes_consumed(), 11);
+        decoder.read_image().unwrap();
+        assert_eq!(&stream[decoder.bytes_consumed() as usize..], b"rest");
+    }
+
+    #[test]
+    fn skip_image() {
+        use std::io::Cursor;
+
+        let stream = b"P5 3 2 255\n\x01\x02\x03\x04\x05\x06P4 3 1\n\x40P6 1 1 255\n\x07\x08\x09";
+        let (reader, header) = PNMDecoder::new(&stream[..]).unwrap().skip_image().unwrap();
+        assert_eq!(header.subtype(), PNMSubtype::Graymap(SampleEncoding::Binary));
+        let (reader, header) = PNMDecoder::from_buf_read(reader).unwrap().skip_image().unwrap();
+        assert_eq!((header.width(), header.height()), (3, 1));
+        let mut decoder = PNMDecoder::from_buf_read(reader).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![7, 8, 9]));
+
+        // Rows read before are not skipped again.
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        let mut row = [0; 3];
+        decoder.read_scanline(&mut row).unwrap();
+        let (reader, _) = decoder.skip_image().unwrap();
+        assert_eq!(PNMDecoder::from_buf_read(reader).unwrap().dimensions().unwrap(), (3, 1));
+
+        let (reader, _) = PNMDecoder::new(Cursor::new(&stream[..]))
+            .unwrap()
+            .seek_past_image()
+            .unwrap();
+        let mut decoder = PNMDecoder::from_buf_read(reader).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![1, 0, 1]));
+
+        match PNMDecoder::new(&b"P5 3 2 255\n\x01\x02"[..]).unwrap().skip_image() {
+            Err(ImageError::PnmError(PnmError::Truncated { expected: 6, got: 2 })) => (),
+            other => panic!("Unexpected result {:?}", other.map(|(_, header)| header.subtype())),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn skip_ascii_image() {
+        let stream = b"P2 2 1 255\n10 20\nP1 2 1 01\n";
+        let (reader, _) = PNMDecoder::new(&stream[..]).unwrap().skip_image().unwrap();
+        let mut decoder = PNMDecoder::from_buf_read(reader).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![1, 0]));
+
+        // The samples are discarded one at a time instead of decoding the raster.
+        let mut pgm = b"P2 100 100 255\n".to_vec();
+        for sample in 0..100 * 100 {
+            pgm.extend_from_slice(format!("{}\n", sample % 256).as_bytes());
+        }
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        let before = ALLOCATIONS.with(Cell::get);
+        decoder.skip_rows().unwrap();
+        assert_eq!(ALLOCATIONS.with(Cell::get), before);
+        assert_eq!(decoder.bytes_consumed(), pgm.len() as u64);
+
+        match PNMDecoder::new(&b"P2 2 2 255\n1 2 3"[..]).unwrap().skip_image() {
+            Err(ImageError::PnmError(PnmError::MissingSamples { expected: 4, got: 3 })) => (),
+            other => panic!("Unexpected result {:?}", other.map(|(_, header)| header.subtype())),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn to_bitmap() {
+        let ramp: Vec<u8> = (0..10u8).map(|v| v * 25).collect();
+        let mut pgmbinary = b"P5 10 2 255\n".to_vec();
+        pgmbinary.extend_from_slice(&ramp);
+        pgmbinary.extend(ramp.iter().rev());
+        let decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        let mut pbm = Vec::new();
+        decoder.to_bitmap(&mut pbm, 100).unwrap();
+        // Black pixels are set bits, rows are padded to whole bytes.
+        assert_eq!(pbm, b"P4\n10 2\n\xF0\x00\x03\xC0");
+
+        let pgmascii = b"P2 3 1 255\n0 128 255";
+        let decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        let mut pbm = Vec::new();
+        decoder.to_bitmap(&mut pbm, 128).unwrap();
+        let mut decoder = PNMDecoder::new(&pbm[..]).unwrap();
         match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(
-                data,
-                vec![
-                    0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x01,
-                    0x00, 0x00, 0x01,
-                ]
-            ),
+            DecodingResult::U8(data) => assert_eq!(data, vec![0, 1, 1]),
+            other => panic!("Decoded wrong image format {:?}", other),
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Arbitrary(ArbitraryHeader {
-                            width: 4,
-                            height: 4,
-                            maxval: 1,
-                            depth: 1,
-                            tupltype: Some(ArbitraryTuplType::BlackAndWhite),
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        let ppm = b"P6 1 1 255\n\x00\x00\x00";
+        let decoder = PNMDecoder::new(&ppm[..]).unwrap();
+        match decoder.to_bitmap(Vec::new(), 128) {
+            Err(ImageError::UnsupportedColor(ColorType::RGB(8))) => (),
+            other => panic!("Expected unsupported color, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn excessive_pam_header() {
+        let mut pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\n".to_vec();
+        for _ in 0..100_000 {
+            pam.extend_from_slice(b"TUPLTYPE A\n");
+        }
+        pam.extend_from_slice(b"ENDHDR\n\x00");
+        match PNMDecoder::new(&pam[..]) {
+            Err(ImageError::FormatError(msg)) => assert!(msg.starts_with("TUPLTYPE too long")),
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Accepted excessive TUPLTYPE"),
+        }
+
+        let mut pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\n".to_vec();
+        for _ in 0..100_000 {
+            pam.extend_from_slice(b"#\n");
+        }
+        pam.extend_from_slice(b"ENDHDR\n\x00");
+        match PNMDecoder::new(&pam[..]) {
+            Err(ImageError::FormatError(msg)) => {
+                assert!(msg.starts_with("Too many lines in pam header"))
+            }
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Accepted excessive header"),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn strict_maxval() {
+        let pgmascii = b"P2 3 1 4095\n0 4095 4096";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        assert_eq!(decoder.maxval(), Some(4095));
+        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(16));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0, 4095, 4096]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let strict = PnmDecoderOptions::new().strict(true);
+        let mut decoder = strict.read(&pgmascii[..]).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::PnmError(PnmError::SampleOutOfRange { value, maxval })) => {
+                assert_eq!((value, maxval), (4096, 4095))
+            }
+            other => panic!("Expected sample error, got {:?}", other),
+        }
+
+        let ppmbinary = b"P6 1 1 4095\n\x0F\xFF\x00\x00\x10\x00";
+        let mut decoder = strict.read(&ppmbinary[..]).unwrap();
+        assert!(decoder.read_image().is_err());
+
+        let ppmbinary = b"P6 1 1 4095\n\x0F\xFF\x00\x00\x0F\xFF";
+        let mut decoder = strict.read(&ppmbinary[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![4095, 0, 4095]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let pgmbinary = b"P5 2 1 200\n\xC8\xFA";
+        let mut decoder = PNMDecoder::new(&pgmbinary[..]).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![200, 250]));
+        let mut decoder = strict.read(&pgmbinary[..]).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::PnmError(PnmError::SampleOutOfRange { value, maxval })) => {
+                assert_eq!((value, maxval), (250, 200))
+            }
+            other => panic!("Expected sample error, got {:?}", other),
+        }
+        let decoder = strict.read(&pgmbinary[..]).unwrap();
+        assert!(decoder.transcode(Vec::new(), SampleEncoding::Ascii).is_err());
+    }
+
+    #[test]
+    fn maxval() {
+        let pgmbin = b"P5 1 1 255\n\x00";
+        let decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        assert_eq!(decoder.maxval(), Some(255));
+
+        let pambw = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE\nENDHDR\n\
+                      \x01";
+        let decoder = PNMDecoder::new(&pambw[..]).unwrap();
+        assert_eq!(decoder.maxval(), Some(1));
+
+        let pbmbin = b"P4 1 1\n\x80";
+        let decoder = PNMDecoder::new(&pbmbin[..]).unwrap();
+        assert_eq!(decoder.maxval(), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn read_image_into() {
+        let pgmbin = b"P5 2 1 65535\n\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        assert_eq!(decoder.total_bytes().unwrap(), 4);
+        let mut short = [0u8; 3];
+        match decoder.read_image_into(&mut short) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("Expected a dimension error, got {:?}", other),
+        }
+
+        let mut buf = [0u8; 4];
+        decoder.read_image_into(&mut buf).unwrap();
+        let mut samples = [0u16; 2];
+        NativeEndian::read_u16_into(&buf, &mut samples);
+        assert_eq!(samples, [0x0102, 0x0304]);
+
+        let pbmascii = b"P1 3 2\n1 0 1 0 1 0";
+        let mut decoder = PNMDecoder::new(&pbmascii[..]).unwrap();
+        let mut buf = vec![0xAA; decoder.total_bytes().unwrap()];
+        decoder.read_image_into(&mut buf).unwrap();
+        assert_eq!(buf, vec![0, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn rows() {
+        let pgmbin = b"P5 3 2 255\n\x01\x02\x03\x04\x05\x06";
+        let decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        let rows = decoder.rows().collect::<ImageResult<Vec<_>>>().unwrap();
+        assert_eq!(rows, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+
+        let truncated = b"P5 3 3 255\n\x01\x02\x03\x04";
+        let decoder = PNMDecoder::new(&truncated[..]).unwrap();
+        let mut rows = decoder.rows();
+        assert_eq!(rows.next().unwrap().unwrap(), vec![1, 2, 3]);
+        match rows.next() {
+            Some(Err(ImageError::PnmError(PnmError::Truncated { .. }))) => (),
+            other => panic!("Expected the row to be truncated, got {:?}", other),
+        }
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn scanline_then_whole_image() {
+        let pgmbin = b"P5 2 2 255\n\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        let mut row = [0; 2];
+        decoder.read_scanline(&mut row).unwrap();
+        assert_eq!(row, [1, 2]);
+        match decoder.read_image() {
+            Err(ImageError::FormatError(_)) => (),
+            other => panic!("Expected a format error, got {:?}", other),
+        }
+        assert!(decoder.read_image_with_progress(|_, _| ()).is_err());
+        decoder.read_scanline(&mut row).unwrap();
+        assert_eq!(row, [3, 4]);
+
+        let pbmbin = b"P4 2 2\n\x40\x80";
+        let mut decoder = PNMDecoder::new(&pbmbin[..]).unwrap();
+        decoder.read_scanline(&mut row).unwrap();
+        match decoder.read_image_packed() {
+            Err(ImageError::FormatError(_)) => (),
+            other => panic!("Expected a format error, got {:?}", other),
+        }
+
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        assert_eq!(decoder.read_image().unwrap().into_u8(), Some(vec![1, 2, 3, 4]));
+    }
+
+    /// Counts the allocations of each thread, as the tests run in parallel.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = Cell::new(0);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Read all rows, asserting that they are `expected` and that only the first one allocates.
+    fn assert_rows_without_allocation<R, B: BufRead>(
+        decoder: &mut PNMDecoder<R, B>,
+        expected: &[Vec<u8>],
+    ) {
+        let mut allocations = Vec::new();
+        for expected in expected {
+            let before = ALLOCATIONS.with(Cell::get);
+            let row = decoder.read_row().unwrap();
+            allocations.push(ALLOCATIONS.with(Cell::get) - before);
+            assert_eq!(row, Some(&expected[..]));
+        }
+        assert_eq!(decoder.read_row().unwrap(), None);
+        assert!(allocations[1..].iter().all(|&count| count == 0), "{:?}", allocations);
+    }
+
+    #[test]
+    fn read_row() {
+        let mut pbm = b"P4 10 100\n".to_vec();
+        let mut expected = Vec::new();
+        for row in 0..100u8 {
+            pbm.extend_from_slice(&[row, 0xC0]);
+            expected.push((0..8).map(|bit| (row >> (7 - bit) & 1) ^ 1).chain(vec![0, 0]).collect());
+        }
+        let mut decoder = PNMDecoder::new(&pbm[..]).unwrap();
+        assert_rows_without_allocation(&mut decoder, &expected);
+
+        let mut pgm = b"P5 3 50 100\n".to_vec();
+        let mut expected = Vec::new();
+        for row in 0..50u8 {
+            pgm.extend_from_slice(&[row, row + 1, row * 2]);
+            expected.push(vec![row, row + 1, row * 2]);
+        }
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert_rows_without_allocation(&mut decoder, &expected);
+
+        let options = PnmDecoderOptions::new().scale_to_full(true).strict(true);
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        let scaled: Vec<Vec<u8>> = expected
+            .iter()
+            .map(|row| row.iter().map(|&v| scale_sample(u32::from(v), 100, 0xFF) as u8).collect())
+            .collect();
+        assert_rows_without_allocation(&mut decoder, &scaled);
+        assert_eq!(
+            options.read(&pgm[..]).unwrap().read_image().unwrap().into_u8(),
+            Some(scaled.concat())
+        );
+
+        let pgm = b"P5 3 2 60\n\x01\x02\x03\x04\x3d\x06";
+        let mut decoder = PnmDecoderOptions::new().strict(true).read(&pgm[..]).unwrap();
+        assert_eq!(decoder.read_row().unwrap(), Some(&[1, 2, 3][..]));
+        match decoder.read_row() {
+            Err(ImageError::PnmError(PnmError::SampleOutOfRange { value: 61, maxval: 60 })) => (),
+            other => panic!("Accepted sample above maxval {:?}", other),
+        }
+
+        let truncated = b"P5 3 3 255\n\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&truncated[..]).unwrap();
+        assert_eq!(decoder.read_row().unwrap(), Some(&[1, 2, 3][..]));
+        assert!(decoder.read_row().is_err());
+
+        // Converting the samples allocates but yields the same rows as decoding the image.
+        let ppm = b"P6 2 2 255\n\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C";
+        let options = PnmDecoderOptions::new().grayscale(true);
+        let mut decoder = options.read(&ppm[..]).unwrap();
+        let mut rows = decoder.read_row().unwrap().unwrap().to_vec();
+        rows.extend_from_slice(decoder.read_row().unwrap().unwrap());
+        assert_eq!(Some(rows), options.read(&ppm[..]).unwrap().read_image().unwrap().into_u8());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn read_row_u16() {
+        let pgm = b"P5 2 2 65535\n\x01\x02\x03\x04\x05\x06\x07\x08";
+        let expected: Vec<Vec<u8>> = [[0x0102u16, 0x0304], [0x0506, 0x0708]]
+            .iter()
+            .map(|row| {
+                let mut bytes = vec![0; 4];
+                NativeEndian::write_u16_into(row, &mut bytes);
+                bytes
+            })
+            .collect();
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert_rows_without_allocation(&mut decoder, &expected);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn read_row_ascii() {
+        let mut pgm = b"P2 3 50 255\n".to_vec();
+        let mut expected = Vec::new();
+        for row in 0..50 {
+            pgm.extend_from_slice(format!("{} {} {}\n", row, row + 1, row + 2).as_bytes());
+            expected.push(vec![row, row + 1, row + 2]);
+        }
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert_rows_without_allocation(&mut decoder, &expected);
+
+        let pbm = b"P1 3 3\n1 0 1\n010\n0 0 1\n";
+        let expected = vec![vec![0, 1, 0], vec![1, 0, 1], vec![1, 1, 0]];
+        let mut decoder = PNMDecoder::new(&pbm[..]).unwrap();
+        assert_rows_without_allocation(&mut decoder, &expected);
+
+        let truncated = b"P2 3 2 255\n1 2 3\n4";
+        let mut decoder = PNMDecoder::new(&truncated[..]).unwrap();
+        assert_eq!(decoder.read_row().unwrap(), Some(&[1, 2, 3][..]));
+        match decoder.read_row() {
+            Err(ImageError::PnmError(PnmError::MissingSamples { expected: 3, got: 1 })) => (),
+            other => panic!("Accepted missing samples {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn pixels() {
+        let ppmbin = b"P6 2 2 255\n\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C";
+        let mut decoder = PNMDecoder::new(&ppmbin[..]).unwrap();
+        let dimensions = decoder.dimensions().unwrap();
+        let color = decoder.colortype().unwrap();
+        let samples = match decoder.read_image().unwrap() {
+            DecodingResult::U8(samples) => samples,
+            other => panic!("Decoded wrong image format {:?}", other),
+        };
+        let pixels = PnmPixels::new(&samples, dimensions, color)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(pixels.len(), 4);
+        assert_eq!(pixels[1], (1, 0, &[4, 5, 6][..]));
+        assert_eq!(pixels[2], (0, 1, &[7, 8, 9][..]));
+
+        let pgmbin = b"P5 1 2 65535\n\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        let dimensions = decoder.dimensions().unwrap();
+        let color = decoder.colortype().unwrap();
+        let samples = match decoder.read_image().unwrap() {
+            DecodingResult::U16(samples) => samples,
+            other => panic!("Decoded wrong image format {:?}", other),
+        };
+        let mut pixels = PnmPixels::new(&samples, dimensions, color).unwrap();
+        assert_eq!(pixels.nth(1), Some((0, 1, &[0x0304][..])));
+        assert!(PnmPixels::new(&samples[..1], dimensions, color).is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let huge = b"P5 100000 100000 255\n";
+        let limits = Limits {
+            max_bytes: Some(64 * 1024 * 1024),
+            ..Limits::default()
+        };
+        match PNMDecoder::with_limits(&huge[..], limits) {
+            Err(ImageError::LimitError) => (),
+            Err(err) => panic!("Expected a limit error, got {:?}", err),
+            Ok(_) => panic!("Expected a limit error"),
+        }
+
+        let pgmbin = b"P5 2 1 255\n\x01\x02";
+        let limits = Limits {
+            max_width: Some(1),
+            ..Limits::default()
+        };
+        assert!(PNMDecoder::with_limits(&pgmbin[..], limits).is_err());
+
+        let limits = Limits {
+            max_width: Some(2),
+            max_height: Some(1),
+            max_bytes: Some(2),
+            max_input: Some(13),
+        };
+        let mut decoder = PNMDecoder::with_limits(&pgmbin[..], limits).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lenient_unknown_header_line() {
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nGAMMA 2.2\nDEPTH 1\nMAXVAL 255\nENDHDR\n\x2a";
+        match PNMDecoder::new(&pam[..]) {
+            Err(ImageError::FormatError(_)) => (),
+            Err(err) => panic!("Expected a format error, got {:?}", err),
+            Ok(_) => panic!("Expected a format error"),
+        }
+
+        let mut decoder = PNMDecoder::new_lenient(&pam[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0x2a]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        let missing = b"P7\nWIDTH 1\nHEIGHT 1\nGAMMA 2.2\nMAXVAL 255\nENDHDR\n\x2a";
+        assert!(PNMDecoder::new_lenient(&missing[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn header_comments() {
+        let pgmascii = b"P2\n# Created by GIMP\n2 1 # the size\n255\n1 2";
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        assert_eq!(decoder.header().comments(), ["Created by GIMP", "the size"]);
+        decoder.read_image().unwrap();
+
+        let pam = b"P7\n# Created by pamtool\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nENDHDR\n\x2a";
+        let decoder = PNMDecoder::new(&pam[..]).unwrap();
+        let (_, header) = decoder.into_inner();
+        assert_eq!(header.comments(), ["Created by pamtool"]);
+
+        let mut written = Vec::new();
+        header.write(&mut written).unwrap();
+        assert!(written.starts_with(b"P7\n# Created by pamtool\nWIDTH 1\n"));
+    }
+
+    #[test]
+    fn error_positions() {
+        let pgmascii = b"P2 2 x 255\n";
+        match PNMDecoder::new(&pgmascii[..]) {
+            Err(ImageError::FormatError(msg)) => {
+                assert_eq!(msg, "Invalid number in preamble at byte 5")
+            }
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Decoded invalid header"),
+        }
+
+        let ppm = b"P6\n1 1\n65536\n";
+        match PNMDecoder::new(&ppm[..]) {
+            Err(ImageError::FormatError(msg)) => {
+                assert_eq!(msg, "maxval must be between 1 and 65535 at byte 7")
+            }
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Decoded invalid header"),
+        }
+
+        let pfm = b"PF\n1 1\ninf\n";
+        match PNMDecoder::new(&pfm[..]) {
+            Err(ImageError::FormatError(msg)) => {
+                assert_eq!(msg, "Float map scale must be finite and non-zero at byte 7")
+            }
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Decoded invalid header"),
+        }
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT x\nDEPTH 1\nMAXVAL 255\nENDHDR\n";
+        match PNMDecoder::new(&pam[..]) {
+            Err(ImageError::FormatError(msg)) => {
+                assert_eq!(msg, "Invalid height on line 3 at byte 11")
+            }
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Decoded invalid header"),
+        }
+    }
+
+    #[test]
+    fn truncated_raster() {
+        let pgmbin = b"P5 4 4 255\n\x01\x02\x03\x04\x05";
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::PnmError(PnmError::Truncated { expected, got })) => {
+                assert_eq!((expected, got), (16, 5));
+            }
+            other => panic!("Expected truncation error, got {:?}", other),
+        }
+
+        let err = ImageError::from(PnmError::Truncated {
+            expected: 16,
+            got: 5,
+        });
+        let message = err.to_string();
+        assert!(message.contains("16") && message.contains("5"));
+
+        let pambw = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 1\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE\nENDHDR\n\
+                      \x01\x02";
+        let mut decoder = PNMDecoder::new(&pambw[..]).unwrap();
+        match decoder.read_image() {
+            Err(ImageError::PnmError(PnmError::SampleOutOfRange { .. })) => (),
+            other => panic!("Expected a sample out of range, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encoded_header() {
+        let preamble = b"P6 # a comment\n2  1\t255\n";
+        let ppmbin = [&preamble[..], &[1, 2, 3, 4, 5, 6]].concat();
+        let mut decoder = PNMDecoder::new(&ppmbin[..]).unwrap();
+        assert_eq!(decoder.header().encoded(), Some(&preamble[..]));
+        decoder.read_image().unwrap();
+
+        let (_, header) = decoder.into_inner();
+        let mut written = Vec::new();
+        header.write(&mut written).unwrap();
+        assert_eq!(written, &preamble[..]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn ascii_megapixel() {
+        let (width, height) = (1000u32, 1000u32);
+        let expected: Vec<u16> = (0..width * height).map(|i| (i * 7 % 1024) as u16).collect();
+        let mut pgmascii = format!("P2\n{} {}\n1023\n", width, height).into_bytes();
+        for (i, sample) in expected.iter().enumerate() {
+            let separator = if i % 17 == 0 { "\n" } else if i % 5 == 0 { " \t " } else { " " };
+            pgmascii.extend_from_slice(format!("{}{}", sample, separator).as_bytes());
+        }
+
+        let mut decoder = PNMDecoder::new(&pgmascii[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert!(data == expected),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn ascii_sample_tokens() {
+        let mut decoder = PNMDecoder::new(&b"P2 4 1 65535\n+7 0 65535\n012"[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![7, 0, 65535, 12]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        for &(data, message) in &[
+            (&b"P2 1 1 255\n+"[..], "Error parsing sample value"),
+            (&b"P2 1 1 255\n1a"[..], "Error parsing sample value"),
+            (&b"P2 1 1 255\n4294967296"[..], "Error parsing sample value"),
+            (&b"P2 1 1 255\n1\xc3\xa9"[..], "Non ascii character where sample value was expected"),
+        ] {
+            let mut decoder = PNMDecoder::new(data).unwrap();
+            match decoder.read_image() {
+                Err(ImageError::FormatError(ref msg)) if msg == message => (),
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn ascii_missing_samples() {
+        for &(data, expected, got) in &[
+            (&b"P2 3 2 255\n1 2 3\n4 "[..], 6, 4),
+            (&b"P2 1 1 255\n"[..], 1, 0),
+            (&b"P1 4 1\n01"[..], 4, 2),
+        ] {
+            let mut decoder = PNMDecoder::new(data).unwrap();
+            match decoder.read_image() {
+                Err(ImageError::PnmError(PnmError::MissingSamples { expected: e, got: g })) => {
+                    assert_eq!((e, g), (expected, got))
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
         }
+
+        let err = ImageError::from(PnmError::MissingSamples { expected: 6, got: 4 });
+        assert_eq!(
+            err.to_string(),
+            "The ascii raster ended early, expected 6 samples but got 4"
+        );
+    }
+
+    #[test]
+    fn u8_from_bytes() {
+        let bytes: Vec<u8> = (0..256).map(|v| v as u8).collect();
+        assert_eq!(U8::from_bytes(&bytes, 16, 16, 1).unwrap(), bytes);
+        assert_eq!(U8::from_bytes(&[], 0, 0, 1).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn large_u8_binary() {
+        let (width, height) = (1024u32, 768u32);
+        let raster: Vec<u8> = (0..width * height).map(|i| (i * 31 % 251) as u8).collect();
+        let header = format!("P5\n{} {}\n255\n", width, height);
+        let pgmbin = [header.as_bytes(), &raster[..]].concat();
+
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => {
+                assert!(data == U8::from_bytes(&raster, width, height, 1).unwrap())
+            }
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn large_u16_binary() {
+        let (width, height) = (2048u32, 1536u32);
+        let raster: Vec<u8> = (0..width * height * 2)
+            .map(|i| (i * 37 % 253) as u8)
+            .collect();
+        let header = format!("P5\n{} {}\n65535\n", width, height);
+        let pgmbin = [header.as_bytes(), &raster[..]].concat();
+
+        let expected: Vec<u16> = raster
+            .chunks(2)
+            .map(|b| (u16::from(b[0]) << 8) | u16::from(b[1]))
+            .collect();
+        let mut decoder = PNMDecoder::new(&pgmbin[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert!(data == expected),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overlong_header_line() {
+        // Without a limit these endless lines would be read until memory runs out.
+        for prefix in &[&b"P7\n#"[..], b"P7\nWIDTH ", b"P2\n#", b"P2\n1"] {
+            match PNMDecoder::new(prefix.chain(io::repeat(b'x'))) {
+                Err(ImageError::FormatError(ref msg)) if msg.contains("too long") => (),
+                Err(err) => panic!("Unexpected error {:?}", err),
+                Ok(_) => panic!("Accepted an endless header line"),
+            }
+        }
+
+        let comment = vec![b'x'; 5000];
+        let pam = [&b"P7\n#"[..], &comment, b"\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\n\
+                                               ENDHDR\n\x01"].concat();
+        assert!(PNMDecoder::new(&pam[..]).is_err());
+        let decoder = PNMDecoder::with_max_header_line(&pam[..], 8192).unwrap();
+        assert_eq!(decoder.header().comments()[0].len(), 5000);
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn read_rect() {
+        use std::io::Cursor;
+
+        fn check(image: &[u8], rect: (u32, u32, u32, u32)) {
+            let mut decoder = PNMDecoder::new(image).unwrap();
+            let (width, _) = decoder.dimensions().unwrap();
+            let pixel_len = decoder.row_len().unwrap() / width as usize;
+            let mut full = vec![0; decoder.total_bytes().unwrap()];
+            decoder.read_image_into(&mut full).unwrap();
+            let (x, y, w, h) = rect;
+            let expected: Vec<u8> = full
+                .chunks(width as usize * pixel_len)
+                .skip(y as usize)
+                .take(h as usize)
+                .flat_map(|row| row[x as usize * pixel_len..(x + w) as usize * pixel_len].to_vec())
+                .collect();
+
+            let mut decoder = PNMDecoder::new(Cursor::new(image)).unwrap();
+            let mut buf = vec![0; expected.len()];
+            decoder.read_rect(x, y, w, h, &mut buf).unwrap();
+            assert_eq!(buf, expected);
+        }
+
+        let (width, height) = (37u32, 23u32);
+        let raster: Vec<u8> = (0..width * height * 2).map(|i| (i * 7 % 256) as u8).collect();
+        let pgm = [format!("P5\n{} {}\n255\n", width, height).as_bytes(), &raster[..]].concat();
+        check(&pgm, (5, 3, 20, 11));
+        check(&pgm, (0, 0, width, height));
+        check(&pgm, (36, 22, 1, 1));
+        let pgm16 = [format!("P5 {} {} 65535 ", width, height).as_bytes(), &raster[..]].concat();
+        check(&pgm16, (9, 4, 13, 17));
+        let pbm = [format!("P4\n{} {}\n", width, height).as_bytes(), &raster[..115]].concat();
+        check(&pbm, (3, 2, 30, 12));
+        let pfm_header = format!("Pf\n{} {}\n-1.0\n", width / 2, height);
+        let pfm = [pfm_header.as_bytes(), &raster[..]].concat();
+        check(&pfm, (2, 5, 10, 9));
+        check(b"P2\n3 3\n9\n1 2 3\n4 5 6\n7 8 9\n", (1, 1, 2, 2));
+
+        // Rows read before are skipped when locating the rectangle.
+        let mut decoder = PNMDecoder::new(Cursor::new(&pgm)).unwrap();
+        let mut row = vec![0; width as usize];
+        decoder.read_scanline(&mut row).unwrap();
+        let mut buf = vec![0; 4];
+        decoder.read_rect(1, 2, 2, 2, &mut buf).unwrap();
+        assert_eq!(buf, [raster[75], raster[76], raster[112], raster[113]]);
+
+        let mut decoder = PNMDecoder::new(Cursor::new(&pgm)).unwrap();
+        let mut buf = vec![0; 4];
+        assert!(decoder.read_rect(36, 0, 2, 2, &mut buf).is_err());
+        assert!(decoder.read_rect(0, 0, 2, 3, &mut buf).is_err());
     }
 
-    /// Tests reading of a valid grayscale pam
     #[test]
-    fn pam_grayscale() {
-        let pamdata = b"P7
-WIDTH 4
-HEIGHT 4
-DEPTH 1
-MAXVAL 255
-TUPLTYPE GRAYSCALE
-# Comment line
-ENDHDR
-\xde\xad\xbe\xef\xde\xad\xbe\xef\xde\xad\xbe\xef\xde\xad\xbe\xef";
-        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
-        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
-        assert_eq!(decoder.subtype(), PNMSubtype::ArbitraryMap);
-        match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(
-                data,
-                vec![
-                    0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde,
-                    0xad, 0xbe, 0xef,
-                ]
-            ),
+    #[cfg(feature = "pnm_u16")]
+    fn read_image_subsampled() {
+        use std::io::Cursor;
+
+        fn check(image: &[u8], factor: u32) {
+            let mut decoder = PNMDecoder::new(image).unwrap();
+            let (width, height) = decoder.dimensions().unwrap();
+            let pixel_len = decoder.row_len().unwrap() / width as usize;
+            let mut full = vec![0; decoder.total_bytes().unwrap()];
+            decoder.read_image_into(&mut full).unwrap();
+            let expected: Vec<u8> = full
+                .chunks(width as usize * pixel_len)
+                .step_by(factor as usize)
+                .flat_map(|row| row.chunks(pixel_len).step_by(factor as usize))
+                .flat_map(|pixel| pixel.to_vec())
+                .collect();
+
+            let mut decoder = PNMDecoder::new(Cursor::new(image)).unwrap();
+            let ((w, h), result) = decoder.read_image_subsampled(factor).unwrap();
+            let rounded_up = |len: u32| (len + factor - 1) / factor;
+            assert_eq!((w, h), (rounded_up(width), rounded_up(height)));
+            let mut buf = vec![0; expected.len()];
+            assert_eq!(write_samples(result, &mut buf), expected.len());
+            assert_eq!(buf, expected);
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Arbitrary(ArbitraryHeader {
-                            width: 4,
-                            height: 4,
-                            depth: 1,
-                            maxval: 255,
-                            tupltype: Some(ArbitraryTuplType::Grayscale),
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        let (width, height) = (37u32, 23u32);
+        let raster: Vec<u8> = (0..width * height * 2).map(|i| (i * 7 % 256) as u8).collect();
+        let pgm = [format!("P5\n{} {}\n255\n", width, height).as_bytes(), &raster[..]].concat();
+        for &factor in &[1, 2, 3, 8, 40] {
+            check(&pgm, factor);
+        }
+        let pgm16 = [format!("P5 {} {} 65535 ", width, height).as_bytes(), &raster[..]].concat();
+        check(&pgm16, 4);
+        let ppm_header = format!("P6 {} {} 255 ", width / 3, height);
+        let ppm = [ppm_header.as_bytes(), &raster[..]].concat();
+        check(&ppm, 3);
+        let pbm = [format!("P4\n{} {}\n", width, height).as_bytes(), &raster[..115]].concat();
+        check(&pbm, 5);
+        let pfm_header = format!("Pf\n{} {}\n-1.0\n", width / 2, height);
+        let pfm = [pfm_header.as_bytes(), &raster[..]].concat();
+        check(&pfm, 2);
+
+        let mut decoder = PNMDecoder::new(Cursor::new(&pgm)).unwrap();
+        assert!(decoder.read_image_subsampled(0).is_err());
+        let mut row = vec![0; width as usize];
+        decoder.read_scanline(&mut row).unwrap();
+        assert!(decoder.read_image_subsampled(2).is_err());
+
+        let mut decoder = PNMDecoder::new(Cursor::new(&b"P2 2 1 255 1 2"[..])).unwrap();
+        match decoder.read_image_subsampled(2) {
+            Err(ImageError::UnsupportedError(_)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|(dimensions, _)| dimensions)),
         }
     }
 
-    /// Tests reading of a valid rgb pam
     #[test]
-    fn pam_rgb() {
-        let pamdata = b"P7
-# Comment line
-MAXVAL 255
-TUPLTYPE RGB
-DEPTH 3
-WIDTH 2
-HEIGHT 2
-ENDHDR
-\xde\xad\xbe\xef\xde\xad\xbe\xef\xde\xad\xbe\xef";
-        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(8));
-        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
-        assert_eq!(decoder.subtype(), PNMSubtype::ArbitraryMap);
-        match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(
-                data,
-                vec![
-                    0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef,
-                ]
-            ),
+    fn read_info() {
+        use std::io::Cursor;
+
+        for &(image, raster) in &[
+            (&b"P5\n# gray\n2 1\n255\n\x01\x02"[..], &b"\x01\x02"[..]),
+            (b"P2 3 1 9\n1 2 3\n", b"1 2 3\n"),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nENDHDR\n\n", b"\n"),
+            (b"Pf\n1 1\n-1.0\n\0\0\0\0", b"\0\0\0\0"),
+        ] {
+            let mut cursor = Cursor::new(image);
+            let (header, offset) = PNMDecoder::read_info(&mut cursor).unwrap();
+            assert_eq!(header.height(), 1);
+            assert_eq!(offset, (image.len() - raster.len()) as u64);
+            assert_eq!(cursor.position(), offset);
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Arbitrary(ArbitraryHeader {
-                            maxval: 255,
-                            tupltype: Some(ArbitraryTuplType::RGB),
-                            depth: 3,
-                            width: 2,
-                            height: 2,
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        let (header, _) = PNMDecoder::read_info(&b"P6\n3 2\n1023\n"[..]).unwrap();
+        assert_eq!((header.width(), header.height()), (3, 2));
+        assert_eq!(header.subtype(), PNMSubtype::Pixmap(SampleEncoding::Binary));
+        assert_eq!(header.maximal_sample(), 1023);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn data_offset() {
+        let pgm = b"\xef\xbb\xbf \nP5\n# gray\n2 1\n255\n\x01\x02";
+        assert!(PNMDecoder::new(&pgm[..]).is_err());
+        let decoder = PNMDecoder::new_lenient(&pgm[..]).unwrap();
+        assert_eq!(decoder.data_offset(), 23);
+        assert_eq!(decoder.raster_len().unwrap(), Some(2));
+
+        // Later frames are located from the start of the stream.
+        let stream = b"P5 2 1 255\n\x01\x02\n# next\nP2 1 2 9 1\n2\n P4 3 1\n\x40";
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        assert_eq!(decoder.data_offset(), 11);
+        decoder = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.data_offset(), 30);
+        assert_eq!(decoder.raster_len().unwrap(), None);
+        decoder = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.data_offset(), 42);
+        assert_eq!(stream.len(), 43);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn next_frame() {
+        let stream = b"P5\n2 1\n255\n\x01\x02P6\n1 1\n255\n\x03\x04\x05\n\
+                       P2 1 1 255 6\n\nP1 2 1 0 1\n";
+        fn read(decoder: &mut PNMDecoder<&[u8]>) -> Vec<u8> {
+            let mut buf = vec![0; decoder.total_bytes().unwrap()];
+            decoder.read_image_into(&mut buf).unwrap();
+            buf
         }
+
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        assert_eq!(read(&mut decoder), [1, 2]);
+
+        let mut decoder = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(8));
+        assert_eq!(read(&mut decoder), [3, 4, 5]);
+
+        // The third image is skipped without reading it.
+        let decoder = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoder.subtype(), PNMSubtype::Graymap(SampleEncoding::Ascii));
+        let mut decoder = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(read(&mut decoder), [1, 0]);
+        assert!(decoder.next_frame().unwrap().is_none());
+
+        let decoder = PNMDecoder::new(&b"P5\n2 1\n255\n\x01\x02P9"[..]).unwrap();
+        assert!(decoder.next_frame().is_err());
     }
 
     #[test]
-    fn pbm_binary() {
-        // The data contains two rows of the image (each line is padded to the full byte). For
-        // comments on its format, see documentation of `impl SampleType for PbmBit`.
-        let pbmbinary = [&b"P4 6 2\n"[..], &[0b01101100 as u8, 0b10110111]].concat();
-        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
-        assert_eq!(decoder.dimensions().unwrap(), (6, 2));
-        assert_eq!(
-            decoder.subtype(),
-            PNMSubtype::Bitmap(SampleEncoding::Binary)
-        );
-        match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(data, vec![1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 0]),
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn into_buffers() {
+        let pgm = b"P5\n3 2\n255\n\x00\x10\x20\x30\x40\x50";
+        let image = PNMDecoder::new(&pgm[..]).unwrap().into_luma8().unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(1, 0).data, [0x10]);
+        assert_eq!(image.get_pixel(2, 1).data, [0x50]);
+        assert!(PNMDecoder::new(&pgm[..]).unwrap().into_rgb8().is_err());
+
+        let ppm = b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06";
+        let image = PNMDecoder::new(&ppm[..]).unwrap().into_rgb8().unwrap();
+        assert_eq!(image.get_pixel(1, 0).data, [4, 5, 6]);
+        assert!(PNMDecoder::new(&ppm[..]).unwrap().into_luma8().is_err());
+        assert!(PNMDecoder::new(&ppm[..]).unwrap().into_rgb16().is_err());
+
+        let ppm16 = b"P6\n1 1\n65535\n\x01\x02\x03\x04\x05\x06";
+        let image = PNMDecoder::new(&ppm16[..]).unwrap().into_rgb16().unwrap();
+        assert_eq!(image.get_pixel(0, 0).data, [0x0102, 0x0304, 0x0506]);
+        let pgm16 = b"P5\n1 1\n65535\n\xbe\xef";
+        let image = PNMDecoder::new(&pgm16[..]).unwrap().into_luma16().unwrap();
+        assert_eq!(image.get_pixel(0, 0).data, [0xBEEF]);
+
+        let image = PNMDecoder::new(&b"P1 2 1 1 0"[..]).unwrap().into_luma8().unwrap();
+        assert_eq!(image.into_raw(), [0, 255]);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_ascii")]
+    fn read_image_packed() {
+        let raster = [0b1010_0110u8, 0b1100_1111, 0b0000_0000, 0b0111_1111, 0xFF, 0x01];
+        let pbm = [&b"P4\n11 3\n"[..], &raster[..]].concat();
+        let mut decoder = PNMDecoder::new(&pbm[..]).unwrap();
+        let packed = decoder.read_image_packed().unwrap();
+        assert_eq!(packed, [0b0101_1001, 0b0010_0000, 0xFF, 0b1000_0000, 0x00, 0b1110_0000]);
+
+        let mut expanded = PNMDecoder::new(&pbm[..]).unwrap();
+        let mut bits = vec![0; expanded.total_bytes().unwrap()];
+        expanded.read_image_into(&mut bits).unwrap();
+        assert_eq!(pack_bits(&bits, 11), packed);
+
+        let mut decoder = PNMDecoder::new(&b"P1 3 2 1 0 1 0 0 1"[..]).unwrap();
+        assert_eq!(decoder.read_image_packed().unwrap(), [0b0100_0000, 0b1100_0000]);
+
+        let mut decoder = PNMDecoder::new(&b"P5 1 1 255 \x01"[..]).unwrap();
+        assert!(decoder.read_image_packed().is_err());
+    }
+
+    #[test]
+    fn decoder_options() {
+        let pgm = b"P5\n4 2\n100\n\x00\x32\x63\x64\x00\x00\x00\x00";
+        assert!(PnmDecoderOptions::new().max_bytes(7).read(&pgm[..]).is_err());
+        let decoder = PnmDecoderOptions::new().max_bytes(8).read(&pgm[..]);
+        assert_eq!(decoder.unwrap().total_bytes().unwrap(), 8);
+        let limits = Limits {
+            max_height: Some(1),
+            ..Limits::default()
+        };
+        match PnmDecoderOptions::new().limits(limits).read(&pgm[..]) {
+            Err(ImageError::LimitError) => (),
+            _ => panic!("Accepted an image exceeding the limits"),
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Bitmap(BitmapHeader {
-                            encoding: SampleEncoding::Binary,
-                            width: 6,
-                            height: 2,
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        let mut decoder = PnmDecoderOptions::new()
+            .scale_to_full(true)
+            .read(&pgm[..])
+            .unwrap();
+        let mut row = [0; 4];
+        decoder.read_scanline(&mut row).unwrap();
+        assert_eq!(row, [0, 128, 252, 255]);
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nCOMMENT x\nENDHDR\n\x01";
+        assert!(PnmDecoderOptions::new().read(&pam[..]).is_err());
+        assert!(PnmDecoderOptions::new().lenient(true).read(&pam[..]).is_ok());
+
+        // The scale claims little endian, the samples are big endian.
+        let pfm = b"Pf\n1 1\n-1.0\n\x3f\x80\x00\x00";
+        let mut decoder = PnmDecoderOptions::new()
+            .force_endianness(Endianness::Big)
+            .read(&pfm[..])
+            .unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(data) => assert_eq!(data, vec![1.0]),
+            other => panic!("Decoded wrong image format {:?}", other),
         }
+
+        let comment = [&b"P2\n#"[..], &[b'x'; 100], b"\n1 1 1 1"].concat();
+        assert!(PnmDecoderOptions::new().max_header_line(50).read(&comment[..]).is_err());
+        assert!(PnmDecoderOptions::new().read(&comment[..]).is_ok());
     }
 
     #[test]
-    fn pbm_ascii() {
-        // The data contains two rows of the image (each line is padded to the full byte). For
-        // comments on its format, see documentation of `impl SampleType for PbmBit`.
-        let pbmbinary = b"P1 6 2\n 0 1 1 0 1 1\n1 0 1 1 0 1";
-        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(1));
-        assert_eq!(decoder.dimensions().unwrap(), (6, 2));
-        assert_eq!(decoder.subtype(), PNMSubtype::Bitmap(SampleEncoding::Ascii));
-        match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(data, vec![1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 0]),
+    #[cfg(feature = "pnm_ascii")]
+    fn interrupted_reads() {
+        /// Returns `Interrupted` before every read and then at most three bytes.
+        struct Interrupting<'a> {
+            data: &'a [u8],
+            interrupt: bool,
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Bitmap(BitmapHeader {
-                            encoding: SampleEncoding::Ascii,
-                            width: 6,
-                            height: 2,
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        impl<'a> Read for Interrupting<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.interrupt = !self.interrupt;
+                if self.interrupt {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+                }
+                let len = buf.len().min(self.data.len()).min(3);
+                buf[..len].copy_from_slice(&self.data[..len]);
+                self.data = &self.data[len..];
+                Ok(len)
+            }
+        }
+
+        let stream = b"P5\n# gray\n3 2\n255\n\x01\x02\x03\x04\x05\x06\
+                       P7\nWIDTH 2\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nENDHDR\n\x07\x08\n\
+                       P2 2 1 255 9 10\n";
+        let reader = Interrupting {
+            data: &stream[..],
+            interrupt: false,
+        };
+        let mut decoder = Some(PNMDecoder::new(reader).unwrap());
+        for expected in &[&[1u8, 2, 3, 4, 5, 6][..], &[7, 8], &[9, 10]] {
+            let mut current = decoder.take().expect("Missing image in stream");
+            let mut buf = vec![0; current.total_bytes().unwrap()];
+            current.read_image_into(&mut buf).unwrap();
+            assert_eq!(&buf[..], *expected);
+            decoder = current.next_frame().unwrap();
+        }
+        assert!(decoder.is_none());
+
+        let reader = Interrupting {
+            data: &b"P5\n3 2\n255\n\x01\x02"[..],
+            interrupt: false,
+        };
+        match PNMDecoder::new(reader).unwrap().read_image() {
+            Err(ImageError::PnmError(PnmError::Truncated { expected: 6, got: 2 })) => (),
+            other => panic!("Expected a truncation error, got {:?}", other.err()),
         }
     }
 
     #[test]
-    fn pgm_binary() {
-        // The data contains two rows of the image (each line is padded to the full byte). For
-        // comments on its format, see documentation of `impl SampleType for PbmBit`.
-        let elements = (0..16).collect::<Vec<_>>();
-        let pbmbinary = [&b"P5 4 4 255\n"[..], &elements].concat();
-        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
-        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
-        assert_eq!(
-            decoder.subtype(),
-            PNMSubtype::Graymap(SampleEncoding::Binary)
-        );
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn force_endianness_u16() {
+        let pgm = b"P5\n2 1\n65535\n\x12\x34\xab\xcd";
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
         match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(data, elements),
+            DecodingResult::U16(data) => assert_eq!(data, vec![0x1234, 0xabcd]),
+            other => panic!("Decoded wrong image format {:?}", other),
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Graymap(GraymapHeader {
-                            encoding: SampleEncoding::Binary,
-                            width: 4,
-                            height: 4,
-                            maxwhite: 255,
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        let options = PnmDecoderOptions::new().force_endianness(Endianness::Little);
+        let mut decoder = options.read(&pgm[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0x3412, 0xcdab]),
+            other => panic!("Decoded wrong image format {:?}", other),
+        }
+
+        // Ascii samples have no byte order.
+        let mut decoder = options.read(&b"P2 1 1 65535 4660"[..]).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(data) => assert_eq!(data, vec![0x1234]),
+            other => panic!("Decoded wrong image format {:?}", other),
         }
     }
 
     #[test]
-    fn pgm_ascii() {
-        // The data contains two rows of the image (each line is padded to the full byte). For
-        // comments on its format, see documentation of `impl SampleType for PbmBit`.
-        let pbmbinary = b"P2 4 4 255\n 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15";
-        let mut decoder = PNMDecoder::new(&pbmbinary[..]).unwrap();
-        assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
-        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
-        assert_eq!(
-            decoder.subtype(),
-            PNMSubtype::Graymap(SampleEncoding::Ascii)
-        );
-        match decoder.read_image().unwrap() {
-            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
-            DecodingResult::U8(data) => assert_eq!(data, (0..16).collect::<Vec<_>>()),
+    fn sample_layout() {
+        for &(image, channels, bytes) in &[
+            (&b"P1 1 1 0"[..], 1, 1),
+            (b"P4 1 1 \x00", 1, 1),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE\nENDHDR\n", 1, 1),
+            (b"P2 1 1 255 0", 1, 1),
+            (b"P5 1 1 65535 ", 1, 2),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nENDHDR\n", 2, 1),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 65535\nENDHDR\n", 2, 2),
+            (b"P3 1 1 255 0 0 0", 3, 1),
+            (b"P6 1 1 65535 ", 3, 2),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nENDHDR\n", 4, 1),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 65535\nENDHDR\n", 4, 2),
+            (b"Pf 1 1 -1.0 ", 1, 4),
+            (b"PF 1 1 1.0 ", 3, 4),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 7\nMAXVAL 255\nTUPLTYPE X\nENDHDR\n", 7, 1),
+            (b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 7\nMAXVAL 1000\nTUPLTYPE X\nENDHDR\n", 7, 2),
+        ] {
+            let mut decoder = PNMDecoder::new(image).unwrap();
+            assert_eq!(decoder.channel_count(), channels);
+            assert_eq!(decoder.bytes_per_sample(), bytes);
+            let pixel_len = usize::from(channels) * usize::from(bytes);
+            assert_eq!(decoder.row_len().unwrap(), pixel_len);
         }
-        match decoder.into_inner() {
-            (
-                _,
-                PNMHeader {
-                    decoded:
-                        HeaderRecord::Graymap(GraymapHeader {
-                            encoding: SampleEncoding::Ascii,
-                            width: 4,
-                            height: 4,
-                            maxwhite: 255,
-                        }),
-                    encoded: _,
-                },
-            ) => (),
-            _ => panic!("Decoded header is incorrect"),
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 256\nMAXVAL 255\nTUPLTYPE X\nENDHDR\n";
+        assert!(PNMDecoder::new(&pam[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn total_bytes_matches_decoded() {
+        fn decoded_len(result: DecodingResult) -> usize {
+            match result {
+                DecodingResult::U8(samples) => samples.len(),
+                DecodingResult::U16(samples) => samples.len() * 2,
+                DecodingResult::F32(samples) => samples.len() * 4,
+            }
+        }
+
+        // Alternating bits are valid samples for every maxval.
+        let raster: Vec<u8> = (0..240).map(|i| i % 2).collect();
+        for header in &[
+            "P4 5 3 ",
+            "P5 5 3 255 ",
+            "P5 5 3 1000 ",
+            "P6 5 3 255 ",
+            "P6 5 3 65535 ",
+            "P7\nWIDTH 5\nHEIGHT 3\nDEPTH 1\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE\nENDHDR\n",
+            "P7\nWIDTH 5\nHEIGHT 3\nDEPTH 2\nMAXVAL 65535\nENDHDR\n",
+            "P7\nWIDTH 5\nHEIGHT 3\nDEPTH 4\nMAXVAL 255\nENDHDR\n",
+            "P7\nWIDTH 5\nHEIGHT 3\nDEPTH 5\nMAXVAL 255\nTUPLTYPE X\nENDHDR\n",
+            "Pf 5 3 -1.0 ",
+            "PF 5 3 1.0 ",
+        ] {
+            let image = [header.as_bytes(), &raster[..]].concat();
+            let mut decoder = PNMDecoder::new(&image[..]).unwrap();
+            let total = decoder.total_bytes().unwrap();
+            assert_eq!(total, decoded_len(decoder.read_image().unwrap()), "{}", header);
+        }
+
+        for ascii in &[
+            &b"P1 3 2 0 1 0 1 0 1"[..],
+            b"P2 2 2 9 1 2 3 4",
+            b"P3 1 2 65535 1 2 3 4 5 6",
+        ] {
+            let mut decoder = PNMDecoder::new(*ascii).unwrap();
+            let total = decoder.total_bytes().unwrap();
+            assert_eq!(total, decoded_len(decoder.read_image().unwrap()));
+        }
+
+        let pam = b"P7\nWIDTH 4294967295\nHEIGHT 4294967295\nDEPTH 4\nMAXVAL 65535\nENDHDR\n";
+        assert!(PNMDecoder::new(&pam[..]).unwrap().total_bytes().is_err());
+    }
+
+}
+
+#[cfg(test)]
+mod bench {
+    #[cfg(feature = "benchmarks")]
+    use image::ImageDecoder;
+    #[cfg(feature = "benchmarks")]
+    use test;
+
+    #[bench]
+    #[cfg(feature = "benchmarks")]
+    fn bench_ascii_pgm(b: &mut test::Bencher) {
+        let mut pgmascii = b"P2\n1000 1000\n255\n".to_vec();
+        for i in 0..1000 * 1000 {
+            pgmascii.extend_from_slice(format!("{} ", i % 256).as_bytes());
         }
+        b.iter(|| {
+            let mut decoder = super::PNMDecoder::new(&pgmascii[..]).unwrap();
+            decoder.read_image().unwrap()
+        });
+        b.bytes = pgmascii.len() as u64;
     }
 }
@@ -6,10 +6,11 @@ use std::io::Write;
 
 use super::AutoBreak;
 use super::{ArbitraryHeader, ArbitraryTuplType, BitmapHeader, GraymapHeader, PixmapHeader};
+use super::{Endianness, FloatmapColor, FloatmapHeader};
 use super::{HeaderRecord, PNMHeader, PNMSubtype, SampleEncoding};
 use color::{num_components, ColorType};
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 
 enum HeaderStrategy {
     Dynamic,
@@ -21,12 +22,15 @@ enum HeaderStrategy {
 pub enum FlatSamples<'a> {
     U8(&'a [u8]),
     U16(&'a [u16]),
+    F32(&'a [f32]),
 }
 
 /// Encodes images to any of the `pnm` image formats.
 pub struct PNMEncoder<W: Write> {
     writer: W,
     header: HeaderStrategy,
+    encoding: SampleEncoding,
+    float_endianness: Endianness,
 }
 
 /// Encapsulate the checking system in the type system. Non of the fields are actually accessed
@@ -65,12 +69,20 @@ enum TupleEncoding<'a> {
         samples: FlatSamples<'a>,
         width: u32,
     },
+    PbmAscii {
+        samples: FlatSamples<'a>,
+    },
     Ascii {
         samples: FlatSamples<'a>,
     },
     Bytes {
         samples: FlatSamples<'a>,
     },
+    Floats {
+        samples: &'a [f32],
+        row_len: usize,
+        endianness: Endianness,
+    },
 }
 
 impl<W: Write> PNMEncoder<W> {
@@ -83,6 +95,8 @@ impl<W: Write> PNMEncoder<W> {
         PNMEncoder {
             writer,
             header: HeaderStrategy::Dynamic,
+            encoding: SampleEncoding::Binary,
+            float_endianness: Endianness::native(),
         }
     }
 
@@ -97,6 +111,8 @@ impl<W: Write> PNMEncoder<W> {
         PNMEncoder {
             writer: self.writer,
             header: HeaderStrategy::Subtype(subtype),
+            encoding: self.encoding,
+            float_endianness: self.float_endianness,
         }
     }
 
@@ -113,6 +129,8 @@ impl<W: Write> PNMEncoder<W> {
         PNMEncoder {
             writer: self.writer,
             header: HeaderStrategy::Chosen(header),
+            encoding: self.encoding,
+            float_endianness: self.float_endianness,
         }
     }
 
@@ -127,6 +145,26 @@ impl<W: Write> PNMEncoder<W> {
         PNMEncoder {
             writer: self.writer,
             header: HeaderStrategy::Dynamic,
+            encoding: self.encoding,
+            float_endianness: self.float_endianness,
+        }
+    }
+
+    /// Choose the sample encoding used by `encode_auto`.
+    ///
+    /// Ascii encoded images are human readable, with lines wrapped at 70 characters. The default
+    /// is the more compact binary encoding.
+    pub fn with_encoding(self, encoding: SampleEncoding) -> Self {
+        PNMEncoder { encoding, ..self }
+    }
+
+    /// Choose the byte order of float maps whose header is not chosen with `with_header`.
+    ///
+    /// The default is the byte order of the current target.
+    pub fn with_float_endianness(self, float_endianness: Endianness) -> Self {
+        PNMEncoder {
+            float_endianness,
+            ..self
         }
     }
 
@@ -156,6 +194,57 @@ impl<W: Write> PNMEncoder<W> {
         }
     }
 
+    /// Encode an image with the narrowest subtype that can represent its color type.
+    ///
+    /// `Gray(1)` is written as a bitmap (P4), other grayscale images as a graymap (P5) and RGB
+    /// images as a pixmap (P6), with the maxval given by the bit depth. Colors with an alpha
+    /// channel are written as a pam (P7). This ignores the header chosen with `with_subtype` or
+    /// `with_header`.
+    ///
+    /// With an ascii encoding chosen by `with_encoding` the formats P1, P2 and P3 are used
+    /// instead. A pam can not be ascii encoded, so colors with an alpha channel are an error.
+    pub fn encode_auto<'s, S>(
+        &mut self,
+        image: S,
+        width: u32,
+        height: u32,
+        color: ColorType,
+    ) -> io::Result<()>
+    where
+        S: Into<FlatSamples<'s>>,
+    {
+        let image = image.into();
+        let encoding = self.encoding;
+        let header = match color {
+            ColorType::Gray(1) => PNMHeader::from(BitmapHeader {
+                encoding,
+                width,
+                height,
+            }),
+            ColorType::Gray(n @ 2...16) => PNMHeader::from(GraymapHeader {
+                encoding,
+                width,
+                height,
+                maxwhite: (1 << n) - 1,
+            }),
+            ColorType::RGB(n @ 1...16) => PNMHeader::from(PixmapHeader {
+                encoding,
+                width,
+                height,
+                maxval: (1 << n) - 1,
+            }),
+            _ if encoding == SampleEncoding::Ascii => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Color type can not be represented in an ascii encoded format",
+                ))
+            }
+            _ => return self.write_dynamic_header(image, width, height, color),
+        };
+
+        Self::write_with_header(&mut self.writer, &header, image, width, height, color)
+    }
+
     /// Choose any valid pnm format that the image can be expressed in and write its header.
     ///
     /// Returns how the body should be written if successful.
@@ -166,6 +255,10 @@ impl<W: Write> PNMEncoder<W> {
         height: u32,
         color: ColorType,
     ) -> io::Result<()> {
+        if let FlatSamples::F32(_) = image {
+            return self.write_floatmap_header(image, width, height, color);
+        }
+
         let depth = num_components(color) as u32;
         let (maxval, tupltype) = match color {
             ColorType::Gray(1) => (1, ArbitraryTuplType::BlackAndWhite),
@@ -191,8 +284,42 @@ impl<W: Write> PNMEncoder<W> {
                 tupltype: Some(tupltype),
             }),
             encoded: None,
+            comments: Vec::new(),
+        };
+
+        Self::write_with_header(&mut self.writer, &header, image, width, height, color)
+    }
+
+    /// Write a float map in the chosen byte order, the only format for float samples.
+    fn write_floatmap_header(
+        &mut self,
+        image: FlatSamples,
+        width: u32,
+        height: u32,
+        color: ColorType,
+    ) -> io::Result<()> {
+        let color_type = match color {
+            ColorType::Gray(32) => FloatmapColor::Gray,
+            ColorType::RGB(32) => FloatmapColor::RGB,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Float maps only support ColorType::Gray(32) and ColorType::RGB(32)",
+                ))
+            }
+        };
+        let scale = match self.float_endianness {
+            Endianness::Big => 1.0,
+            Endianness::Little => -1.0,
         };
 
+        let header = PNMHeader::from(FloatmapHeader {
+            color: color_type,
+            width,
+            height,
+            scale,
+        });
+
         Self::write_with_header(&mut self.writer, &header, image, width, height, color)
     }
 
@@ -209,23 +336,28 @@ impl<W: Write> PNMEncoder<W> {
             (PNMSubtype::ArbitraryMap, color) => {
                 return self.write_dynamic_header(image, width, height, color)
             }
-            (PNMSubtype::Pixmap(encoding), ColorType::RGB(8)) => PNMHeader {
+            (PNMSubtype::Floatmap(_), color) => {
+                return self.write_floatmap_header(image, width, height, color)
+            }
+            (PNMSubtype::Pixmap(encoding), ColorType::RGB(n @ 1...16)) => PNMHeader {
                 decoded: HeaderRecord::Pixmap(PixmapHeader {
                     encoding,
                     width,
                     height,
-                    maxval: 255,
+                    maxval: (1 << n) - 1,
                 }),
                 encoded: None,
+                comments: Vec::new(),
             },
-            (PNMSubtype::Graymap(encoding), ColorType::Gray(8)) => PNMHeader {
+            (PNMSubtype::Graymap(encoding), ColorType::Gray(n @ 1...16)) => PNMHeader {
                 decoded: HeaderRecord::Graymap(GraymapHeader {
                     encoding,
                     width,
                     height,
-                    maxwhite: 255,
+                    maxwhite: (1 << n) - 1,
                 }),
                 encoded: None,
+                comments: Vec::new(),
             },
             (PNMSubtype::Bitmap(encoding), ColorType::Gray(8))
             | (PNMSubtype::Bitmap(encoding), ColorType::Gray(1)) => PNMHeader {
@@ -235,6 +367,7 @@ impl<W: Write> PNMEncoder<W> {
                     height,
                 }),
                 encoded: None,
+                comments: Vec::new(),
             },
             (_, _) => {
                 return Err(io::Error::new(
@@ -403,6 +536,19 @@ impl<'a> CheckedDimensions<'a> {
                     ))
                 }
             },
+            PNMHeader {
+                decoded: HeaderRecord::Floatmap(FloatmapHeader { color: header_color, .. }),
+                ..
+            } => match (header_color, color) {
+                (FloatmapColor::Gray, ColorType::Gray(32)) => (),
+                (FloatmapColor::RGB, ColorType::RGB(32)) => (),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "PFM format only support ColorType::Gray(32) and ColorType::RGB(32)",
+                    ))
+                }
+            },
         }
 
         Ok(CheckedHeaderColor {
@@ -414,11 +560,23 @@ impl<'a> CheckedDimensions<'a> {
 
 impl<'a> CheckedHeaderColor<'a> {
     fn check_sample_values(self, image: FlatSamples<'a>) -> io::Result<CheckedHeader<'a>> {
+        match (&self.dimensions.unchecked.header.decoded, image) {
+            (&HeaderRecord::Floatmap(_), FlatSamples::F32(_)) => return self.checked(image),
+            (&HeaderRecord::Floatmap(_), _) | (_, FlatSamples::F32(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Float samples can only be encoded as float maps",
+                ))
+            }
+            _ => (),
+        }
+
         let header_maxval = match self.dimensions.unchecked.header.decoded {
             HeaderRecord::Bitmap(_) => 1,
             HeaderRecord::Graymap(GraymapHeader { maxwhite, .. }) => maxwhite,
             HeaderRecord::Pixmap(PixmapHeader { maxval, .. }) => maxval,
             HeaderRecord::Arbitrary(ArbitraryHeader { maxval, .. }) => maxval,
+            HeaderRecord::Floatmap(_) => 1,
         };
 
         // We trust the image color bit count to be correct at least.
@@ -450,6 +608,10 @@ impl<'a> CheckedHeaderColor<'a> {
             ));
         }
 
+        self.checked(image)
+    }
+
+    fn checked(self, image: FlatSamples<'a>) -> io::Result<CheckedHeader<'a>> {
         let encoding = image.encoding_for(&self.dimensions.unchecked.header.decoded);
 
         let image = CheckedImageBuffer::check(
@@ -493,6 +655,17 @@ impl<'a> SampleWriter<'a> {
         auto_break_writer.flush()
     }
 
+    fn write_pbm_ascii<V>(self, samples: &[V]) -> io::Result<()>
+    where
+        V: Default + Eq + Copy,
+    {
+        // Black pixels are encoded as 1s, like by `write_pbm_bits`
+        let bits = samples
+            .iter()
+            .map(|&v| if v == V::default() { 1 } else { 0 });
+        self.write_samples_ascii(bits)
+    }
+
     fn write_pbm_bits<V>(self, samples: &[V], width: u32) -> io::Result<()>
     /* Default gives 0 for all primitives. TODO: replace this with `Zeroable` once it hits stable */
     where
@@ -530,13 +703,15 @@ impl<'a> FlatSamples<'a> {
         match *self {
             FlatSamples::U8(arr) => arr.len(),
             FlatSamples::U16(arr) => arr.len(),
+            FlatSamples::F32(arr) => arr.len(),
         }
     }
 
     fn all_smaller(&self, max_val: u32) -> bool {
         match *self {
-            FlatSamples::U8(arr) => arr.iter().any(|&val| u32::from(val) > max_val),
-            FlatSamples::U16(arr) => arr.iter().any(|&val| u32::from(val) > max_val),
+            FlatSamples::U8(arr) => arr.iter().all(|&val| u32::from(val) <= max_val),
+            FlatSamples::U16(arr) => arr.iter().all(|&val| u32::from(val) <= max_val),
+            FlatSamples::F32(_) => true,
         }
     }
 
@@ -554,7 +729,22 @@ impl<'a> FlatSamples<'a> {
             HeaderRecord::Bitmap(BitmapHeader {
                 encoding: SampleEncoding::Ascii,
                 ..
-            }) => TupleEncoding::Ascii { samples: *self },
+            }) => TupleEncoding::PbmAscii { samples: *self },
+
+            HeaderRecord::Floatmap(ref header) => match *self {
+                FlatSamples::F32(samples) => {
+                    let components = match header.color {
+                        FloatmapColor::Gray => 1,
+                        FloatmapColor::RGB => 3,
+                    };
+                    TupleEncoding::Floats {
+                        samples,
+                        row_len: header.width as usize * components,
+                        endianness: header.endianness(),
+                    }
+                }
+                _ => unreachable!("Only float samples are encoded as float maps"),
+            },
 
             HeaderRecord::Arbitrary(_) => TupleEncoding::Bytes { samples: *self },
 
@@ -591,6 +781,12 @@ impl<'a> From<&'a [u16]> for FlatSamples<'a> {
     }
 }
 
+impl<'a> From<&'a [f32]> for FlatSamples<'a> {
+    fn from(samples: &'a [f32]) -> Self {
+        FlatSamples::F32(samples)
+    }
+}
+
 impl<'a> TupleEncoding<'a> {
     fn write_image(&self, writer: &mut Write) -> io::Result<()> {
         match *self {
@@ -603,15 +799,23 @@ impl<'a> TupleEncoding<'a> {
                 width,
             } => SampleWriter(writer).write_pbm_bits(samples, width),
 
+            TupleEncoding::PbmAscii {
+                samples: FlatSamples::U8(samples),
+            } => SampleWriter(writer).write_pbm_ascii(samples),
+            TupleEncoding::PbmAscii {
+                samples: FlatSamples::U16(samples),
+            } => SampleWriter(writer).write_pbm_ascii(samples),
+
             TupleEncoding::Bytes {
                 samples: FlatSamples::U8(samples),
             } => writer.write_all(samples),
             TupleEncoding::Bytes {
                 samples: FlatSamples::U16(samples),
-            } => samples
-                .iter()
-                .map(|&sample| writer.write_u16::<BigEndian>(sample))
-                .collect(),
+            } => {
+                let mut bytes = vec![0; samples.len() * 2];
+                BigEndian::write_u16_into(samples, &mut bytes);
+                writer.write_all(&bytes)
+            }
 
             TupleEncoding::Ascii {
                 samples: FlatSamples::U8(samples),
@@ -619,6 +823,39 @@ impl<'a> TupleEncoding<'a> {
             TupleEncoding::Ascii {
                 samples: FlatSamples::U16(samples),
             } => SampleWriter(writer).write_samples_ascii(samples.iter()),
+
+            TupleEncoding::Floats {
+                samples,
+                row_len,
+                endianness,
+            } => {
+                // Rows are stored from bottom to top.
+                let bits = samples
+                    .chunks(row_len)
+                    .rev()
+                    .flat_map(|row| row.iter().map(|sample| sample.to_bits()))
+                    .collect::<Vec<_>>();
+                let mut bytes = vec![0; samples.len() * 4];
+                match endianness {
+                    Endianness::Big => BigEndian::write_u32_into(&bits, &mut bytes),
+                    Endianness::Little => LittleEndian::write_u32_into(&bits, &mut bytes),
+                }
+                writer.write_all(&bytes)
+            }
+
+            TupleEncoding::PbmBits {
+                samples: FlatSamples::F32(_),
+                ..
+            }
+            | TupleEncoding::PbmAscii {
+                samples: FlatSamples::F32(_),
+            }
+            | TupleEncoding::Bytes {
+                samples: FlatSamples::F32(_),
+            }
+            | TupleEncoding::Ascii {
+                samples: FlatSamples::F32(_),
+            } => unreachable!("Float samples are only encoded as float maps"),
         }
     }
 }
@@ -646,4 +883,139 @@ mod tests {
             .encode(&data[..], 2, 2, ColorType::Palette(8))
             .expect("Failed encoding custom color value");
     }
+
+    #[test]
+    fn custom_header_low_maxval() {
+        let header = GraymapHeader {
+            encoding: SampleEncoding::Binary,
+            width: 2,
+            height: 1,
+            maxwhite: 100,
+        };
+
+        let mut output = Vec::new();
+        PNMEncoder::new(&mut output)
+            .with_header(header.clone().into())
+            .encode(&[0u8, 100][..], 2, 1, ColorType::Gray(8))
+            .expect("Failed encoding samples within the maxval");
+        assert_eq!(output, b"P5\n2 1 100\n\x00\x64");
+
+        let err = PNMEncoder::new(Vec::new())
+            .with_header(header.into())
+            .encode(&[0u8, 101][..], 2, 1, ColorType::Gray(8))
+            .expect_err("Encoded a sample above the maxval");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn encode_auto() {
+        let bits = [0u8, 1, 1, 0];
+        let gray = [0u8, 17, 34, 255];
+        let wide = [0u16, 1, 2, 0xFFFF];
+        let rgb = [0u8; 12];
+
+        let cases: [(FlatSamples, ColorType, &[u8]); 6] = [
+            (FlatSamples::U8(&bits), ColorType::Gray(1), b"P4\n4 1\n"),
+            (FlatSamples::U8(&gray), ColorType::Gray(8), b"P5\n4 1 255\n"),
+            (FlatSamples::U16(&wide), ColorType::Gray(16), b"P5\n4 1 65535\n"),
+            (FlatSamples::U8(&rgb), ColorType::RGB(8), b"P6\n4 1 255\n"),
+            (
+                FlatSamples::U8(&gray),
+                ColorType::GrayA(8),
+                b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n",
+            ),
+            (
+                FlatSamples::U8(&rgb),
+                ColorType::RGBA(8),
+                b"P7\nWIDTH 3\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            ),
+        ];
+
+        for &(data, color, expected) in &cases {
+            let width = (data.len() / num_components(color)) as u32;
+            let mut output = Vec::new();
+            PNMEncoder::new(&mut output)
+                .encode_auto(data, width, 1, color)
+                .expect("Failed encoding image");
+            assert_eq!(&output[..expected.len()], expected, "for {:?}", color);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+    fn encode_auto_ascii() {
+        use image::{DecodingResult, ImageDecoder};
+        use pnm::PNMDecoder;
+
+        fn decode(data: &[u8]) -> (PNMSubtype, DecodingResult) {
+            let mut decoder = PNMDecoder::new(data).expect("Failed decoding header");
+            let image = decoder.read_image().expect("Failed decoding image");
+            (decoder.subtype(), image)
+        }
+
+        let bits: Vec<u8> = (0..60).map(|v| (v % 3 % 2) as u8).collect();
+        let gray: Vec<u8> = (0..200).map(|v| v as u8).collect();
+        let rgb: Vec<u16> = (0..300).map(|v| v * 200).collect();
+        let images: [(FlatSamples, u32, ColorType); 3] = [
+            (FlatSamples::U8(&bits), 20, ColorType::Gray(1)),
+            (FlatSamples::U8(&gray), 40, ColorType::Gray(8)),
+            (FlatSamples::U16(&rgb), 20, ColorType::RGB(16)),
+        ];
+
+        for &(data, width, color) in &images {
+            let height = (data.len() / num_components(color)) as u32 / width;
+            let mut binary = Vec::new();
+            PNMEncoder::new(&mut binary)
+                .encode_auto(data, width, height, color)
+                .expect("Failed encoding binary image");
+            let mut ascii = Vec::new();
+            PNMEncoder::new(&mut ascii)
+                .with_encoding(SampleEncoding::Ascii)
+                .encode_auto(data, width, height, color)
+                .expect("Failed encoding ascii image");
+
+            assert!(ascii.split(|&b| b == b'\n').all(|line| line.len() <= 70));
+            let (binary_subtype, binary_image) = decode(&binary);
+            let (ascii_subtype, ascii_image) = decode(&ascii);
+            assert_eq!(binary_subtype.sample_encoding(), SampleEncoding::Binary);
+            assert_eq!(ascii_subtype.sample_encoding(), SampleEncoding::Ascii);
+            match (binary_image, ascii_image) {
+                (DecodingResult::U8(a), DecodingResult::U8(b)) if color == ColorType::Gray(1) => {
+                    assert_eq!(a, bits);
+                    assert_eq!(b, bits);
+                }
+                (DecodingResult::U8(a), DecodingResult::U8(b)) => assert_eq!(a, b),
+                (DecodingResult::U16(a), DecodingResult::U16(b)) => assert_eq!(a, b),
+                other => panic!("Decoded mismatching images {:?}", other),
+            }
+        }
+
+        let mut output = Vec::new();
+        assert!(
+            PNMEncoder::new(&mut output)
+                .with_encoding(SampleEncoding::Ascii)
+                .encode_auto(&gray[..], 10, 10, ColorType::GrayA(8))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn u16_big_endian() {
+        let data: [u16; 3] = [0x0102, 0x0304, 0xFFFF];
+
+        let mut output = Vec::new();
+        PNMEncoder::new(&mut output)
+            .with_subtype(PNMSubtype::Pixmap(SampleEncoding::Binary))
+            .encode(&data[..], 1, 1, ColorType::RGB(16))
+            .expect("Failed encoding 16-bit image");
+        assert_eq!(output, b"P6\n1 1 65535\n\x01\x02\x03\x04\xFF\xFF".to_vec());
+
+        let data: [u16; 2] = [0x0123, 0x0FFF];
+        let mut output = Vec::new();
+        PNMEncoder::new(&mut output)
+            .with_subtype(PNMSubtype::Graymap(SampleEncoding::Binary))
+            .encode(&data[..], 2, 1, ColorType::Gray(12))
+            .expect("Failed encoding 12-bit image");
+        assert_eq!(output, b"P5\n2 1 4095\n\x01\x23\x0F\xFF".to_vec());
+    }
 }
@@ -1,7 +1,16 @@
+use std::error::Error;
+use std::fmt;
 use std::io;
+use std::str::FromStr;
+use std::string::ParseError;
+
+use color::ColorType;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// The kind of encoding used to store sample values
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum SampleEncoding {
     /// Samples are unsigned binary integers in big endian
     Binary,
@@ -12,6 +21,7 @@ pub enum SampleEncoding {
 
 /// Denotes the category of the magic number
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PNMSubtype {
     /// Magic numbers P1 and P4
     Bitmap(SampleEncoding),
@@ -24,6 +34,20 @@ pub enum PNMSubtype {
 
     /// Magic number P7
     ArbitraryMap,
+
+    /// Magic numbers Pf and PF
+    Floatmap(FloatmapColor),
+}
+
+/// The color channels of a float map
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FloatmapColor {
+    /// A single gray channel, magic number Pf
+    Gray,
+
+    /// Three channels: Red, Green, Blue, magic number PF
+    RGB,
 }
 
 /// Stores the complete header data of a file.
@@ -32,20 +56,25 @@ pub enum PNMSubtype {
 /// it is possible to recover the header and construct an encoder. Using the encoder on the just
 /// loaded image should result in a byte copy of the original file (for single image pnms without
 /// additional trailing data).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PNMHeader {
     pub(crate) decoded: HeaderRecord,
     pub(crate) encoded: Option<Vec<u8>>,
+    pub(crate) comments: Vec<String>,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) enum HeaderRecord {
     Bitmap(BitmapHeader),
     Graymap(GraymapHeader),
     Pixmap(PixmapHeader),
     Arbitrary(ArbitraryHeader),
+    Floatmap(FloatmapHeader),
 }
 
 /// Header produced by a `pbm` file ("Portable Bit Map")
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BitmapHeader {
     /// Binary or Ascii encoded file
     pub encoding: SampleEncoding,
@@ -59,6 +88,7 @@ pub struct BitmapHeader {
 
 /// Header produced by a `pgm` file ("Portable Gray Map")
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GraymapHeader {
     /// Binary or Ascii encoded file
     pub encoding: SampleEncoding,
@@ -75,6 +105,7 @@ pub struct GraymapHeader {
 
 /// Header produced by a `ppm` file ("Portable Pixel Map")
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PixmapHeader {
     /// Binary or Ascii encoded file
     pub encoding: SampleEncoding,
@@ -91,6 +122,7 @@ pub struct PixmapHeader {
 
 /// Header produced by a `pam` file ("Portable Arbitrary Map")
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ArbitraryHeader {
     /// Height of the image file
     pub height: u32,
@@ -108,8 +140,59 @@ pub struct ArbitraryHeader {
     pub tupltype: Option<ArbitraryTuplType>,
 }
 
+/// Header produced by a `pfm` file ("Portable Float Map")
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FloatmapHeader {
+    /// Gray or RGB image file
+    pub color: FloatmapColor,
+
+    /// Height of the image file
+    pub height: u32,
+
+    /// Width of the image file
+    pub width: u32,
+
+    /// Scale factor of the samples. Its sign denotes the byte order of the samples, positive
+    /// values are used for big endian and negative values for little endian.
+    pub scale: f32,
+}
+
+impl FloatmapHeader {
+    /// The byte order of the samples, as denoted by the sign of the scale.
+    pub fn endianness(&self) -> Endianness {
+        if self.scale < 0.0 {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        }
+    }
+}
+
+/// Byte order of multi-byte samples
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Endianness {
+    /// Most significant byte first
+    Big,
+
+    /// Least significant byte first
+    Little,
+}
+
+impl Endianness {
+    /// The byte order of the current target.
+    pub fn native() -> Self {
+        if cfg!(target_endian = "little") {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        }
+    }
+}
+
 /// Standardized tuple type specifiers in the header of a `pam`.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ArbitraryTuplType {
     /// Pixels are either black (0) or white (1)
     BlackAndWhite,
@@ -133,6 +216,43 @@ pub enum ArbitraryTuplType {
     Custom(String),
 }
 
+impl ArbitraryTuplType {
+    /// The color type of images of this tuple type, with 8 bits per sample.
+    ///
+    /// Images with a maxval above 255 are decoded with 16 bits per sample instead. Returns `None`
+    /// for custom tuple types.
+    pub fn color_type(&self) -> Option<ColorType> {
+        match *self {
+            ArbitraryTuplType::BlackAndWhite => Some(ColorType::Gray(1)),
+            ArbitraryTuplType::BlackAndWhiteAlpha => Some(ColorType::GrayA(1)),
+            ArbitraryTuplType::Grayscale => Some(ColorType::Gray(8)),
+            ArbitraryTuplType::GrayscaleAlpha => Some(ColorType::GrayA(8)),
+            ArbitraryTuplType::RGB => Some(ColorType::RGB(8)),
+            ArbitraryTuplType::RGBAlpha => Some(ColorType::RGBA(8)),
+            ArbitraryTuplType::Custom(_) => None,
+        }
+    }
+}
+
+impl FromStr for ArbitraryTuplType {
+    type Err = ParseError;
+
+    /// Parses the value of a `TUPLTYPE` line.
+    ///
+    /// Known names are matched regardless of case, any other string is kept verbatim as `Custom`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_uppercase().as_str() {
+            "BLACKANDWHITE" => ArbitraryTuplType::BlackAndWhite,
+            "BLACKANDWHITE_ALPHA" => ArbitraryTuplType::BlackAndWhiteAlpha,
+            "GRAYSCALE" => ArbitraryTuplType::Grayscale,
+            "GRAYSCALE_ALPHA" => ArbitraryTuplType::GrayscaleAlpha,
+            "RGB" => ArbitraryTuplType::RGB,
+            "RGB_ALPHA" => ArbitraryTuplType::RGBAlpha,
+            _ => ArbitraryTuplType::Custom(s.to_string()),
+        })
+    }
+}
+
 impl PNMSubtype {
     /// Get the two magic constant bytes corresponding to this format subtype.
     pub fn magic_constant(self) -> &'static [u8; 2] {
@@ -144,6 +264,8 @@ impl PNMSubtype {
             PNMSubtype::Graymap(SampleEncoding::Binary) => b"P5",
             PNMSubtype::Pixmap(SampleEncoding::Binary) => b"P6",
             PNMSubtype::ArbitraryMap => b"P7",
+            PNMSubtype::Floatmap(FloatmapColor::Gray) => b"Pf",
+            PNMSubtype::Floatmap(FloatmapColor::RGB) => b"PF",
         }
     }
 
@@ -151,6 +273,7 @@ impl PNMSubtype {
     pub fn sample_encoding(self) -> SampleEncoding {
         match self {
             PNMSubtype::ArbitraryMap => SampleEncoding::Binary,
+            PNMSubtype::Floatmap(_) => SampleEncoding::Binary,
             PNMSubtype::Bitmap(enc) => enc,
             PNMSubtype::Graymap(enc) => enc,
             PNMSubtype::Pixmap(enc) => enc,
@@ -158,6 +281,71 @@ impl PNMSubtype {
     }
 }
 
+impl fmt::Display for PNMSubtype {
+    /// Writes the magic constant, e.g. `P6`
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "P{}", self.magic_constant()[1] as char)
+    }
+}
+
+impl FromStr for PNMSubtype {
+    type Err = ParseSubtypeError;
+
+    /// Parses a magic constant, `P1` through `P7`, `Pf` or `PF`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match guess_pnm_subtype(s.as_bytes()) {
+            Some(subtype) if s.len() == 2 => Ok(subtype),
+            _ => Err(ParseSubtypeError {
+                input: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// The error returned when parsing a string that is not a pnm magic constant as `PNMSubtype`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseSubtypeError {
+    input: String,
+}
+
+impl fmt::Display for ParseSubtypeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "`{}` is not a pnm magic constant, expected P1 through P7, Pf or PF",
+            self.input
+        )
+    }
+}
+
+impl Error for ParseSubtypeError {
+    fn description(&self) -> &str {
+        "Invalid pnm magic constant"
+    }
+}
+
+/// Guess the pnm subtype from the magic constant at the start of `bytes`.
+///
+/// Only the first two bytes are inspected, returns `None` if they are not a pnm magic constant.
+pub fn guess_pnm_subtype(bytes: &[u8]) -> Option<PNMSubtype> {
+    if bytes.len() < 2 || bytes[0] != b'P' {
+        return None;
+    }
+
+    match bytes[1] {
+        b'1' => Some(PNMSubtype::Bitmap(SampleEncoding::Ascii)),
+        b'2' => Some(PNMSubtype::Graymap(SampleEncoding::Ascii)),
+        b'3' => Some(PNMSubtype::Pixmap(SampleEncoding::Ascii)),
+        b'4' => Some(PNMSubtype::Bitmap(SampleEncoding::Binary)),
+        b'5' => Some(PNMSubtype::Graymap(SampleEncoding::Binary)),
+        b'6' => Some(PNMSubtype::Pixmap(SampleEncoding::Binary)),
+        b'7' => Some(PNMSubtype::ArbitraryMap),
+        b'f' => Some(PNMSubtype::Floatmap(FloatmapColor::Gray)),
+        b'F' => Some(PNMSubtype::Floatmap(FloatmapColor::RGB)),
+        _ => None,
+    }
+}
+
 impl PNMHeader {
     /// Retrieve the format subtype from which the header was created.
     pub fn subtype(&self) -> PNMSubtype {
@@ -166,6 +354,7 @@ impl PNMHeader {
             HeaderRecord::Graymap(GraymapHeader { encoding, .. }) => PNMSubtype::Graymap(encoding),
             HeaderRecord::Pixmap(PixmapHeader { encoding, .. }) => PNMSubtype::Pixmap(encoding),
             HeaderRecord::Arbitrary(ArbitraryHeader { .. }) => PNMSubtype::ArbitraryMap,
+            HeaderRecord::Floatmap(FloatmapHeader { color, .. }) => PNMSubtype::Floatmap(color),
         }
     }
 
@@ -176,6 +365,7 @@ impl PNMHeader {
             HeaderRecord::Graymap(GraymapHeader { width, .. }) => width,
             HeaderRecord::Pixmap(PixmapHeader { width, .. }) => width,
             HeaderRecord::Arbitrary(ArbitraryHeader { width, .. }) => width,
+            HeaderRecord::Floatmap(FloatmapHeader { width, .. }) => width,
         }
     }
 
@@ -186,19 +376,36 @@ impl PNMHeader {
             HeaderRecord::Graymap(GraymapHeader { height, .. }) => height,
             HeaderRecord::Pixmap(PixmapHeader { height, .. }) => height,
             HeaderRecord::Arbitrary(ArbitraryHeader { height, .. }) => height,
+            HeaderRecord::Floatmap(FloatmapHeader { height, .. }) => height,
         }
     }
 
     /// The biggest value a sample can have. In other words, the colour resolution.
+    ///
+    /// Float maps have no integral sample range, for them this is 1.
     pub fn maximal_sample(&self) -> u32 {
         match self.decoded {
             HeaderRecord::Bitmap(BitmapHeader { .. }) => 1,
+            HeaderRecord::Floatmap(FloatmapHeader { .. }) => 1,
             HeaderRecord::Graymap(GraymapHeader { maxwhite, .. }) => maxwhite,
             HeaderRecord::Pixmap(PixmapHeader { maxval, .. }) => maxval,
             HeaderRecord::Arbitrary(ArbitraryHeader { maxval, .. }) => maxval,
         }
     }
 
+    /// The text of the comments in the header, without the leading `#`.
+    ///
+    /// They are written again by `write`, after the magic constant and before the image data.
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// The original bytes of a decoded header, from the magic constant up to and including the
+    /// whitespace separating it from the image data.
+    pub fn encoded(&self) -> Option<&[u8]> {
+        self.encoded.as_ref().map(|bytes| &bytes[..])
+    }
+
     /// Retrieve the underlying bitmap header if any
     pub fn as_bitmap(&self) -> Option<&BitmapHeader> {
         match self.decoded {
@@ -231,14 +438,26 @@ impl PNMHeader {
         }
     }
 
+    /// Retrieve the underlying floatmap header if any
+    pub fn as_floatmap(&self) -> Option<&FloatmapHeader> {
+        match self.decoded {
+            HeaderRecord::Floatmap(ref floatmap) => Some(floatmap),
+            _ => None,
+        }
+    }
+
     /// Write the header back into a binary stream
+    ///
+    /// A header obtained from the decoder is written exactly as it was read.
     pub fn write(&self, writer: &mut io::Write) -> io::Result<()> {
+        if let Some(ref content) = self.encoded {
+            return writer.write_all(content);
+        }
         writer.write_all(self.subtype().magic_constant())?;
+        for comment in &self.comments {
+            write!(writer, "\n# {}", comment)?;
+        }
         match *self {
-            PNMHeader {
-                encoded: Some(ref content),
-                ..
-            } => writer.write_all(content),
             PNMHeader {
                 decoded:
                     HeaderRecord::Bitmap(BitmapHeader {
@@ -268,6 +487,16 @@ impl PNMHeader {
                     }),
                 ..
             } => writeln!(writer, "\n{} {} {}", width, height, maxval),
+            PNMHeader {
+                decoded:
+                    HeaderRecord::Floatmap(FloatmapHeader {
+                        color: _color,
+                        width,
+                        height,
+                        scale,
+                    }),
+                ..
+            } => writeln!(writer, "\n{} {}\n{:?}", width, height, scale),
             PNMHeader {
                 decoded:
                     HeaderRecord::Arbitrary(ArbitraryHeader {
@@ -316,6 +545,7 @@ impl From<BitmapHeader> for PNMHeader {
         PNMHeader {
             decoded: HeaderRecord::Bitmap(header),
             encoded: None,
+            comments: Vec::new(),
         }
     }
 }
@@ -325,6 +555,7 @@ impl From<GraymapHeader> for PNMHeader {
         PNMHeader {
             decoded: HeaderRecord::Graymap(header),
             encoded: None,
+            comments: Vec::new(),
         }
     }
 }
@@ -334,6 +565,7 @@ impl From<PixmapHeader> for PNMHeader {
         PNMHeader {
             decoded: HeaderRecord::Pixmap(header),
             encoded: None,
+            comments: Vec::new(),
         }
     }
 }
@@ -343,6 +575,17 @@ impl From<ArbitraryHeader> for PNMHeader {
         PNMHeader {
             decoded: HeaderRecord::Arbitrary(header),
             encoded: None,
+            comments: Vec::new(),
+        }
+    }
+}
+
+impl From<FloatmapHeader> for PNMHeader {
+    fn from(header: FloatmapHeader) -> Self {
+        PNMHeader {
+            decoded: HeaderRecord::Floatmap(header),
+            encoded: None,
+            comments: Vec::new(),
         }
     }
 }
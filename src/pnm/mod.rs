@@ -1,25 +1,46 @@
-//! Decoding of netpbm image formats (pbm, pgm, ppm and pam).
+//! Decoding of netpbm image formats (pbm, pgm, ppm and pam) and of float maps (pfm).
 //!
 //! The formats pbm, pgm and ppm are fully supported. The pam decoder recognizes the tuple types
-//! `BLACKANDWHITE`, `GRAYSCALE` and `RGB` and explicitely recognizes but rejects their `_ALPHA`
-//! variants for now as alpha color types are unsupported.
+//! `BLACKANDWHITE`, `BLACKANDWHITE_ALPHA`, `GRAYSCALE`, `GRAYSCALE_ALPHA`, `RGB` and
+//! `RGB_ALPHA`. Other tuple types are decoded as interleaved channels, see
+//! `PNMDecoder::channel_count`. Float maps of both byte orders are decoded into 32-bit float
+//! samples.
+//!
+//! Decoding samples with a maxval above 255 requires the default feature `pnm_u16` and decoding
+//! ascii rasters (P1, P2 and P3) the default feature `pnm_ascii`. Without them, such images are
+//! rejected with `ImageError::UnsupportedError` when their samples are read.
+//!
+//! With the optional feature `tracing`, the decoder emits debug events when it has parsed the
+//! magic constant and the header and while decoding a whole image. The optional feature `tokio`
+//! enables `pnm` and adds `AsyncPNMDecoder` for reading images from asynchronous readers.
+//!
+//! The optional feature `serde` implements `Serialize` and `Deserialize` for `PNMHeader` and the
+//! header types of the subtypes. Enums are serialized by the names of their variants.
+#[cfg(feature = "tokio")]
+pub use self::async_decoder::AsyncPNMDecoder;
 use self::autobreak::AutoBreak;
-pub use self::decoder::PNMDecoder;
+pub use self::decoder::{Downscale, Limits, PNMDecoder, PnmDecoderOptions, PnmPixels, PnmRows};
 pub use self::encoder::PNMEncoder;
 use self::header::HeaderRecord;
-pub use self::header::{ArbitraryHeader, ArbitraryTuplType, BitmapHeader, GraymapHeader,
-                       PixmapHeader};
-pub use self::header::{PNMHeader, PNMSubtype, SampleEncoding};
+pub use self::header::{ArbitraryHeader, ArbitraryTuplType, BitmapHeader, FloatmapColor,
+                       FloatmapHeader, GraymapHeader, PixmapHeader};
+pub use self::header::{guess_pnm_subtype, Endianness, PNMHeader, PNMSubtype, ParseSubtypeError,
+                       SampleEncoding};
+pub use self::view::PnmImageView;
+pub use image::PnmError;
 
+#[cfg(feature = "tokio")]
+mod async_decoder;
 mod autobreak;
 mod decoder;
 mod encoder;
 mod header;
+mod view;
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use color::ColorType;
+    use color::{num_components, ColorType};
     use image::{DecodingResult, ImageDecoder};
 
     fn execute_roundtrip_default(buffer: &[u8], width: u32, height: u32, color: ColorType) {
@@ -120,6 +141,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "pnm_ascii")]
     fn roundtrip_rgb() {
         #[cfg_attr(rustfmt, rustfmt_skip)]
         let buf: [u8; 27] = [
@@ -152,9 +174,202 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "pnm_u16")]
     fn roundtrip_u16() {
         let buf: [u16; 6] = [0, 1, 0xFFFF, 0x1234, 0x3412, 0xBEAF];
 
         execute_roundtrip_u16(&buf, 6, 1, ColorType::Gray(16));
     }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn roundtrip_u16_gradient() {
+        let gradient: Vec<u16> = (0..240u32).map(|v| (v * 273) as u16).collect();
+
+        for &(subtype, color, width) in &[
+            (PNMSubtype::Graymap(SampleEncoding::Binary), ColorType::Gray(16), 16),
+            (PNMSubtype::Pixmap(SampleEncoding::Binary), ColorType::RGB(16), 4),
+            (PNMSubtype::ArbitraryMap, ColorType::RGB(16), 4),
+        ] {
+            let height = gradient.len() as u32 / width / num_components(color) as u32;
+            let mut encoded_buffer = Vec::new();
+            PNMEncoder::new(&mut encoded_buffer)
+                .with_subtype(subtype)
+                .encode(&gradient[..], width, height, color)
+                .expect("Failed to encode the image buffer");
+
+            let mut decoder = PNMDecoder::new(&encoded_buffer[..]).unwrap();
+            assert_eq!(decoder.subtype(), subtype);
+            assert_eq!(decoder.maxval(), Some(0xFFFF));
+            assert_eq!(decoder.colortype().unwrap(), color);
+            match decoder.read_image().expect("Failed to decode the image") {
+                DecodingResult::U16(ref data) if data == &gradient => (),
+                other => panic!("Loaded image buffer deviates from original! {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn guess_subtype() {
+        for &subtype in &[
+            PNMSubtype::Bitmap(SampleEncoding::Ascii),
+            PNMSubtype::Graymap(SampleEncoding::Ascii),
+            PNMSubtype::Pixmap(SampleEncoding::Ascii),
+            PNMSubtype::Bitmap(SampleEncoding::Binary),
+            PNMSubtype::Graymap(SampleEncoding::Binary),
+            PNMSubtype::Pixmap(SampleEncoding::Binary),
+            PNMSubtype::ArbitraryMap,
+            PNMSubtype::Floatmap(FloatmapColor::Gray),
+            PNMSubtype::Floatmap(FloatmapColor::RGB),
+        ] {
+            let magic = subtype.magic_constant();
+            assert_eq!(guess_pnm_subtype(&magic[..]), Some(subtype));
+            assert_eq!(guess_pnm_subtype(&[magic[0], magic[1], b'\n']), Some(subtype));
+        }
+
+        assert_eq!(guess_pnm_subtype(b""), None);
+        assert_eq!(guess_pnm_subtype(b"P"), None);
+        assert_eq!(guess_pnm_subtype(b"P8"), None);
+        assert_eq!(guess_pnm_subtype(b"\x89PNG\r\n\x1a\n"), None);
+    }
+
+    #[test]
+    fn subtype_from_str() {
+        for &(magic, subtype) in &[
+            ("P1", PNMSubtype::Bitmap(SampleEncoding::Ascii)),
+            ("P2", PNMSubtype::Graymap(SampleEncoding::Ascii)),
+            ("P3", PNMSubtype::Pixmap(SampleEncoding::Ascii)),
+            ("P4", PNMSubtype::Bitmap(SampleEncoding::Binary)),
+            ("P5", PNMSubtype::Graymap(SampleEncoding::Binary)),
+            ("P6", PNMSubtype::Pixmap(SampleEncoding::Binary)),
+            ("P7", PNMSubtype::ArbitraryMap),
+            ("Pf", PNMSubtype::Floatmap(FloatmapColor::Gray)),
+            ("PF", PNMSubtype::Floatmap(FloatmapColor::RGB)),
+        ] {
+            assert_eq!(magic.parse::<PNMSubtype>(), Ok(subtype));
+            assert_eq!(subtype.to_string(), magic);
+        }
+
+        for &invalid in &["", "P", "P0", "P8", "p6", "P66", "P6 ", "ppm"] {
+            let err = invalid.parse::<PNMSubtype>().unwrap_err();
+            assert!(err.to_string().contains(&format!("`{}`", invalid)));
+        }
+    }
+
+    #[test]
+    fn tupltype_from_str() {
+        for &(name, ref tupltype, color) in &[
+            ("BLACKANDWHITE", ArbitraryTuplType::BlackAndWhite, Some(ColorType::Gray(1))),
+            (
+                "BLACKANDWHITE_ALPHA",
+                ArbitraryTuplType::BlackAndWhiteAlpha,
+                Some(ColorType::GrayA(1)),
+            ),
+            ("GRAYSCALE", ArbitraryTuplType::Grayscale, Some(ColorType::Gray(8))),
+            ("GRAYSCALE_ALPHA", ArbitraryTuplType::GrayscaleAlpha, Some(ColorType::GrayA(8))),
+            ("RGB", ArbitraryTuplType::RGB, Some(ColorType::RGB(8))),
+            ("RGB_ALPHA", ArbitraryTuplType::RGBAlpha, Some(ColorType::RGBA(8))),
+        ] {
+            assert_eq!(name.parse::<ArbitraryTuplType>().as_ref(), Ok(tupltype));
+            assert_eq!(name.to_lowercase().parse::<ArbitraryTuplType>().as_ref(), Ok(tupltype));
+            assert_eq!(tupltype.color_type(), color);
+        }
+
+        let custom = "Cmyk_Extra".parse::<ArbitraryTuplType>().unwrap();
+        assert_eq!(custom, ArbitraryTuplType::Custom("Cmyk_Extra".to_string()));
+        assert_eq!(custom.color_type(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn header_serde() {
+        let header = ArbitraryHeader {
+            width: 3,
+            height: 2,
+            depth: 4,
+            maxval: 255,
+            tupltype: Some(ArbitraryTuplType::RGBAlpha),
+        };
+        let json = ::serde_json::to_string(&header).unwrap();
+        assert!(json.contains(r#""tupltype":"RGBAlpha""#), "{}", json);
+        let decoded: ArbitraryHeader = ::serde_json::from_str(&json).unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!((decoded.depth, decoded.maxval), (4, 255));
+        assert_eq!(decoded.tupltype, Some(ArbitraryTuplType::RGBAlpha));
+
+        let custom = ArbitraryTuplType::Custom("CMYK".to_string());
+        let json = ::serde_json::to_string(&custom).unwrap();
+        assert_eq!(json, r#"{"Custom":"CMYK"}"#);
+        assert_eq!(::serde_json::from_str::<ArbitraryTuplType>(&json).unwrap(), custom);
+
+        let pnm = PNMHeader::from(header);
+        let json = ::serde_json::to_string(&pnm).unwrap();
+        let decoded: PNMHeader = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.subtype(), PNMSubtype::ArbitraryMap);
+        assert_eq!(decoded.as_arbitrary().unwrap().tupltype, pnm.as_arbitrary().unwrap().tupltype);
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn roundtrip_alpha() {
+        let rgba: Vec<u8> = (0..48).map(|v| (v * 5) as u8).collect();
+        execute_roundtrip_default(&rgba, 4, 3, ColorType::RGBA(8));
+        execute_roundtrip_default(&rgba, 6, 4, ColorType::GrayA(8));
+        execute_roundtrip_with_subtype(&rgba, 4, 3, ColorType::RGBA(8), PNMSubtype::ArbitraryMap);
+
+        let rgba: Vec<u16> = (0..48).map(|v| (v * 1361) as u16).collect();
+        execute_roundtrip_u16(&rgba, 4, 3, ColorType::RGBA(16));
+        execute_roundtrip_u16(&rgba, 6, 4, ColorType::GrayA(16));
+    }
+
+    #[test]
+    fn pam_alpha_header() {
+        let mut encoded_buffer = Vec::new();
+        PNMEncoder::new(&mut encoded_buffer)
+            .encode(&[0u16, 1, 2, 3][..], 1, 1, ColorType::RGBA(16))
+            .expect("Failed to encode the image buffer");
+        let expected = &b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 65535\n\
+                          TUPLTYPE RGB_ALPHA\nENDHDR\n\
+                          \x00\x00\x00\x01\x00\x02\x00\x03"[..];
+        assert_eq!(&encoded_buffer[..], expected);
+    }
+
+    #[test]
+    fn roundtrip_floatmap() {
+        let buf: [f32; 12] = [
+            0.0, 0.25, 0.5, 1.0, -1.5, 3.75, 1e-3, 100.0, 0.125, 2.0, 0.333, 7.5
+        ];
+
+        for &endianness in &[Endianness::Big, Endianness::Little] {
+            let mut encoded_buffer = Vec::new();
+            PNMEncoder::new(&mut encoded_buffer)
+                .with_float_endianness(endianness)
+                .encode(&buf[..], 2, 2, ColorType::RGB(32))
+                .expect("Failed to encode the image buffer");
+
+            let mut decoder = PNMDecoder::new(&encoded_buffer[..]).unwrap();
+            assert_eq!(decoder.subtype(), PNMSubtype::Floatmap(FloatmapColor::RGB));
+            assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(32));
+            let image = decoder.read_image().expect("Failed to decode the image");
+            let (_, header) = decoder.into_inner();
+            assert_eq!(header.as_floatmap().unwrap().endianness(), endianness);
+            match image {
+                DecodingResult::F32(ref data) => {
+                    assert_eq!(data.len(), buf.len());
+                    for (a, b) in data.iter().zip(buf.iter()) {
+                        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+                    }
+                }
+                _ => panic!("Loaded image buffer deviates from original! {:?}", image),
+            }
+        }
+
+        let mut encoded_buffer = Vec::new();
+        PNMEncoder::new(&mut encoded_buffer)
+            .with_subtype(PNMSubtype::Floatmap(FloatmapColor::Gray))
+            .with_float_endianness(Endianness::Big)
+            .encode(&buf[..2], 2, 1, ColorType::Gray(32))
+            .expect("Failed to encode the image buffer");
+        assert_eq!(&encoded_buffer[..], &b"Pf\n2 1\n1.0\n\0\0\0\0\x3e\x80\0\0"[..]);
+    }
 }
@@ -0,0 +1,206 @@
+//! A `GenericImageView` over decoded pnm samples.
+use color::{num_components, ColorType, Rgba};
+use image::{DecodingResult, GenericImageView, ImageError, ImageResult};
+
+/// Decoded samples of a pnm image viewed as `Rgba<u16>` pixels.
+///
+/// Samples are scaled to the full 16-bit range, so 8-bit samples are multiplied by 257 and the
+/// bits of a bitmap, decoded as 1 for white, become 0 or 65535. Gray values are replicated into
+/// the color channels and the alpha channel is opaque for images without one. Float samples
+/// have no such range and are not supported.
+pub struct PnmImageView {
+    samples: DecodingResult,
+    width: u32,
+    height: u32,
+    color: ColorType,
+}
+
+impl PnmImageView {
+    /// View `samples` of an image of the given dimensions and color type as pixels.
+    ///
+    /// The samples must be 8-bit for `Gray(1)`, `GrayA(1)` and the 8-bit color types and 16-bit
+    /// for the 16-bit ones, as `PNMDecoder::read_image` decodes them. Other color types are
+    /// rejected with `ImageError::UnsupportedColor`, a sample count not matching the dimensions
+    /// with `ImageError::DimensionError`.
+    pub fn new(
+        samples: DecodingResult,
+        width: u32,
+        height: u32,
+        color: ColorType,
+    ) -> ImageResult<PnmImageView> {
+        let matching = match (color, &samples) {
+            (ColorType::Gray(1), &DecodingResult::U8(ref samples))
+            | (ColorType::GrayA(1), &DecodingResult::U8(ref samples))
+            | (ColorType::Gray(8), &DecodingResult::U8(ref samples))
+            | (ColorType::GrayA(8), &DecodingResult::U8(ref samples))
+            | (ColorType::RGB(8), &DecodingResult::U8(ref samples))
+            | (ColorType::RGBA(8), &DecodingResult::U8(ref samples)) => Some(samples.len()),
+            (ColorType::Gray(16), &DecodingResult::U16(ref samples))
+            | (ColorType::GrayA(16), &DecodingResult::U16(ref samples))
+            | (ColorType::RGB(16), &DecodingResult::U16(ref samples))
+            | (ColorType::RGBA(16), &DecodingResult::U16(ref samples)) => Some(samples.len()),
+            _ => None,
+        };
+        let len = match matching {
+            Some(len) => len,
+            None => return Err(ImageError::UnsupportedColor(color)),
+        };
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(num_components(color)));
+        if expected != Some(len) {
+            return Err(ImageError::DimensionError);
+        }
+        Ok(PnmImageView {
+            samples,
+            width,
+            height,
+            color,
+        })
+    }
+
+    /// The color type of the viewed samples.
+    pub fn color(&self) -> ColorType {
+        self.color
+    }
+
+    /// The viewed samples.
+    pub fn samples(&self) -> &DecodingResult {
+        &self.samples
+    }
+
+    /// Extract the viewed samples.
+    pub fn into_samples(self) -> DecodingResult {
+        self.samples
+    }
+
+    /// The channels of the pixel at `index`, scaled to 16 bits.
+    fn channels(&self, index: usize, channels: &mut [u16]) {
+        match self.samples {
+            DecodingResult::U8(ref samples) => {
+                let scale = match self.color {
+                    ColorType::Gray(1) | ColorType::GrayA(1) => 65535,
+                    _ => 257,
+                };
+                for (channel, &sample) in channels.iter_mut().zip(&samples[index..]) {
+                    *channel = u16::from(sample) * scale;
+                }
+            }
+            DecodingResult::U16(ref samples) => {
+                channels.copy_from_slice(&samples[index..index + channels.len()])
+            }
+            DecodingResult::F32(_) => unreachable!("Float samples are rejected by new"),
+        }
+    }
+}
+
+impl GenericImageView for PnmImageView {
+    type Pixel = Rgba<u16>;
+    type InnerImageView = Self;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        (0, 0, self.width, self.height)
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Rgba<u16> {
+        if x >= self.width || y >= self.height {
+            panic!(
+                "Image index {:?} out of bounds {:?}",
+                (x, y),
+                (self.width, self.height)
+            )
+        }
+        let components = num_components(self.color);
+        let index = (y as usize * self.width as usize + x as usize) * components;
+        let mut channels = [0, 0, 0, 0];
+        self.channels(index, &mut channels[..components]);
+        let [a, b, c, d] = channels;
+        Rgba(match components {
+            1 => [a, a, a, 0xFFFF],
+            2 => [a, a, a, b],
+            3 => [a, b, c, 0xFFFF],
+            _ => [a, b, c, d],
+        })
+    }
+
+    fn inner(&self) -> &Self::InnerImageView {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnm::PNMDecoder;
+
+    #[test]
+    fn get_pixel() {
+        let ppm = b"P6 2 1 255\n\x01\x02\x03\x04\x05\x06";
+        let view = PNMDecoder::new(&ppm[..]).unwrap().read_image_view().unwrap();
+        assert_eq!(view.dimensions(), (2, 1));
+        assert_eq!(view.get_pixel(1, 0), Rgba([1028, 1285, 1542, 65535]));
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 2\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\
+                    \x01\x02\x03\x04";
+        let view = PNMDecoder::new(&pam[..]).unwrap().read_image_view().unwrap();
+        assert_eq!(view.get_pixel(0, 1), Rgba([771, 771, 771, 1028]));
+
+        let pbm = b"P4 3 2\n\x40\xa0";
+        let view = PNMDecoder::new(&pbm[..]).unwrap().read_image_view().unwrap();
+        assert_eq!(view.color(), ColorType::Gray(1));
+        let row: Vec<_> = (0..3).map(|x| view.get_pixel(x, 0).data[0]).collect();
+        assert_eq!(row, [65535, 0, 65535]);
+        assert_eq!(view.get_pixel(0, 1), Rgba([0, 0, 0, 65535]));
+
+        let pixels: Vec<_> = view.pixels().map(|(x, y, pixel)| (x, y, pixel.data[1])).collect();
+        assert_eq!(pixels.len(), 6);
+        assert_eq!(pixels[4], (1, 1, 65535));
+
+        let pam = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE_ALPHA\n\
+                    ENDHDR\n\x01\x00\x00\x01";
+        let view = PNMDecoder::new(&pam[..]).unwrap().read_image_view().unwrap();
+        assert_eq!(view.get_pixel(0, 0), Rgba([65535, 65535, 65535, 0]));
+        assert_eq!(view.get_pixel(1, 0), Rgba([0, 0, 0, 65535]));
+    }
+
+    #[test]
+    #[cfg(feature = "pnm_u16")]
+    fn get_pixel_u16() {
+        let pgm = b"P5 2 1 65535\n\x01\x02\xff\xfe";
+        let view = PNMDecoder::new(&pgm[..]).unwrap().read_image_view().unwrap();
+        assert_eq!(view.get_pixel(0, 0), Rgba([0x0102, 0x0102, 0x0102, 0xFFFF]));
+        assert_eq!(view.get_pixel(1, 0), Rgba([0xFFFE, 0xFFFE, 0xFFFE, 0xFFFF]));
+
+        let pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 65535\nTUPLTYPE RGB_ALPHA\nENDHDR\n\
+                    \x00\x01\x00\x02\x00\x03\x00\x04";
+        let view = PNMDecoder::new(&pam[..]).unwrap().read_image_view().unwrap();
+        assert_eq!(view.get_pixel(0, 0), Rgba([1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn new() {
+        let samples = || DecodingResult::U8(vec![1, 2, 3]);
+        assert!(PnmImageView::new(samples(), 1, 1, ColorType::RGB(8)).is_ok());
+        match PnmImageView::new(samples(), 2, 1, ColorType::RGB(8)) {
+            Err(ImageError::DimensionError) => (),
+            _ => panic!("Accepted too few samples"),
+        }
+        match PnmImageView::new(samples(), 1, 1, ColorType::RGB(16)) {
+            Err(ImageError::UnsupportedColor(ColorType::RGB(16))) => (),
+            _ => panic!("Accepted 8-bit samples for a 16-bit color type"),
+        }
+        let floats = DecodingResult::F32(vec![0.5]);
+        assert!(PnmImageView::new(floats, 1, 1, ColorType::Gray(32)).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds() {
+        let view = PnmImageView::new(DecodingResult::U8(vec![1]), 1, 1, ColorType::Gray(8));
+        view.unwrap().get_pixel(1, 0);
+    }
+}
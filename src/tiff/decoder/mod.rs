@@ -121,6 +121,11 @@ fn rev_hpredict(
     Ok(match image {
         DecodingResult::U8(buf) => DecodingResult::U8(rev_hpredict_nsamp(buf, size, samples)),
         DecodingResult::U16(buf) => DecodingResult::U16(rev_hpredict_nsamp(buf, size, samples)),
+        DecodingResult::F32(_) => {
+            return Err(ImageError::UnsupportedError(
+                "Horizontal predictor for float samples is unsupported.".to_string(),
+            ))
+        }
     })
 }
 
@@ -533,6 +538,7 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
         match result {
             DecodingResult::U8(ref mut buffer) => unsafe { buffer.set_len(buffer_size) },
             DecodingResult::U16(ref mut buffer) => unsafe { buffer.set_len(buffer_size) },
+            DecodingResult::F32(_) => unreachable!(),
         }
         let mut units_read = 0;
         for (i, (&offset, &byte_count)) in try!(self.get_tag_u32_vec(ifd::Tag::StripOffsets))
@@ -556,6 +562,7 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
                     byte_count,
                     uncompressed_strip_size
                 )),
+                DecodingResult::F32(_) => unreachable!(),
             };
             if units_read == buffer_size {
                 break;
@@ -566,6 +573,7 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
             match result {
                 DecodingResult::U8(ref mut buffer) => unsafe { buffer.set_len(units_read) },
                 DecodingResult::U16(ref mut buffer) => unsafe { buffer.set_len(units_read) },
+                DecodingResult::F32(_) => unreachable!(),
             }
         }
         if let Ok(predictor) = self.get_tag_u32(ifd::Tag::Predictor) {
@@ -171,6 +171,93 @@ fn check_hdr_references() {
     }
 }
 
+/// Compare the samples of the pnm fixtures with their references.
+///
+/// Each reference lists the samples of one image row per line, in the order of the raster of the
+/// plain format netpbm writes. Bitmaps are listed like the graymap `pbmtopgm` converts them to,
+/// with 1 for white, and float maps from top to bottom.
+#[cfg(all(feature = "pnm_ascii", feature = "pnm_u16"))]
+#[test]
+fn check_pnm_references() {
+    use image::{DecodingResult, ImageDecoder};
+    use std::io::Read;
+
+    let mut path: PathBuf = BASE_PATH.iter().collect();
+    path.push(IMAGE_DIR);
+    path.push("pnm");
+    path.push("*");
+    path.push("*.p[bgpaf]m");
+    let pattern = &*format!("{}", path.display());
+    let mut checked = 0;
+    for path in glob::glob(pattern).unwrap().filter_map(Result::ok) {
+        let mut ref_path: PathBuf = BASE_PATH.iter().collect();
+        ref_path.push(REFERENCE_DIR);
+        ref_path.push("pnm");
+        let (filename, testsuite) = {
+            let mut path: Vec<_> = path.components().collect();
+            (path.pop().unwrap(), path.pop().unwrap())
+        };
+        ref_path.push(testsuite.as_os_str());
+        ref_path.push(format!("{}.txt", filename.as_os_str().to_str().unwrap()));
+
+        let mut reference = String::new();
+        fs::File::open(&ref_path)
+            .and_then(|mut file| file.read_to_string(&mut reference))
+            .unwrap_or_else(|err| panic!("Missing reference {:?}: {}", ref_path, err));
+        let rows: Vec<Vec<f64>> = reference
+            .lines()
+            .map(|line| line.split_whitespace().map(|v| v.parse().unwrap()).collect())
+            .collect();
+
+        let file = io::BufReader::new(fs::File::open(&path).unwrap());
+        let mut decoder = image::pnm::PNMDecoder::new(file).unwrap();
+        let (width, height) = decoder.dimensions().unwrap();
+        let channels = u32::from(decoder.channel_count());
+        let samples: Vec<f64> = match decoder.read_image().unwrap() {
+            DecodingResult::U8(samples) => samples.into_iter().map(f64::from).collect(),
+            DecodingResult::U16(samples) => samples.into_iter().map(f64::from).collect(),
+            DecodingResult::F32(samples) => samples.into_iter().map(f64::from).collect(),
+        };
+
+        assert_eq!(rows.len(), height as usize, "Row count of {:?}", path);
+        let rowlen = (width * channels) as usize;
+        for (y, (row, expected)) in samples.chunks(rowlen).zip(&rows).enumerate() {
+            assert_eq!(row, &expected[..], "Row {} of {:?}", y, path);
+        }
+        checked += 1;
+    }
+    assert!(checked > 0, "No pnm fixtures found");
+}
+
+/// Check that the raster of each binary pnm fixture starts right after its header and fills the
+/// rest of the file.
+#[cfg(feature = "pnm")]
+#[test]
+fn check_pnm_data_offsets() {
+    use std::io::Read;
+
+    let mut path: PathBuf = BASE_PATH.iter().collect();
+    path.push(IMAGE_DIR);
+    path.push("pnm");
+    path.push("binary");
+    path.push("*.p[bgpaf]m");
+    let pattern = &*format!("{}", path.display());
+    let mut checked = 0;
+    for path in glob::glob(pattern).unwrap().filter_map(Result::ok) {
+        let mut data = Vec::new();
+        fs::File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .unwrap();
+        let decoder = image::pnm::PNMDecoder::new(&data[..]).unwrap();
+        let offset = decoder.data_offset() as usize;
+        assert_eq!(decoder.header().encoded(), Some(&data[..offset]), "Header of {:?}", path);
+        let raster_len = decoder.raster_len().unwrap().unwrap();
+        assert_eq!(offset + raster_len, data.len(), "Raster of {:?}", path);
+        checked += 1;
+    }
+    assert!(checked > 0, "No pnm fixtures found");
+}
+
 /// Check that BMP files with large values could cause OOM issues are rejected.
 ///
 /// The images are postfixed with `bad_bmp` to not be loaded by the other test.